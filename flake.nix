@@ -47,6 +47,11 @@
 
         packages.lwsk-no_std = packages.lwsk.overrideAttrs (_: { buildNoDefaultFeatures = true; });
 
+        # `nix flake check` builds the kernel with and without std
+        checks = {
+          inherit (packages) lwsk lwsk-no_std;
+        };
+
         devShells.default = pkgs.mkShell {
           nativeBuildInputs = with pkgs; [
             toolchain
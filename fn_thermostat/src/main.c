@@ -1,26 +1,28 @@
 #include "pid_ctrl.h"
-#include "types.h"
 
-// input buffer
-uint8_t INPUT[8];
+// measured and desired room temperature in °C
+float INPUT[2];
 
-// output buffer
-uint8_t OUTPUT[4];
+// duty cycle of the heater, negative values switch it off
+float OUTPUT[1];
 
-static pidctl_t pid;
+// controller, its configuration is written by the kernel at load time
+pidctl_t PID;
 
-void process() {
+int process() {
   // read inputs
-  float current_temperature = *INPUT;
-  float set_temperature = INPUT[4];
+  float current_temperature = INPUT[0];
+  float set_temperature = INPUT[1];
 
-  // calculate error
-  float error = current_temperature - set_temperature;
+  // calculate error, positive if the room is too cold
+  float error = set_temperature - current_temperature;
 
-  // calculate setpoint
-  float setpoint;
-  pidctl(pid, error, setpoint);
+  // calculate duty cycle
+  float duty;
+  pidctl(PID, error, duty);
 
   // write output
-  *OUTPUT = setpoint;
+  OUTPUT[0] = duty;
+
+  return 0;
 }
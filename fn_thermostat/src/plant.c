@@ -0,0 +1,38 @@
+// Thermal model of a heated room, to be hosted as a WasmPlant IO driver.
+//
+// Each call to process advances the model by one fixed time step.
+
+// duty cycle of the heater in [0, 1]
+float INPUT[1];
+
+// room temperature and desired temperature in °C
+float OUTPUT[2] = {15.0f, 21.0f};
+
+// time step in s
+static const float dt = 10.0f;
+
+// power of the heater at full duty in W
+static const float heater_power = 2000.0f;
+
+// heat capacity of the room in J/K
+static const float heat_capacity = 50000.0f;
+
+// thermal conductance to the outside in W/K
+static const float conductance = 25.0f;
+
+// outside temperature in °C
+static const float outside_temperature = 5.0f;
+
+int process() {
+  float duty = INPUT[0];
+  if (duty < 0.0f) {
+    duty = 0.0f;
+  } else if (duty > 1.0f) {
+    duty = 1.0f;
+  }
+
+  float loss = conductance * (OUTPUT[0] - outside_temperature);
+  OUTPUT[0] += (duty * heater_power - loss) * dt / heat_capacity;
+
+  return 0;
+}
@@ -0,0 +1,404 @@
+//! Shared memory protocol to exchange channel contents with lwsk
+//!
+//! A region is a file in `/dev/shm` holding a small header followed by two slots, one for each
+//! direction. Every slot is guarded by a seqlock: the sequence counter is odd while the writer is
+//! busy, and a reader only accepts a copy if the counter was even and unchanged across the copy.
+//! Each slot must have exactly one writer, which is why the two sides are distinguished by [Side].
+//!
+//! A reader only returns data written after it attached, so a region left over from a previous
+//! session does not pass as fresh data. A writer dying mid-write leaves the counter odd: the
+//! reader reports this once the counter is stuck for [STALE_WRITE_TIMEOUT], and the next writer
+//! attaching to the slot makes the counter even again, without the torn data being read.
+//!
+//! # Layout
+//!
+//! All integers are little endian.
+//!
+//! | Offset                 | Content                                          |
+//! |------------------------|--------------------------------------------------|
+//! | 0                      | magic `b"LWSK"`                                  |
+//! | 4                      | protocol version, currently `1`                  |
+//! | 8                      | capacity of each slot in bytes                   |
+//! | 64                     | slot written by the peer, read by the kernel     |
+//! | 64 + stride            | slot written by the kernel, read by the peer     |
+//!
+//! A slot consists of the `u32` sequence counter, the `u32` length of the valid data and the data
+//! itself, padded to a multiple of 8 bytes (the stride).
+//!
+//! # Example
+//!
+//! ```no_run
+//! use lwsk_shm::{Endpoint, Side};
+//!
+//! let mut plant = Endpoint::open("thermal-plant", 8, Side::Peer).unwrap();
+//! let mut actuation = [0u8; 8];
+//! loop {
+//!     if let Some(_len) = plant.recv(&mut actuation).unwrap() {
+//!         // step the plant model
+//!     }
+//!     plant.send(&21.5f32.to_le_bytes()).unwrap();
+//! }
+//! ```
+
+use std::fs::OpenOptions;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{fence, AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use memmap2::MmapMut;
+
+/// Magic number at the beginning of every region
+pub const MAGIC: [u8; 4] = *b"LWSK";
+
+/// Version of the protocol implemented by this crate
+pub const VERSION: u32 = 1;
+
+/// Size of the region header, the first slot starts right after it
+const HEADER_SIZE: usize = 64;
+
+/// Size of the slot header, consisting of the sequence counter and the length
+const SLOT_HEADER_SIZE: usize = 8;
+
+/// How often a reader retries to get a consistent copy before giving up
+const MAX_READ_ATTEMPTS: usize = 16;
+
+/// How long a sequence counter may stay odd before its writer is considered dead
+pub const STALE_WRITE_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Which side of a region an [Endpoint] represents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Side {
+    /// The separation kernel
+    Kernel,
+
+    /// The other process, for example a plant model
+    Peer,
+}
+
+impl Side {
+    /// The side across the region
+    pub fn other(self) -> Self {
+        match self {
+            Side::Kernel => Side::Peer,
+            Side::Peer => Side::Kernel,
+        }
+    }
+}
+
+/// A mapped shared memory region
+pub struct Region {
+    _map: MmapMut,
+    base: *mut u8,
+    capacity: usize,
+}
+
+// Safety: the region is only ever accessed through the seqlock protocol
+unsafe impl Send for Region {}
+
+impl Region {
+    /// Open the region `name` in `/dev/shm`, creating and initializing it if it does not exist yet
+    ///
+    /// Both sides must agree on the `capacity` of the slots, opening an existing region with a
+    /// different capacity fails.
+    pub fn open(name: &str, capacity: usize) -> io::Result<Self> {
+        let capacity_u32 = u32::try_from(capacity)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "capacity too large"))?;
+
+        let path = PathBuf::from("/dev/shm").join(name.trim_start_matches('/'));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let size = (HEADER_SIZE + 2 * Self::stride(capacity)) as u64;
+        let fresh = file.metadata()?.len() == 0;
+        if fresh {
+            file.set_len(size)?;
+        } else if file.metadata()?.len() != size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "shared memory region has unexpected size",
+            ));
+        }
+
+        // Safety: the region is only ever accessed through the seqlock protocol below
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+
+        if fresh {
+            map[0..4].copy_from_slice(&MAGIC);
+            map[4..8].copy_from_slice(&VERSION.to_le_bytes());
+            map[8..12].copy_from_slice(&capacity_u32.to_le_bytes());
+        } else if map[0..4] != MAGIC
+            || map[4..8] != VERSION.to_le_bytes()
+            || map[8..12] != capacity_u32.to_le_bytes()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "shared memory region header does not match",
+            ));
+        }
+
+        let base = map.as_mut_ptr();
+        Ok(Self {
+            _map: map,
+            base,
+            capacity,
+        })
+    }
+
+    /// Capacity of each slot in bytes
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn stride(capacity: usize) -> usize {
+        SLOT_HEADER_SIZE + capacity.next_multiple_of(8)
+    }
+
+    /// Offset of the slot written by `writer`
+    fn slot_offset(&self, writer: Side) -> usize {
+        match writer {
+            Side::Peer => HEADER_SIZE,
+            Side::Kernel => HEADER_SIZE + Self::stride(self.capacity),
+        }
+    }
+
+    fn seq(&self, writer: Side) -> &AtomicU32 {
+        // Safety: the map is page aligned, all offsets are multiples of 8 and within the map
+        unsafe { AtomicU32::from_ptr(self.base.add(self.slot_offset(writer)) as *mut u32) }
+    }
+
+    fn len(&self, writer: Side) -> &AtomicU32 {
+        // Safety: the map is page aligned, all offsets are multiples of 8 and within the map
+        unsafe { AtomicU32::from_ptr(self.base.add(self.slot_offset(writer) + 4) as *mut u32) }
+    }
+
+    fn data_ptr(&self, writer: Side) -> *mut u8 {
+        // Safety: the data of each slot lies within the map
+        unsafe { self.base.add(self.slot_offset(writer) + SLOT_HEADER_SIZE) }
+    }
+
+    /// Write `data` into the slot owned by `writer`
+    fn write(&self, writer: Side, data: &[u8]) -> io::Result<()> {
+        if data.len() > self.capacity {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "data exceeds the capacity of the shared memory slot",
+            ));
+        }
+
+        let seq = self.seq(writer);
+        seq.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        // Safety: we are the only writer of this slot, readers discard torn copies
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), self.data_ptr(writer), data.len());
+        }
+        self.len(writer).store(data.len() as u32, Ordering::Relaxed);
+
+        seq.fetch_add(1, Ordering::Release);
+        Ok(())
+    }
+
+    /// Read the slot owned by `writer` if its sequence differs from `last_seq`
+    ///
+    /// Returns the number of bytes copied to `buf`, or [None] if there is no new consistent data.
+    fn read(&self, writer: Side, buf: &mut [u8], last_seq: &mut u32) -> Option<usize> {
+        let seq = self.seq(writer);
+        for _ in 0..MAX_READ_ATTEMPTS {
+            let before = seq.load(Ordering::Acquire);
+            if before == *last_seq {
+                return None;
+            }
+            if before % 2 == 1 {
+                core::hint::spin_loop();
+                continue;
+            }
+
+            let len = (self.len(writer).load(Ordering::Relaxed) as usize)
+                .min(self.capacity)
+                .min(buf.len());
+            // Safety: the length is bounded by both the slot and the buffer, torn copies are
+            // detected below
+            unsafe {
+                std::ptr::copy_nonoverlapping(self.data_ptr(writer), buf.as_mut_ptr(), len);
+            }
+
+            fence(Ordering::Acquire);
+            if seq.load(Ordering::Relaxed) == before {
+                *last_seq = before;
+                return Some(len);
+            }
+        }
+
+        None
+    }
+}
+
+/// One side of a shared memory region
+pub struct Endpoint {
+    region: Region,
+    side: Side,
+    last_seq: u32,
+
+    /// Odd sequence of the other side seen by the last [Self::recv], and since when
+    odd_since: Option<(u32, Instant)>,
+}
+
+impl Endpoint {
+    /// Open the region `name` as `side`, see [Region::open]
+    pub fn open(name: &str, capacity: usize, side: Side) -> io::Result<Self> {
+        let region = Region::open(name, capacity)?;
+
+        // the previous writer of our slot died mid-write
+        let own = region.seq(side);
+        if own.load(Ordering::Acquire) % 2 == 1 {
+            own.fetch_add(1, Ordering::Release);
+        }
+
+        // data of a write in progress is still fresh, anything before is stale
+        let last_seq = region.seq(side.other()).load(Ordering::Acquire) & !1;
+        Ok(Self {
+            region,
+            side,
+            last_seq,
+            odd_since: None,
+        })
+    }
+
+    /// The underlying region
+    pub fn region(&self) -> &Region {
+        &self.region
+    }
+
+    /// Publish `data` to the other side
+    pub fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.region.write(self.side, data)
+    }
+
+    /// Copy the latest data published by the other side to `buf`
+    ///
+    /// Returns the number of bytes copied, or [None] if nothing new was published since the last
+    /// call. Data longer than `buf` is truncated. Fails if the other side died mid-write, until a
+    /// new writer attaches.
+    pub fn recv(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        let other = self.side.other();
+        let seq = self.region.seq(other).load(Ordering::Acquire);
+        match self.odd_since {
+            Some((odd, since)) if odd == seq && since.elapsed() >= STALE_WRITE_TIMEOUT => {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "writer of the shared memory slot died mid-write",
+                ));
+            }
+            // a new writer repaired the slot, the data is torn
+            Some((odd, since)) if odd != seq && since.elapsed() >= STALE_WRITE_TIMEOUT => {
+                self.odd_since = None;
+                self.last_seq = seq & !1;
+                return Ok(None);
+            }
+            _ => {}
+        }
+
+        let received = self.region.read(other, buf, &mut self.last_seq);
+        let seq = self.region.seq(other).load(Ordering::Acquire);
+        if received.is_some() || seq.is_multiple_of(2) {
+            self.odd_since = None;
+        } else if self.odd_since.is_none_or(|(odd, _)| odd != seq) {
+            self.odd_since = Some((seq, Instant::now()));
+        }
+        Ok(received)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Name of a region unique to this process and `test`, removed when dropped
+    struct TestRegion(String);
+
+    impl TestRegion {
+        fn new(test: &str) -> Self {
+            let name = format!("lwsk-shm-test-{}-{test}", std::process::id());
+            let _ = std::fs::remove_file(PathBuf::from("/dev/shm").join(&name));
+            Self(name)
+        }
+    }
+
+    impl Drop for TestRegion {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(PathBuf::from("/dev/shm").join(&self.0));
+        }
+    }
+
+    #[test]
+    fn data_is_exchanged_in_both_directions() {
+        let region = TestRegion::new("exchange");
+        let mut kernel = Endpoint::open(&region.0, 4, Side::Kernel).unwrap();
+        let mut peer = Endpoint::open(&region.0, 4, Side::Peer).unwrap();
+        let mut buf = [0u8; 4];
+
+        assert_eq!(kernel.recv(&mut buf).unwrap(), None);
+        peer.send(&[1, 2, 3]).unwrap();
+        assert_eq!(kernel.recv(&mut buf).unwrap(), Some(3));
+        assert_eq!(buf[..3], [1, 2, 3]);
+        assert_eq!(kernel.recv(&mut buf).unwrap(), None);
+
+        kernel.send(&[4, 5, 6, 7]).unwrap();
+        assert_eq!(peer.recv(&mut buf).unwrap(), Some(4));
+        assert_eq!(buf, [4, 5, 6, 7]);
+        assert!(kernel.send(&[0; 5]).is_err());
+    }
+
+    #[test]
+    fn reader_ignores_data_written_before_attaching() {
+        let region = TestRegion::new("stale");
+        let mut peer = Endpoint::open(&region.0, 4, Side::Peer).unwrap();
+        peer.send(&[1, 2, 3, 4]).unwrap();
+
+        let mut kernel = Endpoint::open(&region.0, 4, Side::Kernel).unwrap();
+        let mut buf = [0u8; 4];
+        assert_eq!(kernel.recv(&mut buf).unwrap(), None);
+
+        peer.send(&[5, 6, 7, 8]).unwrap();
+        assert_eq!(kernel.recv(&mut buf).unwrap(), Some(4));
+        assert_eq!(buf, [5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn writer_dying_mid_write_is_detected_and_repaired() {
+        let region = TestRegion::new("stuck");
+        let mut kernel = Endpoint::open(&region.0, 4, Side::Kernel).unwrap();
+        let peer = Endpoint::open(&region.0, 4, Side::Peer).unwrap();
+        let mut buf = [0u8; 4];
+
+        // die after starting a write
+        peer.region()
+            .seq(Side::Peer)
+            .fetch_add(1, Ordering::Release);
+        drop(peer);
+        assert_eq!(kernel.recv(&mut buf).unwrap(), None);
+        std::thread::sleep(STALE_WRITE_TIMEOUT);
+        let err = kernel.recv(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+
+        // the torn data is not taken as new
+        let mut peer = Endpoint::open(&region.0, 4, Side::Peer).unwrap();
+        assert_eq!(kernel.recv(&mut buf).unwrap(), None);
+        peer.send(&[1, 2, 3, 4]).unwrap();
+        assert_eq!(kernel.recv(&mut buf).unwrap(), Some(4));
+        assert_eq!(buf, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn regions_of_different_capacity_are_rejected() {
+        let region = TestRegion::new("capacity");
+        let _kernel = Endpoint::open(&region.0, 4, Side::Kernel).unwrap();
+        assert!(Endpoint::open(&region.0, 8, Side::Peer).is_err());
+    }
+}
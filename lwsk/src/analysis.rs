@@ -0,0 +1,366 @@
+//! Static analysis of the timing of schedules
+//!
+//! Given a [Timing] model of the target, this derives for each schedule without running it
+//!
+//! - the worst-case length of its frame, i.e. of one pass over its entries, and whether it fits
+//!   the declared budget
+//! - reads of a channel which happen before the channel is written in the same frame, thus
+//!   yielding data of the previous frame
+//! - the end-to-end latency from each IO input to each IO output the data flows to
+//!
+//! A frame ends at the first switch to another schedule, entries after it are never reached. Only
+//! schedules without switch repeat themselves, thus only in them data flows into the next frame.
+//! Functions run for the time their `fuel_per_call` takes at the declared fuel rate, sporadic
+//! functions are assumed to always run, and `await_io` entries are assumed to time out.
+
+use core::time::Duration;
+use std::collections::BTreeMap;
+
+use crate::schedule::ScheduleEntry;
+use crate::{KernelConfig, LwskError, LwskResult};
+
+/// Worst-case timing of the target
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timing {
+    /// Fuel consumed per microsecond on the target, at least
+    pub fuel_per_us: u64,
+
+    /// Duration of each pull from or push to an IO driver, at most
+    pub io: Duration,
+
+    /// Time budget of the frame of schedules, by name
+    pub frames: BTreeMap<String, Duration>,
+}
+
+impl Timing {
+    /// Worst-case duration of a function call consuming `fuel`
+    pub fn call_duration(&self, fuel: u64) -> Duration {
+        let ns = (fuel as u128 * 1000).div_ceil(self.fuel_per_us.max(1) as u128);
+        Duration::from_nanos(ns.min(u64::MAX as u128) as u64)
+    }
+
+    /// Worst-case duration of a schedule entry
+    pub fn entry_duration(&self, kconfig: &KernelConfig, entry: &ScheduleEntry) -> Duration {
+        match *entry {
+            ScheduleEntry::FunctionInvocation(idx) | ScheduleEntry::SporadicInvocation(idx) => {
+                self.call_duration(kconfig.functions[idx].fuel_per_call)
+            }
+            ScheduleEntry::IoIn { .. } | ScheduleEntry::IoOut { .. } => self.io,
+            ScheduleEntry::Wait(duration) => duration,
+            ScheduleEntry::AwaitIo { timeout, .. } => timeout,
+            ScheduleEntry::SwitchSchedule(_) => Duration::ZERO,
+        }
+    }
+}
+
+/// Result of the analysis of one schedule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduleAnalysis {
+    pub schedule_idx: usize,
+
+    /// Worst-case length of one frame
+    pub frame: Duration,
+
+    /// Declared budget of the frame, if any
+    pub budget: Option<Duration>,
+
+    /// Whether the schedule repeats itself instead of switching to another one
+    pub cyclic: bool,
+
+    pub stale_reads: Vec<StaleRead>,
+    pub latencies: Vec<Latency>,
+}
+
+impl ScheduleAnalysis {
+    /// Whether the frame fits its budget, if it has one
+    pub fn fits(&self) -> bool {
+        self.budget.is_none_or(|budget| self.frame <= budget)
+    }
+}
+
+/// A read of a channel before it is written in the same frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaleRead {
+    /// Index of the reading entry in the schedule
+    pub entry_idx: usize,
+
+    pub channel_idx: usize,
+
+    /// Index of the first entry writing the channel after the read
+    pub writer_entry_idx: usize,
+}
+
+/// Worst-case time from the start of a pull until the pulled data is pushed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Latency {
+    pub from_io_idx: usize,
+    pub to_io_idx: usize,
+    pub latency: Duration,
+}
+
+/// Channel read by a schedule entry, if any
+fn reads(kconfig: &KernelConfig, entry: &ScheduleEntry) -> Option<usize> {
+    match *entry {
+        ScheduleEntry::FunctionInvocation(idx) | ScheduleEntry::SporadicInvocation(idx) => {
+            kconfig.functions[idx].consumes
+        }
+        ScheduleEntry::IoOut {
+            from_channel_idx, ..
+        } => Some(from_channel_idx),
+        _ => None,
+    }
+}
+
+/// Channel written by a schedule entry, if any
+fn writes(kconfig: &KernelConfig, entry: &ScheduleEntry) -> Option<usize> {
+    match *entry {
+        ScheduleEntry::FunctionInvocation(idx) | ScheduleEntry::SporadicInvocation(idx) => {
+            kconfig.functions[idx].produces
+        }
+        ScheduleEntry::IoIn { to_channel_idx, .. } => Some(to_channel_idx),
+        _ => None,
+    }
+}
+
+/// Analyze all schedules of a valid `kconfig`
+pub fn analyze(kconfig: &KernelConfig, timing: &Timing) -> LwskResult<Vec<ScheduleAnalysis>> {
+    if timing.fuel_per_us == 0 {
+        error!("the fuel rate of the timing must not be zero");
+        return Err(LwskError::InvalidTiming);
+    }
+    for name in timing.frames.keys() {
+        if !kconfig
+            .schedules
+            .iter()
+            .any(|schedule| &schedule.name == name)
+        {
+            error!("the timing has a frame for schedule {name:?}, which does not exist");
+            return Err(LwskError::InvalidTiming);
+        }
+    }
+
+    Ok((0..kconfig.schedules.len())
+        .map(|schedule_idx| analyze_schedule(kconfig, timing, schedule_idx))
+        .collect())
+}
+
+fn analyze_schedule(
+    kconfig: &KernelConfig,
+    timing: &Timing,
+    schedule_idx: usize,
+) -> ScheduleAnalysis {
+    let schedule = &kconfig.schedules[schedule_idx];
+    let switch = schedule
+        .sequence
+        .iter()
+        .position(|entry| matches!(entry, ScheduleEntry::SwitchSchedule(_)));
+    let entries = &schedule.sequence[..switch.map_or(schedule.sequence.len(), |idx| idx + 1)];
+    let cyclic = switch.is_none();
+
+    // start of each entry within the frame
+    let mut starts = Vec::with_capacity(entries.len());
+    let mut frame = Duration::ZERO;
+    for entry in entries {
+        starts.push(frame);
+        frame += timing.entry_duration(kconfig, entry);
+    }
+
+    let mut stale_reads = Vec::new();
+    for (entry_idx, entry) in entries.iter().enumerate() {
+        let Some(channel_idx) = reads(kconfig, entry) else {
+            continue;
+        };
+        let writers: Vec<_> = (0..entries.len())
+            .filter(|&idx| idx != entry_idx && writes(kconfig, &entries[idx]) == Some(channel_idx))
+            .collect();
+        if let (Some(&first), false) = (writers.first(), writers.iter().any(|&idx| idx < entry_idx))
+        {
+            stale_reads.push(StaleRead {
+                entry_idx,
+                channel_idx,
+                writer_entry_idx: first,
+            });
+        }
+    }
+
+    let mut latencies: Vec<Latency> = Vec::new();
+    for (pull_idx, entry) in entries.iter().enumerate() {
+        let ScheduleEntry::IoIn { from_io_idx, .. } = *entry else {
+            continue;
+        };
+
+        // earliest execution of each entry carrying the pulled data, as position in the frames
+        // unrolled from the pull on
+        let n = entries.len();
+        let mut reached: Vec<Option<usize>> = vec![None; n];
+        reached[pull_idx] = Some(pull_idx);
+        let mut queue = vec![pull_idx];
+        while let Some(writer_idx) = queue.pop() {
+            let Some(channel_idx) = writes(kconfig, &entries[writer_idx]) else {
+                continue;
+            };
+            let position = reached[writer_idx].expect("queued entries are reached");
+            for reader_idx in 0..n {
+                if reads(kconfig, &entries[reader_idx]) != Some(channel_idx) {
+                    continue;
+                }
+                let next = match reader_idx > writer_idx {
+                    true => position - writer_idx + reader_idx,
+                    false if cyclic => position - writer_idx + n + reader_idx,
+                    false => continue,
+                };
+                if reached[reader_idx].is_none_or(|reached| next < reached) {
+                    reached[reader_idx] = Some(next);
+                    queue.push(reader_idx);
+                }
+            }
+        }
+
+        let mut earliest = BTreeMap::new();
+        for (push_idx, position) in reached.iter().enumerate() {
+            let (ScheduleEntry::IoOut { to_io_idx, .. }, Some(position)) =
+                (&entries[push_idx], position)
+            else {
+                continue;
+            };
+            let end = frame * (position / n) as u32
+                + starts[push_idx]
+                + timing.entry_duration(kconfig, &entries[push_idx]);
+            let latency = end - starts[pull_idx];
+            earliest
+                .entry(*to_io_idx)
+                .and_modify(|earliest: &mut Duration| *earliest = latency.min(*earliest))
+                .or_insert(latency);
+        }
+
+        // of several pulls from the same IO driver, the one with the longest latency counts
+        for (to_io_idx, latency) in earliest {
+            match latencies
+                .iter_mut()
+                .find(|l| l.from_io_idx == from_io_idx && l.to_io_idx == to_io_idx)
+            {
+                Some(known) => known.latency = known.latency.max(latency),
+                None => latencies.push(Latency {
+                    from_io_idx,
+                    to_io_idx,
+                    latency,
+                }),
+            }
+        }
+    }
+
+    ScheduleAnalysis {
+        schedule_idx,
+        frame,
+        budget: timing.frames.get(&schedule.name).copied(),
+        cyclic,
+        stale_reads,
+        latencies,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::memory::Sink;
+    use crate::io::{ErrorPolicy, IoBinding};
+    use crate::schedule::Schedule;
+    use crate::Channel;
+
+    const IO: Duration = Duration::from_millis(1);
+
+    /// Kernel with one channel, IO bindings `io[0]` and `io[1]` and a schedule of `sequence`
+    fn kernel(sequence: Vec<ScheduleEntry>) -> KernelConfig {
+        let binding =
+            |name| IoBinding::new(name, Box::new(Sink::new(None)), ErrorPolicy::default());
+        KernelConfig {
+            channels: vec![Channel {
+                name: "c".into(),
+                buf: vec![0; 1],
+                valid: true,
+                timestamp: None,
+                generation: 0,
+            }],
+            functions: Vec::new(),
+            schedules: vec![Schedule::new("main".into(), sequence).unwrap()],
+            io: vec![binding("in"), binding("out")],
+            current_schedule_idx: 0,
+            health_events: Vec::new(),
+        }
+    }
+
+    fn timing() -> Timing {
+        Timing {
+            fuel_per_us: 1,
+            io: IO,
+            frames: BTreeMap::new(),
+        }
+    }
+
+    const PULL: ScheduleEntry = ScheduleEntry::IoIn {
+        from_io_idx: 0,
+        to_channel_idx: 0,
+    };
+    const PUSH: ScheduleEntry = ScheduleEntry::IoOut {
+        from_channel_idx: 0,
+        to_io_idx: 1,
+    };
+
+    #[test]
+    fn reads_before_writes_are_stale_in_cyclic_schedules() {
+        let kconfig = kernel(vec![PUSH, ScheduleEntry::Wait(IO), PULL]);
+        let analysis = analyze(&kconfig, &timing()).unwrap().remove(0);
+        assert!(analysis.cyclic);
+        assert_eq!(analysis.frame, 3 * IO);
+        assert_eq!(
+            analysis.stale_reads,
+            [StaleRead {
+                entry_idx: 0,
+                channel_idx: 0,
+                writer_entry_idx: 2,
+            }]
+        );
+
+        // the data reaches the push at the start of the next frame
+        assert_eq!(
+            analysis.latencies,
+            [Latency {
+                from_io_idx: 0,
+                to_io_idx: 1,
+                latency: 2 * IO,
+            }]
+        );
+
+        let in_order = analyze(&kernel(vec![PULL, PUSH]), &timing()).unwrap();
+        assert!(in_order[0].stale_reads.is_empty());
+        assert_eq!(in_order[0].latencies[0].latency, 2 * IO);
+    }
+
+    #[test]
+    fn frames_end_at_the_first_switch() {
+        let sequence = vec![
+            PUSH,
+            PULL,
+            ScheduleEntry::SwitchSchedule(0),
+            ScheduleEntry::Wait(IO),
+            PUSH,
+        ];
+        let mut timing = timing();
+        timing.frames.insert("main".into(), IO);
+        let analysis = analyze(&kernel(sequence), &timing).unwrap().remove(0);
+        assert!(!analysis.cyclic);
+        assert_eq!(analysis.frame, 2 * IO);
+        assert_eq!(analysis.budget, Some(IO));
+        assert!(!analysis.fits());
+
+        // the read is stale, but data does not flow into the next frame
+        assert_eq!(analysis.stale_reads.len(), 1);
+        assert!(analysis.latencies.is_empty());
+
+        timing.frames.insert("other".into(), IO);
+        assert!(matches!(
+            analyze(&kernel(vec![PULL]), &timing),
+            Err(LwskError::InvalidTiming)
+        ));
+    }
+}
@@ -1,72 +1,1429 @@
-use std::collections::{BTreeMap, HashMap};
-use std::path::Path;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::{fs, io};
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use super::KernelConfig;
+use crate::analysis::Timing;
+use crate::diff::{edits, module_hash, Change, Kind};
+use crate::graph::{Graph, Node};
+use crate::image::{
+    ChannelImage, FunctionImage, Image, IoImage, ModuleImage, ScheduleImage, StaticConfigImage,
+    IMAGE_VERSION, MAGIC,
+};
+use crate::io::codec::{Checksum, Codec, Endian, Field, FieldType};
+use crate::io::registry::DriverRegistry;
+use crate::io::{ErrorAction, ErrorPolicy, IoBinding};
+use crate::resolved::{
+    hex, sha256_hex, Resolved, ResolvedChannel, ResolvedConfig, ResolvedFunction, ResolvedIo,
+    ResolvedSchedule, RESOLVED_VERSION,
+};
 use crate::schedule::Schedule;
-use crate::{Function, LwskResult};
+use crate::{Function, LwskError, LwskResult};
 
 /// Base type of a configuration
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Blueprint {
+    /// Other blueprints to merge into this one, resolved by [Blueprint::new]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    include: Vec<IncludeBp>,
+
+    /// Parameters referenced within this blueprint, substituted by [Blueprint::new]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    parameters: BTreeMap<String, ParameterBp>,
+
+    /// Wasm functions by name
+    #[serde(default)]
     functions: BTreeMap<String, FunctionBp>,
+
+    /// Data channels by name
+    #[serde(default)]
     channels: BTreeMap<String, ChannelBp>,
+
+    /// Sequences of actions by name, the first one in alphabetical order runs first
+    #[serde(default)]
     schedules: BTreeMap<String, Vec<ScheduleBp>>,
-    io: BTreeMap<String, IoBp>,
+
+    /// IO drivers by name
+    #[serde(default)]
+    io: BTreeMap<String, IoBindingBp>,
+
+    /// Worst-case timing of the target, to analyze the schedules with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timing: Option<TimingBp>,
+
+    /// File defining each function and schedule by kind and name, to report unknown references
+    #[serde(skip)]
+    origins: BTreeMap<(&'static str, String), PathBuf>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// A blueprint to include, either just its path or a table with path and namespace
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum IncludeBp {
+    Path(String),
+    Namespaced {
+        path: String,
+
+        /// Prefix of all names in the included blueprint, the file stem by default
+        ///
+        /// An empty namespace merges the names unchanged.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        namespace: Option<String>,
+    },
+}
+
+/// A Wasm function, exchanging data via its `INPUT` and `OUTPUT` globals
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
 pub struct FunctionBp {
-    // The WASM module file
+    /// The WASM module file
     wasm: String,
 
-    // Channel consumed by this function
-    #[serde(default)]
+    /// Channel consumed by this function
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     consumes: Option<String>,
 
-    // Channel produced by this function
-    #[serde(default)]
+    /// Channel produced by this function
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     produces: Option<String>,
 
     /// Amount of fuel to provide per call
     fuel_per_call: u64,
+
+    /// Static configuration, written into an exported global once at load time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    config: Option<StaticConfigBp>,
+}
+
+/// Static configuration of a function, so that the same module can be tuned per deployment
+///
+/// The values are serialized little endian without padding, in the order of the layout.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StaticConfigBp {
+    /// Exported global pointing to the memory to write the configuration to
+    #[serde(default = "default_config_global")]
+    global: String,
+
+    /// Layout of the configuration, like the fields of a channel layout
+    layout: Vec<FieldBp>,
+
+    /// Values, either a table by field name or an array in the order of the layout
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<serde_json::Value>")]
+    values: Option<toml::Value>,
+
+    /// File to read the values from instead, in any blueprint [Format]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+}
+
+fn default_config_global() -> String {
+    "CONFIG".into()
+}
+
+// floats in the values are compared and hashed by their representation
+impl PartialEq for StaticConfigBp {
+    fn eq(&self, other: &Self) -> bool {
+        self.global == other.global
+            && self.layout == other.layout
+            && self.values.as_ref().map(ToString::to_string)
+                == other.values.as_ref().map(ToString::to_string)
+            && self.file == other.file
+    }
+}
+
+impl Eq for StaticConfigBp {}
+
+impl core::hash::Hash for StaticConfigBp {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.global.hash(state);
+        self.layout.hash(state);
+        self.values.as_ref().map(ToString::to_string).hash(state);
+        self.file.hash(state);
+    }
+}
+
+/// Worst-case timing of the target, see [crate::analysis]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub struct TimingBp {
+    /// Fuel consumed per microsecond on the target, at least
+    fuel_per_us: u64,
+
+    /// Duration in nanoseconds of each pull from or push to an IO driver, at most
+    #[serde(default)]
+    io_ns: u64,
+
+    /// Time budget in nanoseconds of the frame of schedules, by name
+    #[serde(default)]
+    frame_ns: BTreeMap<String, u64>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+impl From<&TimingBp> for Timing {
+    fn from(bp: &TimingBp) -> Self {
+        Self {
+            fuel_per_us: bp.fuel_per_us,
+            io: std::time::Duration::from_nanos(bp.io_ns),
+            frames: bp
+                .frame_ns
+                .iter()
+                .map(|(name, ns)| (name.clone(), std::time::Duration::from_nanos(*ns)))
+                .collect(),
+        }
+    }
+}
+
+/// Buffer of data exchanged between functions and IO drivers
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
 pub struct ChannelBp {
     /// Size in byte of the channel
     size: usize,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// An action of a schedule
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
 #[serde(untagged)]
 pub enum ScheduleBp {
+    /// Run a function
     Function { function: String },
+    /// Function which only runs if its input was updated since its last run
+    SporadicFunction { sporadic_function: String },
+    /// Push a channel to an IO driver
     IoOut { from_channel: String, to_io: String },
+    /// Pull from an IO driver into a channel
     IoIn { from_io: String, to_channel: String },
+    /// Wait for `wait_ns` nanoseconds
     Wait { wait_ns: u64 },
+    /// Wait until the IO driver has data to pull, at most for `timeout_ns`
+    AwaitIo { await_io: String, timeout_ns: u64 },
+    /// Continue with another schedule
     Schedule { switch_to_schedule: String },
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+impl ScheduleBp {
+    /// Names referenced by this action, together with their kind
+    fn references(&self) -> Vec<(&'static str, &String)> {
+        match self {
+            Self::Function { function: name }
+            | Self::SporadicFunction {
+                sporadic_function: name,
+            } => vec![("function", name)],
+            Self::IoOut {
+                from_channel,
+                to_io,
+            } => vec![("channel", from_channel), ("IO binding", to_io)],
+            Self::IoIn {
+                from_io,
+                to_channel,
+            } => vec![("IO binding", from_io), ("channel", to_channel)],
+            Self::AwaitIo { await_io, .. } => vec![("IO binding", await_io)],
+            Self::Schedule { switch_to_schedule } => vec![("schedule", switch_to_schedule)],
+            Self::Wait { .. } => Vec::new(),
+        }
+    }
+
+    /// Names referenced by this action
+    fn references_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            Self::Function { function: name }
+            | Self::SporadicFunction {
+                sporadic_function: name,
+            }
+            | Self::AwaitIo { await_io: name, .. }
+            | Self::Schedule {
+                switch_to_schedule: name,
+            } => vec![name],
+            Self::IoOut {
+                from_channel,
+                to_io,
+            } => vec![from_channel, to_io],
+            Self::IoIn {
+                from_io,
+                to_channel,
+            } => vec![from_io, to_channel],
+            Self::Wait { .. } => Vec::new(),
+        }
+    }
+}
+
+/// An IO driver together with the way it is bound to channels
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub struct IoBindingBp {
+    #[serde(flatten)]
+    driver: IoBp,
+
+    /// Codecs between the channel (first) and the wire (last)
+    #[serde(default)]
+    codec: Vec<CodecBp>,
+
+    /// Number of immediate retries of a failed pull or push
+    #[serde(default)]
+    retries: u32,
+
+    /// What to do once a pull or push failed after all retries
+    #[serde(default)]
+    on_error: ErrorAction,
+
+    /// Run the driver on its own thread, so that it never blocks the schedule
+    ///
+    /// A threaded driver does not share state with other drivers, e.g. a CCSDS link or a Modbus
+    /// server.
+    #[serde(default)]
+    threaded: bool,
+
+    /// Interval in microseconds at which a threaded driver is pulled
+    #[serde(default = "default_poll_interval_us")]
+    poll_interval_us: u64,
+}
+
+fn default_poll_interval_us() -> u64 {
+    1000
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type")]
+pub enum CodecBp {
+    /// Packed struct in the given byte order
+    Packed {
+        fields: Vec<FieldBp>,
+        endian: Endian,
+    },
+
+    /// CBOR array of numbers
+    #[serde(alias = "CBOR")]
+    Cbor { fields: Vec<FieldBp> },
+
+    /// Sequence of numbers in the postcard wire format
+    Postcard { fields: Vec<FieldBp> },
+
+    /// JSON object if all fields are named, JSON array otherwise
+    #[serde(alias = "JSON")]
+    Json { fields: Vec<FieldBp> },
+
+    /// Checksum appended to the data
+    #[serde(alias = "CRC")]
+    Crc { algorithm: Checksum },
+}
+
+/// A field of the channel layout, either just its type or a table with name and type
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum FieldBp {
+    Type(FieldType),
+    Named {
+        name: String,
+        #[serde(rename = "type")]
+        ty: FieldType,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", remote = "Self")]
+#[schemars(transform = exclude_built_in_types)]
 pub enum IoBp {
     #[serde(alias = "UDP")]
-    Udp { bind: String, connect: String },
+    #[schemars(transform = also_named("UDP"))]
+    Udp {
+        /// Local address to bind to
+        bind: String,
+
+        /// Only peer to exchange datagrams with
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        connect: Option<String>,
+
+        /// Destinations of pushed data if not connected, may be multicast or broadcast addresses
+        #[serde(default)]
+        send_to: Vec<String>,
+
+        /// Multicast groups to join
+        #[serde(default)]
+        join: Vec<String>,
+
+        /// Sources to accept data from if not connected, either `ip` or `ip:port`
+        #[serde(default)]
+        allow_from: Vec<String>,
+
+        /// Time to live of outgoing multicast datagrams
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ttl: Option<u32>,
+
+        /// Multicast interface, either a local IPv4 address or an IPv6 interface index
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        interface: Option<String>,
+
+        /// Whether outgoing multicast datagrams are looped back to this host
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        multicast_loop: Option<bool>,
+
+        /// Allow sending to broadcast addresses
+        #[serde(default)]
+        broadcast: bool,
+    },
+
+    /// Named region in `/dev/shm`, see the `lwsk-shm` crate for the protocol
+    #[serde(alias = "SHM")]
+    #[schemars(transform = also_named("SHM"))]
+    SharedMemory {
+        /// Name of the region
+        name: String,
+
+        /// Capacity in byte of each direction
+        size: usize,
+    },
+
+    /// Serial port in raw mode
+    Serial {
+        /// Path of the device, e.g. `/dev/ttyUSB0`
+        path: String,
+
+        /// Baud rate
+        baud: u32,
+    },
+
+    /// MAVLink v2 frames, exchanged via another IO driver
+    #[serde(alias = "MAVLink")]
+    #[schemars(transform = also_named("MAVLink"))]
+    Mavlink {
+        /// IO driver carrying the frames, usually UDP or serial
+        transport: Box<IoBp>,
+
+        /// System id of outgoing frames
+        system_id: u8,
+
+        /// Component id of outgoing frames
+        component_id: u8,
+
+        /// Messages to copy into the channel on pull
+        #[serde(default)]
+        rx: Vec<MavlinkMessageBp>,
+
+        /// Messages to emit on push
+        #[serde(default)]
+        tx: Vec<MavlinkMessageBp>,
+    },
+
+    /// CCSDS space packets of one APID, exchanged via another IO driver
+    ///
+    /// All CCSDS drivers with an identical transport share it, incoming packets are routed to
+    /// them by APID.
+    #[serde(alias = "CCSDS")]
+    #[schemars(transform = also_named("CCSDS"))]
+    Ccsds {
+        /// IO driver carrying the packets
+        transport: Box<IoBp>,
+
+        /// Application process identifier of the packets
+        apid: u16,
+
+        /// Mark sent packets as telecommands instead of telemetry
+        #[serde(default)]
+        telecommand: bool,
+
+        /// Time code carried in the secondary header, if any
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        time_code: Option<TimeCodeBp>,
+    },
+
+    /// Range of a Modbus TCP register map
+    ///
+    /// Registers are carried as big endian words, coils and discrete inputs as packed bits.
+    #[serde(alias = "MODBUS")]
+    #[schemars(transform = also_named("MODBUS"))]
+    Modbus {
+        /// Whether to poll a remote server or to serve the range to clients
+        role: ModbusRoleBp,
+
+        /// Address of the remote server, or local address to listen on
+        address: String,
+
+        /// Unit identifier of requests, only relevant for clients
+        #[serde(default = "default_unit_id")]
+        unit_id: u8,
+
+        /// Table to map to the channel
+        table: crate::io::modbus::Table,
+
+        /// Address of the first register or coil
+        #[serde(default)]
+        start: u16,
+
+        /// Number of registers or coils
+        count: u16,
+
+        /// Time in milliseconds to wait for a server, only relevant for clients
+        #[serde(default = "default_modbus_timeout_ms")]
+        timeout_ms: u64,
+    },
+
+    /// MQTT 3.1.1 client publishing and subscribing to topics
+    #[serde(alias = "MQTT")]
+    #[schemars(transform = also_named("MQTT"))]
+    Mqtt {
+        /// Address of the broker, e.g. `localhost:1883`
+        broker: String,
+
+        /// Client identifier, defaults to one unique to this process
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        client_id: Option<String>,
+
+        /// Topic filter whose latest message is pulled
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        subscribe: Option<String>,
+
+        /// Topic to publish pushed data to
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        publish: Option<String>,
+
+        /// QoS of the subscription and of published messages, 0 or 1
+        #[serde(default = "default_mqtt_qos")]
+        qos: crate::io::mqtt::Qos,
+
+        /// Whether the broker retains published messages
+        #[serde(default)]
+        retain: bool,
+
+        /// Whether the broker discards any previous session
+        #[serde(default = "default_true")]
+        clean_session: bool,
+
+        /// Keep alive interval in seconds, zero to disable
+        #[serde(default = "default_mqtt_keep_alive_s")]
+        keep_alive_s: u64,
+
+        /// Time in milliseconds to wait before reconnecting
+        #[serde(default = "default_mqtt_reconnect_ms")]
+        reconnect_ms: u64,
+
+        /// Message published by the broker if the connection is lost
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        will: Option<MqttWillBp>,
+
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        username: Option<String>,
+
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+    },
+
+    /// Yields the same data on every pull
+    Constant { data: BytesBp },
+
+    /// Yields one of the values per pull, in order
+    Sequence {
+        values: Vec<BytesBp>,
+
+        /// Start over once all values were yielded
+        #[serde(default)]
+        repeat: bool,
+    },
+
+    /// Collects all pushed data for later inspection
+    Sink {
+        /// Number of most recent pushes to keep
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        limit: Option<usize>,
+    },
+
+    /// Yields pushed data on later pulls
+    Loopback {
+        /// Number of pushes to buffer at most
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        capacity: Option<usize>,
+    },
+
+    /// Simulated device implemented as Wasm module, stepped once per pull
+    ///
+    /// Pushed data is written to its `INPUT` global, pulled data is read from its `OUTPUT` global.
+    #[serde(alias = "Plant")]
+    #[schemars(transform = also_named("Plant"))]
+    WasmPlant {
+        /// The Wasm module file of the model
+        wasm: String,
+
+        /// Amount of fuel to provide per step
+        fuel_per_call: u64,
+    },
+
+    /// Periodic timer (Linux timerfd), yielding the number of expirations since the last pull
+    #[serde(alias = "TimerFd")]
+    #[schemars(transform = also_named("TimerFd"))]
+    Timer {
+        /// Period in nanoseconds
+        period_ns: u64,
+
+        /// Time until the first expiration in nanoseconds, one period if zero
+        #[serde(default)]
+        delay_ns: u64,
+    },
+
+    /// Event counter (Linux eventfd), incremented by pushes and yielding the count since the last
+    /// pull
+    #[serde(alias = "EventFd")]
+    #[schemars(transform = also_named("EventFd"))]
+    Event {
+        /// Only decrement the counter by one per pull
+        #[serde(default)]
+        semaphore: bool,
+    },
+
+    /// Any other type, created via the [DriverRegistry]
+    #[serde(skip)]
+    #[schemars(untagged, !skip)]
+    Plugin(PluginBp),
+}
+
+impl IoBp {
+    /// Whether `type_name` is the name or an alias of a built-in IO driver type, which is never
+    /// taken for a plugin
+    ///
+    /// Deserializing a binding of any other type fails as an unknown variant.
+    pub fn is_built_in(type_name: &str) -> bool {
+        /// Whether deserializing failed because of an unknown variant
+        #[derive(Debug)]
+        struct Error(bool);
+
+        impl core::fmt::Display for Error {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                write!(f, "unknown variant: {}", self.0)
+            }
+        }
+
+        impl std::error::Error for Error {}
+
+        impl serde::de::Error for Error {
+            fn custom<T: core::fmt::Display>(_msg: T) -> Self {
+                Self(false)
+            }
+
+            fn unknown_variant(_variant: &str, _expected: &'static [&'static str]) -> Self {
+                Self(true)
+            }
+        }
+
+        let binding =
+            serde::de::value::MapDeserializer::<_, Error>::new([("type", type_name)].into_iter());
+        !matches!(IoBp::deserialize(binding), Err(Error(true)))
+    }
+}
+
+impl Serialize for IoBp {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            IoBp::Plugin(plugin) => plugin.serialize(serializer),
+            bp => IoBp::serialize(bp, serializer),
+        }
+    }
+}
+
+// an invalid built-in driver must report why, instead of being taken for a plugin
+impl<'de> Deserialize<'de> for IoBp {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let table = toml::Value::Table(toml::Table::deserialize(deserializer)?);
+        match table.get("type").and_then(toml::Value::as_str) {
+            Some(ty) if !IoBp::is_built_in(ty) => PluginBp::deserialize(table).map(IoBp::Plugin),
+            _ => IoBp::deserialize(table),
+        }
+        .map_err(D::Error::custom)
+    }
+}
+
+/// IO driver of a type which is not built in
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PluginBp {
+    /// Name under which the driver is registered
+    #[serde(rename = "type")]
+    ty: String,
+
+    /// All other keys of the IO binding
+    #[serde(flatten)]
+    #[schemars(with = "serde_json::Map<String, serde_json::Value>")]
+    config: toml::Table,
+}
+
+// floats in the configuration are compared and hashed by their representation
+impl PartialEq for PluginBp {
+    fn eq(&self, other: &Self) -> bool {
+        self.ty == other.ty && self.config.to_string() == other.config.to_string()
+    }
+}
+
+impl Eq for PluginBp {}
+
+impl core::hash::Hash for PluginBp {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.ty.hash(state);
+        self.config.to_string().hash(state);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub struct MqttWillBp {
+    topic: String,
+
+    /// Payload as UTF-8 text
+    payload: String,
+
+    #[serde(default = "default_mqtt_qos")]
+    qos: crate::io::mqtt::Qos,
+
+    #[serde(default)]
+    retain: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_mqtt_qos() -> crate::io::mqtt::Qos {
+    crate::io::mqtt::Qos::AtMostOnce
+}
+
+fn default_mqtt_keep_alive_s() -> u64 {
+    30
+}
+
+fn default_mqtt_reconnect_ms() -> u64 {
+    1000
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ModbusRoleBp {
+    Client,
+    Server,
+}
+
+fn default_unit_id() -> u8 {
+    1
+}
+
+fn default_modbus_timeout_ms() -> u64 {
+    100
+}
+
+/// Raw data, either as list of bytes or as UTF-8 text
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum BytesBp {
+    Bytes(Vec<u8>),
+    Text(String),
+}
+
+impl From<&BytesBp> for Vec<u8> {
+    fn from(bp: &BytesBp) -> Self {
+        match bp {
+            BytesBp::Bytes(bytes) => bytes.clone(),
+            BytesBp::Text(text) => text.as_bytes().to_vec(),
+        }
+    }
+}
+
+/// CCSDS Unsegmented time Code
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub struct TimeCodeBp {
+    /// Number of octets of whole seconds
+    coarse: usize,
+
+    /// Number of octets of the fraction of a second
+    #[serde(default)]
+    fine: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub struct MavlinkMessageBp {
+    /// Message id as defined in the dialect
+    id: u32,
+
+    /// CRC seed of the message as defined in the dialect
+    crc_extra: u8,
+
+    /// Length in byte of the untruncated payload, required for transmitted messages
+    #[serde(default)]
+    payload_len: usize,
+
+    /// Fields to copy between payload and channel
+    fields: Vec<FieldMapBp>,
+}
+
+/// Copy `size` bytes between `offset` in a message and `channel_offset` in a channel
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub struct FieldMapBp {
+    offset: usize,
+    size: usize,
+    channel_offset: usize,
+}
+
+impl From<&MavlinkMessageBp> for crate::io::mavlink::Message {
+    fn from(bp: &MavlinkMessageBp) -> Self {
+        Self {
+            id: bp.id,
+            crc_extra: bp.crc_extra,
+            payload_len: bp.payload_len,
+            fields: bp
+                .fields
+                .iter()
+                .map(|field| crate::io::mavlink::FieldMap {
+                    offset: field.offset,
+                    size: field.size,
+                    channel_offset: field.channel_offset,
+                })
+                .collect(),
+        }
+    }
 }
 
-impl Blueprint {
-    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        let bp: Blueprint = toml::from_str(&fs::read_to_string(path)?)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        trace!("parsed blueprint:\n{bp:#?}");
-        Ok(bp)
+impl Blueprint {
+    /// JSON Schema of blueprints, for editors to complete and check them
+    ///
+    /// Integers, floats and booleans may also be given as a `${name}` reference to a parameter.
+    pub fn json_schema() -> serde_json::Value {
+        Self::json_schema_with_registry(&DriverRegistry::new())
+    }
+
+    /// JSON Schema of blueprints like [Self::json_schema], including the configuration of each IO
+    /// driver in `registry` which was registered with one
+    pub fn json_schema_with_registry(registry: &DriverRegistry) -> serde_json::Value {
+        let mut schema = schemars::schema_for!(Blueprint).to_value();
+        for type_name in registry.type_names() {
+            if let Some(driver) = registry.schema(type_name) {
+                add_plugin_schema(&mut schema, type_name, driver.clone().to_value());
+            }
+        }
+        allow_references(&mut schema);
+        schema
+    }
+
+    /// Load the blueprint at `path`, including all blueprints it includes
+    ///
+    /// The [Format] of each blueprint is told by its extension.
+    ///
+    /// Names in an included blueprint are prefixed by its namespace, as in `namespace/name`, and
+    /// must not clash with any other name. References within it are relative to that namespace,
+    /// `../name` refers to a name of the including blueprint and `/name` to one of the top-level
+    /// blueprint. Relative paths of includes and Wasm modules are resolved against the directory
+    /// of the blueprint containing them.
+    ///
+    /// Fails with an [LwskError::UnknownReference] if a reference does not name a definition.
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::with_overrides(path, &Overrides::default())
+    }
+
+    /// Load the blueprint at `path` like [Self::new], applying `overrides`
+    ///
+    /// Parameters take their value from the overrides, then from their environment variable, then
+    /// from their default. References to them, `${name}` within strings, are substituted before
+    /// the blueprint is deserialized. A string consisting of a single reference takes the typed
+    /// value of the parameter, and `$$` yields a literal `$`.
+    pub fn with_overrides<P: AsRef<Path>>(path: P, overrides: &Overrides) -> io::Result<Self> {
+        let mut loader = Loader {
+            overrides,
+            stack: Vec::new(),
+            parameters: Vec::new(),
+            origins: BTreeMap::new(),
+        };
+        let mut bp = loader.load(path.as_ref(), "")?;
+        for name in bp.references_mut() {
+            if let Some(absolute) = name.strip_prefix('/') {
+                *name = absolute.into();
+            }
+        }
+
+        for (name, _) in &overrides.parameters {
+            if !loader.parameters.contains(name) {
+                return Err(invalid_data(format!("no parameter {name:?} is declared")));
+            }
+        }
+
+        if !overrides.paths.is_empty() {
+            let mut value = toml::Value::try_from(&bp).map_err(|e| invalid_data(e.to_string()))?;
+            for (path, override_value) in &overrides.paths {
+                debug!("overriding {path:?}");
+                set_path(&mut value, path, override_value.clone())?;
+            }
+            bp = value
+                .try_into()
+                .map_err(|e| invalid_data(format!("overrides are invalid: {e}")))?;
+
+            // keys unknown to the blueprint are silently dropped when deserializing it
+            let value = toml::Value::try_from(&bp).map_err(|e| invalid_data(e.to_string()))?;
+            for (path, _) in &overrides.paths {
+                if get_path(&value, path).is_none() {
+                    return Err(invalid_data(format!(
+                        "{path:?} is not part of the blueprint"
+                    )));
+                }
+            }
+        }
+
+        bp.origins = loader.origins;
+        bp.check_references()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        trace!("parsed blueprint:\n{bp:#?}");
+        Ok(bp)
+    }
+
+    /// Make relative paths in this blueprint relative to `dir` instead
+    fn resolve_paths(&mut self, dir: &Path) {
+        for function in self.functions.values_mut() {
+            resolve_path(&mut function.wasm, dir);
+            if let Some(file) = function.config.as_mut().and_then(|c| c.file.as_mut()) {
+                resolve_path(file, dir);
+            }
+        }
+        for binding in self.io.values_mut() {
+            binding.driver.resolve_paths(dir);
+        }
+    }
+
+    /// Prefix all names, and all references to them, by `namespace`
+    ///
+    /// References to the including blueprint lose one `../` instead, absolute ones are kept.
+    fn prefix(&mut self, namespace: &str) {
+        prefix_keys(&mut self.functions, namespace);
+        prefix_keys(&mut self.channels, namespace);
+        prefix_keys(&mut self.schedules, namespace);
+        prefix_keys(&mut self.io, namespace);
+        if let Some(timing) = &mut self.timing {
+            prefix_keys(&mut timing.frame_ns, namespace);
+        }
+
+        for name in self.references_mut() {
+            if let Some(outer) = name.strip_prefix("../") {
+                *name = outer.into();
+            } else if !name.starts_with('/') {
+                *name = format!("{namespace}/{name}");
+            }
+        }
+    }
+
+    /// All references of functions and schedules
+    fn references_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        let functions = self
+            .functions
+            .values_mut()
+            .flat_map(|function| function.consumes.iter_mut().chain(&mut function.produces));
+        let schedules = self
+            .schedules
+            .values_mut()
+            .flatten()
+            .flat_map(ScheduleBp::references_mut);
+        functions.chain(schedules)
+    }
+
+    /// Check that each reference names a definition of its kind
+    fn check_references(&self) -> LwskResult<()> {
+        let functions = self.functions.iter().flat_map(|(name, function)| {
+            let channels = function.consumes.iter().chain(&function.produces);
+            channels.map(move |channel| (("function", name), ("channel", channel)))
+        });
+        let schedules = self.schedules.iter().flat_map(|(name, entries)| {
+            let references = entries.iter().flat_map(ScheduleBp::references);
+            references.map(move |reference| (("schedule", name), reference))
+        });
+
+        for ((holder_kind, holder), (kind, name)) in functions.chain(schedules) {
+            let known = match kind {
+                "channel" => self.channels.contains_key(name),
+                "function" => self.functions.contains_key(name),
+                "IO binding" => self.io.contains_key(name),
+                _ => self.schedules.contains_key(name),
+            };
+            if !known {
+                error!("{holder_kind} {holder:?} references the unknown {kind} {name:?}");
+                return Err(self.unknown_reference(holder_kind, holder, kind, name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Error for the reference of the `holder_kind` named `holder` to the unknown `kind` `name`
+    fn unknown_reference(
+        &self,
+        holder_kind: &'static str,
+        holder: &str,
+        kind: &'static str,
+        name: &str,
+    ) -> LwskError {
+        let file = self
+            .origins
+            .get(&(holder_kind, holder.into()))
+            .map_or_else(|| "an override".into(), |path| format!("{path:?}"));
+        LwskError::UnknownReference {
+            file,
+            kind,
+            name: name.into(),
+        }
+    }
+
+    /// Merge all definitions of `other`, included from `path`, into this blueprint
+    fn merge(&mut self, other: Self, path: &Path) -> io::Result<()> {
+        merge_map(&mut self.functions, other.functions, "function", path)?;
+        merge_map(&mut self.channels, other.channels, "channel", path)?;
+        merge_map(&mut self.schedules, other.schedules, "schedule", path)?;
+        merge_map(&mut self.io, other.io, "IO binding", path)?;
+
+        match (&mut self.timing, other.timing) {
+            (Some(timing), Some(other)) => {
+                if (timing.fuel_per_us, timing.io_ns) != (other.fuel_per_us, other.io_ns) {
+                    return Err(invalid_data(format!(
+                        "timing included from {path:?} differs from the one already defined"
+                    )));
+                }
+                merge_map(&mut timing.frame_ns, other.frame_ns, "frame", path)
+            }
+            (timing @ None, other) => {
+                *timing = other;
+                Ok(())
+            }
+            (Some(_), None) => Ok(()),
+        }
+    }
+
+    /// Worst-case timing of the target, if declared
+    pub fn timing(&self) -> Option<Timing> {
+        self.timing.as_ref().map(Into::into)
+    }
+
+    /// Dataflow and mode graph of this blueprint
+    ///
+    /// References to unknown names are left out, [Self::new] rejects them.
+    pub fn graph(&self) -> Graph {
+        let mut graph = Graph {
+            io: self.io.keys().cloned().collect(),
+            channels: self.channels.keys().cloned().collect(),
+            functions: self.functions.keys().cloned().collect(),
+            ..Default::default()
+        };
+
+        let mut edge = |from: Node, to: Node| {
+            let known = |node: &Node| match node {
+                Node::Io(name) => self.io.contains_key(name),
+                Node::Channel(name) => self.channels.contains_key(name),
+                Node::Function(name) => self.functions.contains_key(name),
+            };
+            if known(&from) && known(&to) {
+                graph.dataflow.insert((from, to));
+            }
+        };
+
+        for (name, function) in &self.functions {
+            if let Some(channel) = &function.consumes {
+                edge(Node::Channel(channel.clone()), Node::Function(name.clone()));
+            }
+            if let Some(channel) = &function.produces {
+                edge(Node::Function(name.clone()), Node::Channel(channel.clone()));
+            }
+        }
+
+        for entries in self.schedules.values() {
+            for entry in entries {
+                match entry {
+                    ScheduleBp::IoOut {
+                        from_channel,
+                        to_io,
+                    } => edge(Node::Channel(from_channel.clone()), Node::Io(to_io.clone())),
+                    ScheduleBp::IoIn {
+                        from_io,
+                        to_channel,
+                    } => edge(Node::Io(from_io.clone()), Node::Channel(to_channel.clone())),
+                    _ => {}
+                }
+            }
+        }
+
+        for (name, entries) in &self.schedules {
+            let mut switches = BTreeSet::new();
+            for entry in entries {
+                match entry {
+                    ScheduleBp::Function {
+                        function: function_name,
+                    }
+                    | ScheduleBp::SporadicFunction {
+                        sporadic_function: function_name,
+                    } => {
+                        graph.scheduled.insert(function_name.clone());
+                    }
+                    ScheduleBp::Schedule { switch_to_schedule } => {
+                        switches.insert(switch_to_schedule.clone());
+                    }
+                    _ => {}
+                }
+            }
+            graph.schedules.insert(name.clone(), switches);
+        }
+
+        graph
+    }
+
+    /// Semantic differences from this blueprint to `new`
+    ///
+    /// Wasm modules are compared by their hash, those which can not be read count as changed.
+    pub fn diff(&self, new: &Self) -> Vec<Change> {
+        let mut changes = Vec::new();
+        added_and_removed(&mut changes, Kind::Channel, &self.channels, &new.channels);
+        added_and_removed(
+            &mut changes,
+            Kind::Function,
+            &self.functions,
+            &new.functions,
+        );
+        added_and_removed(
+            &mut changes,
+            Kind::Schedule,
+            &self.schedules,
+            &new.schedules,
+        );
+        added_and_removed(&mut changes, Kind::Io, &self.io, &new.io);
+
+        for (name, old, new) in in_both(&self.channels, &new.channels) {
+            if old.size != new.size {
+                changes.push(Change::ChannelSize {
+                    channel: name.clone(),
+                    old: old.size,
+                    new: new.size,
+                });
+            }
+        }
+
+        let size = |bp: &Self, channel: &Option<String>| {
+            channel
+                .as_ref()
+                .and_then(|channel| bp.channels.get(channel))
+                .map(|channel| channel.size)
+        };
+        let config_size = |f: &FunctionBp| {
+            f.config.as_ref().map(|config| {
+                config
+                    .layout
+                    .iter()
+                    .map(|field| Field::from(field).ty.size())
+                    .sum()
+            })
+        };
+        for (name, old_fn, new_fn) in in_both(&self.functions, &new.functions) {
+            let old_hash = module_hash(Path::new(&old_fn.wasm));
+            let new_hash = module_hash(Path::new(&new_fn.wasm));
+            if old_hash != new_hash || old_hash.is_none() {
+                changes.push(Change::Module {
+                    function: name.clone(),
+                    old: old_hash,
+                    new: new_hash,
+                });
+            }
+
+            let bindings = [
+                ("INPUT", &old_fn.consumes, &new_fn.consumes),
+                ("OUTPUT", &old_fn.produces, &new_fn.produces),
+            ];
+            for (global, old_channel, new_channel) in bindings {
+                if old_channel != new_channel {
+                    changes.push(Change::Binding {
+                        function: name.clone(),
+                        global,
+                        old: old_channel.clone(),
+                        new: new_channel.clone(),
+                    });
+                }
+                let (old_size, new_size) = (size(self, old_channel), size(new, new_channel));
+                if old_size != new_size && old_hash.is_some() && old_hash == new_hash {
+                    changes.push(Change::Layout {
+                        function: name.clone(),
+                        global: global.into(),
+                        old: old_size,
+                        new: new_size,
+                    });
+                }
+            }
+
+            if old_fn.config != new_fn.config {
+                changes.push(Change::StaticConfig {
+                    function: name.clone(),
+                });
+                let (old_size, new_size) = (config_size(old_fn), config_size(new_fn));
+                if old_size != new_size && old_hash.is_some() && old_hash == new_hash {
+                    changes.push(Change::Layout {
+                        function: name.clone(),
+                        global: new_fn
+                            .config
+                            .as_ref()
+                            .or(old_fn.config.as_ref())
+                            .map_or_else(default_config_global, |c| c.global.clone()),
+                        old: old_size,
+                        new: new_size,
+                    });
+                }
+            }
+
+            if old_fn.fuel_per_call != new_fn.fuel_per_call {
+                changes.push(Change::Budget {
+                    what: format!("fuel per call of function {name:?}"),
+                    old: Some(old_fn.fuel_per_call),
+                    new: Some(new_fn.fuel_per_call),
+                });
+            }
+        }
+
+        for (name, old, new) in in_both(&self.schedules, &new.schedules) {
+            let (removed, added) = edits(old, new);
+            let describe = |entries: &[ScheduleBp], idx: usize| {
+                let entry = serde_json::to_string(&entries[idx]).unwrap_or_default();
+                (idx, entry)
+            };
+            if !removed.is_empty() || !added.is_empty() {
+                changes.push(Change::ScheduleEntries {
+                    schedule: name.clone(),
+                    removed: removed.into_iter().map(|idx| describe(old, idx)).collect(),
+                    added: added.into_iter().map(|idx| describe(new, idx)).collect(),
+                });
+            }
+        }
+
+        for (name, old, new) in in_both(&self.io, &new.io) {
+            if old != new {
+                changes.push(Change::Io { name: name.clone() });
+            }
+        }
+
+        let (old_timing, new_timing) = (self.timing.as_ref(), new.timing.as_ref());
+        let mut budget = |what: String, old: Option<u64>, new: Option<u64>| {
+            if old != new {
+                changes.push(Change::Budget { what, old, new });
+            }
+        };
+        budget(
+            "fuel per microsecond of the timing".into(),
+            old_timing.map(|timing| timing.fuel_per_us),
+            new_timing.map(|timing| timing.fuel_per_us),
+        );
+        budget(
+            "IO duration in nanoseconds of the timing".into(),
+            old_timing.map(|timing| timing.io_ns),
+            new_timing.map(|timing| timing.io_ns),
+        );
+        let frames = |timing: Option<&TimingBp>| timing.map(|timing| timing.frame_ns.clone());
+        let (old_frames, new_frames) = (
+            frames(old_timing).unwrap_or_default(),
+            frames(new_timing).unwrap_or_default(),
+        );
+        let schedules: BTreeSet<_> = old_frames.keys().chain(new_frames.keys()).collect();
+        for schedule in schedules {
+            budget(
+                format!("frame in nanoseconds of schedule {schedule:?}"),
+                old_frames.get(schedule).copied(),
+                new_frames.get(schedule).copied(),
+            );
+        }
+
+        changes
+    }
+
+    /// Names of the IO bindings, in the order of their indices in the [KernelConfig]
+    pub fn io_names(&self) -> impl Iterator<Item = &str> {
+        self.io.keys().map(String::as_str)
+    }
+
+    pub fn to_kernel_config(&self) -> LwskResult<KernelConfig> {
+        self.to_kernel_config_with_registry(&DriverRegistry::default())
+    }
+
+    /// Validate this blueprint and lower it to its explicit [Resolved] form
+    pub fn resolve(&self) -> LwskResult<Resolved> {
+        let kconfig = self.to_kernel_config_with_io(|_, _, _| {
+            Ok(Box::new(crate::io::memory::Sink::new(Some(0))))
+        })?;
+        kconfig.validate()?;
+        if kconfig.functions.len() != self.functions.len() {
+            error!("not all functions could be loaded");
+            return Err(LwskError::WasmLoadError);
+        }
+
+        let cwd = std::env::current_dir()?;
+        let mut functions = Vec::with_capacity(kconfig.functions.len());
+        for (idx, f) in kconfig.functions.iter().enumerate() {
+            let wasm = &self.functions[&f.name].wasm;
+            let wasm = fs::canonicalize(wasm).unwrap_or_else(|_| cwd.join(wasm));
+            let bytes = fs::read(&wasm).inspect_err(|e| {
+                error!("could not read {wasm:?}: {e}");
+            })?;
+            functions.push(ResolvedFunction {
+                index: idx as u32,
+                name: f.name.clone(),
+                wasm: wasm.to_string_lossy().into_owned(),
+                sha256: sha256_hex(&bytes),
+                consumes: f.consumes.map(|idx| idx as u32),
+                produces: f.produces.map(|idx| idx as u32),
+                fuel_per_call: f.fuel_per_call,
+                config: f.config.as_ref().map(|config| ResolvedConfig {
+                    global: config.global.clone(),
+                    data: hex(&config.data),
+                }),
+            });
+        }
+
+        Ok(Resolved {
+            resolved: RESOLVED_VERSION,
+            initial_schedule: kconfig.current_schedule_idx as u32,
+            channels: kconfig
+                .channels
+                .iter()
+                .enumerate()
+                .map(|(idx, channel)| ResolvedChannel {
+                    index: idx as u32,
+                    name: channel.name.clone(),
+                    size: channel.buf.len() as u32,
+                })
+                .collect(),
+            functions,
+            io: self
+                .io
+                .iter()
+                .enumerate()
+                .map(|(idx, (name, binding))| {
+                    let mut binding = binding.clone();
+                    binding.driver.resolve_paths(&cwd);
+                    ResolvedIo {
+                        index: idx as u32,
+                        name: name.clone(),
+                        binding,
+                    }
+                })
+                .collect(),
+            schedules: kconfig
+                .schedules
+                .iter()
+                .enumerate()
+                .map(|(idx, schedule)| ResolvedSchedule {
+                    index: idx as u32,
+                    name: schedule.name.clone(),
+                    entries: schedule.sequence.iter().map(Into::into).collect(),
+                })
+                .collect(),
+            timing: self.timing.clone(),
+        })
+    }
+
+    /// Validate this blueprint and build a self-contained [Image] of it
+    ///
+    /// No IO driver is created while doing so.
+    pub fn to_image(&self) -> LwskResult<Vec<u8>> {
+        let kconfig = self.to_kernel_config_with_io(|_, _, _| {
+            Ok(Box::new(crate::io::memory::Sink::new(Some(0))))
+        })?;
+        kconfig.validate()?;
+
+        let mut wasm = Vec::with_capacity(kconfig.functions.len());
+        for f in &kconfig.functions {
+            let path = &self.functions[&f.name].wasm;
+            wasm.push(fs::read(path).inspect_err(|e| {
+                error!("could not read {path:?}: {e}");
+            })?);
+        }
+
+        let mut io_configs = Vec::with_capacity(self.io.len());
+        for (name, binding) in &self.io {
+            let config = minicbor_serde::to_vec(binding).map_err(|e| {
+                error!("could not encode IO binding {name:?}: {e}");
+                LwskError::InvalidImage
+            })?;
+            let mut modules = Vec::new();
+            for path in binding.driver.modules() {
+                let wasm = fs::read(path).inspect_err(|e| {
+                    error!("could not read {path:?}: {e}");
+                })?;
+                modules.push((path, wasm));
+            }
+            io_configs.push((config, modules));
+        }
+
+        Image {
+            magic: MAGIC,
+            version: IMAGE_VERSION,
+            channels: kconfig
+                .channels
+                .iter()
+                .map(|channel| ChannelImage {
+                    name: &channel.name,
+                    size: channel.buf.len() as u32,
+                })
+                .collect(),
+            functions: kconfig
+                .functions
+                .iter()
+                .zip(&wasm)
+                .map(|(f, wasm)| FunctionImage {
+                    name: &f.name,
+                    wasm,
+                    consumes: f.consumes.map(|idx| idx as u32),
+                    produces: f.produces.map(|idx| idx as u32),
+                    fuel_per_call: f.fuel_per_call,
+                    config: f.config.as_ref().map(|config| StaticConfigImage {
+                        global: &config.global,
+                        data: &config.data,
+                    }),
+                })
+                .collect(),
+            io: kconfig
+                .io
+                .iter()
+                .zip(&io_configs)
+                .map(|(binding, (config, modules))| IoImage {
+                    name: &binding.name,
+                    config,
+                    policy: binding.policy,
+                    modules: modules
+                        .iter()
+                        .map(|(path, wasm)| ModuleImage { path, wasm })
+                        .collect(),
+                })
+                .collect(),
+            schedules: kconfig
+                .schedules
+                .iter()
+                .map(|schedule| ScheduleImage {
+                    name: &schedule.name,
+                    entries: schedule.sequence.iter().map(Into::into).collect(),
+                })
+                .collect(),
+        }
+        .encode()
+    }
+
+    /// Derive a [KernelConfig], looking up IO drivers which are not built in from `registry`
+    pub fn to_kernel_config_with_registry(
+        &self,
+        registry: &DriverRegistry,
+    ) -> LwskResult<KernelConfig> {
+        let mut ctx = IoContext::new(registry);
+        self.to_kernel_config_with_io(|_, _, binding| binding.to_driver(&mut ctx))
     }
 
-    // TODO split function & replace unwraps with results
-    pub fn to_kernel_config(&self) -> LwskResult<KernelConfig> {
+    /// Derive a [KernelConfig], using `make_io` to create the driver for each IO binding
+    ///
+    /// `make_io` is called with the index, name and blueprint of each IO binding.
+    // TODO split function
+    pub fn to_kernel_config_with_io<F>(&self, mut make_io: F) -> LwskResult<KernelConfig>
+    where
+        F: FnMut(usize, &str, &IoBindingBp) -> LwskResult<Box<dyn crate::io::IoDriver>>,
+    {
+        self.check_references()?;
+
+        // functions which could not be loaded are missing from their map
+        let lookup =
+            |map: &HashMap<&str, usize>, holder: (&'static str, &str), kind, name: &str| {
+                map.get(name).copied().ok_or_else(|| {
+                    error!(
+                        "{} {:?} references the unloaded {kind} {name:?}",
+                        holder.0, holder.1
+                    );
+                    self.unknown_reference(holder.0, holder.1, kind, name)
+                })
+            };
         debug!("initializing channels");
         let mut channel_id_map: HashMap<&str, usize> = HashMap::with_capacity(self.channels.len());
         let kernel_channels = self
@@ -79,6 +1436,9 @@ impl Blueprint {
                 super::Channel {
                     name: name.clone(),
                     buf: vec![0u8; bp_channel.size],
+                    valid: true,
+                    timestamp: None,
+                    generation: 0,
                 }
             })
             .collect();
@@ -94,18 +1454,28 @@ impl Blueprint {
                 continue;
             };
 
+            let holder = ("function", name.as_str());
             f.consumes = bp_func
                 .consumes
                 .as_ref()
-                .map(|name| channel_id_map.get(name.as_str()).unwrap().to_owned());
+                .map(|channel| lookup(&channel_id_map, holder, "channel", channel))
+                .transpose()?;
 
             f.produces = bp_func
                 .produces
                 .as_ref()
-                .map(|name| channel_id_map.get(name.as_str()).unwrap().to_owned());
+                .map(|channel| lookup(&channel_id_map, holder, "channel", channel))
+                .transpose()?;
 
             f.fuel_per_call = bp_func.fuel_per_call;
 
+            if let Some(config) = &bp_func.config {
+                let data = config.to_bytes().inspect_err(|_| {
+                    error!("the static configuration of function {name:?} is invalid");
+                })?;
+                f.configure(&config.global, &data)?;
+            }
+
             kernel_functions.push(f);
 
             // TODO this len function can be replaced by using enummerate
@@ -114,16 +1484,10 @@ impl Blueprint {
 
         debug!("initializing io drivers");
         let mut io_id_map: HashMap<&str, usize> = HashMap::with_capacity(self.io.len());
-        let mut kernel_io: Vec<Box<dyn crate::io::IoDriver>> = Vec::new();
-        for (name, io) in &self.io {
-            match io {
-                IoBp::Udp { bind, connect } => {
-                    let driver = crate::io::udp::Udp::new(bind, connect).unwrap();
-
-                    io_id_map.insert(name, kernel_io.len());
-                    kernel_io.push(Box::from(driver));
-                }
-            };
+        let mut kernel_io = Vec::new();
+        for (idx, (name, io)) in self.io.iter().enumerate() {
+            io_id_map.insert(name, idx);
+            kernel_io.push(IoBinding::new(name, make_io(idx, name, io)?, io.policy()));
         }
 
         debug!("assembling schedules");
@@ -131,20 +1495,25 @@ impl Blueprint {
             HashMap::with_capacity(self.schedules.len());
         let mut kernel_schedules = Vec::new();
         for (name, bp_schedule) in &self.schedules {
+            let holder = ("schedule", name.as_str());
             let mut schedule_sequence = Vec::new();
             for slot in bp_schedule {
                 // TODO maybe impl From<ScheduleBp> for ScheduleEntry
                 schedule_sequence.push(match slot {
                     ScheduleBp::Function { function } => {
-                        let idx = *function_id_map.get(function.as_str()).unwrap();
+                        let idx = lookup(&function_id_map, holder, "function", function)?;
                         crate::schedule::ScheduleEntry::FunctionInvocation(idx)
                     }
+                    ScheduleBp::SporadicFunction { sporadic_function } => {
+                        let idx = lookup(&function_id_map, holder, "function", sporadic_function)?;
+                        crate::schedule::ScheduleEntry::SporadicInvocation(idx)
+                    }
                     ScheduleBp::IoOut {
                         from_channel,
                         to_io,
                     } => {
-                        let from_idx = *channel_id_map.get(from_channel.as_str()).unwrap();
-                        let to_idx = *io_id_map.get(to_io.as_str()).unwrap();
+                        let from_idx = lookup(&channel_id_map, holder, "channel", from_channel)?;
+                        let to_idx = lookup(&io_id_map, holder, "IO binding", to_io)?;
                         crate::schedule::ScheduleEntry::IoOut {
                             from_channel_idx: from_idx,
                             to_io_idx: to_idx,
@@ -154,16 +1523,23 @@ impl Blueprint {
                         from_io,
                         to_channel,
                     } => {
-                        let from_idx = *io_id_map.get(from_io.as_str()).unwrap();
-                        let to_idx = *channel_id_map.get(to_channel.as_str()).unwrap();
-                        crate::schedule::ScheduleEntry::IoOut {
-                            from_channel_idx: from_idx,
-                            to_io_idx: to_idx,
+                        let from_idx = lookup(&io_id_map, holder, "IO binding", from_io)?;
+                        let to_idx = lookup(&channel_id_map, holder, "channel", to_channel)?;
+                        crate::schedule::ScheduleEntry::IoIn {
+                            from_io_idx: from_idx,
+                            to_channel_idx: to_idx,
                         }
                     }
                     ScheduleBp::Wait { wait_ns } => crate::schedule::ScheduleEntry::Wait(
                         core::time::Duration::from_nanos(*wait_ns),
                     ),
+                    ScheduleBp::AwaitIo {
+                        await_io,
+                        timeout_ns,
+                    } => crate::schedule::ScheduleEntry::AwaitIo {
+                        io_idx: lookup(&io_id_map, holder, "IO binding", await_io)?,
+                        timeout: core::time::Duration::from_nanos(*timeout_ns),
+                    },
                     ScheduleBp::Schedule { .. } => {
                         crate::schedule::ScheduleEntry::SwitchSchedule(usize::MAX)
                     }
@@ -175,21 +1551,19 @@ impl Blueprint {
 
         debug!("inserting schedule switch indices");
 
-        for (bp_schedule, kernel_schedule) in
-            self.schedules.values().zip(kernel_schedules.iter_mut())
+        for ((name, bp_schedule), kernel_schedule) in
+            self.schedules.iter().zip(kernel_schedules.iter_mut())
         {
+            let holder = ("schedule", name.as_str());
             for (bp_entry, kernel_entry) in
                 bp_schedule.iter().zip(kernel_schedule.sequence.iter_mut())
             {
-                if let ScheduleBp::Schedule { switch_to_schedule } = bp_entry {
-                    // TODO return a sensible error when kernel entry already has a schedule assigned
-                    assert_eq!(
-                        *kernel_entry,
-                        crate::schedule::ScheduleEntry::SwitchSchedule(usize::MAX)
-                    );
-                    *kernel_entry = crate::schedule::ScheduleEntry::SwitchSchedule(
-                        *schedules_id_map.get(switch_to_schedule.as_str()).unwrap(),
-                    )
+                if let (
+                    ScheduleBp::Schedule { switch_to_schedule },
+                    crate::schedule::ScheduleEntry::SwitchSchedule(idx),
+                ) = (bp_entry, kernel_entry)
+                {
+                    *idx = lookup(&schedules_id_map, holder, "schedule", switch_to_schedule)?;
                 }
             }
         }
@@ -210,12 +1584,914 @@ impl Blueprint {
             schedules: kernel_schedules,
             io: kernel_io,
             current_schedule_idx: 0, // the lexical first schedule by name is the initial schedule
+            health_events: Vec::new(),
+        })
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Make `path` relative to `dir`, unless it is absolute
+fn resolve_path(path: &mut String, dir: &Path) {
+    if Path::new(path.as_str()).is_relative() {
+        *path = dir.join(path.as_str()).to_string_lossy().into_owned();
+    }
+}
+
+/// Prefix all keys of `map` by `namespace`
+fn prefix_keys<T>(map: &mut BTreeMap<String, T>, namespace: &str) {
+    *map = core::mem::take(map)
+        .into_iter()
+        .map(|(name, value)| (format!("{namespace}/{name}"), value))
+        .collect();
+}
+
+/// Record definitions which are only in `old` as removed, and only in `new` as added
+fn added_and_removed<T>(
+    changes: &mut Vec<Change>,
+    kind: Kind,
+    old: &BTreeMap<String, T>,
+    new: &BTreeMap<String, T>,
+) {
+    for name in old.keys().filter(|name| !new.contains_key(*name)) {
+        let name = name.clone();
+        changes.push(Change::Removed { kind, name });
+    }
+    for name in new.keys().filter(|name| !old.contains_key(*name)) {
+        let name = name.clone();
+        changes.push(Change::Added { kind, name });
+    }
+}
+
+/// Definitions which are both in `old` and `new`
+fn in_both<'a, T>(
+    old: &'a BTreeMap<String, T>,
+    new: &'a BTreeMap<String, T>,
+) -> impl Iterator<Item = (&'a String, &'a T, &'a T)> {
+    old.iter()
+        .filter_map(|(name, old)| new.get(name).map(|new| (name, old, new)))
+}
+
+/// Move all entries of `from` into `into`, failing on the first name defined in both
+fn merge_map<T>(
+    into: &mut BTreeMap<String, T>,
+    from: BTreeMap<String, T>,
+    kind: &str,
+    path: &Path,
+) -> io::Result<()> {
+    for (name, value) in from {
+        if into.contains_key(&name) {
+            return Err(invalid_data(format!(
+                "{kind} {name:?} included from {path:?} is already defined"
+            )));
+        }
+        into.insert(name, value);
+    }
+    Ok(())
+}
+
+/// Replace YAML tags, as serde_yaml emits them for enums, by maps with the tag as only key
+fn untagged(value: serde_yaml::Value) -> serde_yaml::Value {
+    use serde_yaml::Value;
+    match value {
+        Value::Tagged(tagged) => {
+            let tag = tagged.tag.to_string();
+            let mut map = serde_yaml::Mapping::new();
+            map.insert(
+                Value::String(tag.trim_start_matches('!').into()),
+                untagged(tagged.value),
+            );
+            Value::Mapping(map)
+        }
+        Value::Sequence(values) => Value::Sequence(values.into_iter().map(untagged).collect()),
+        Value::Mapping(map) => Value::Mapping(
+            map.into_iter()
+                .map(|(key, value)| (key, untagged(value)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// Remove all keys with a `null` value from the tables in `value`
+fn without_nulls(value: serde_json::Value) -> io::Result<serde_json::Value> {
+    use serde_json::Value;
+    Ok(match value {
+        Value::Null => {
+            return Err(invalid_data(
+                "null is only allowed as value of a key".into(),
+            ))
+        }
+        Value::Array(values) => Value::Array(
+            values
+                .into_iter()
+                .map(without_nulls)
+                .collect::<io::Result<_>>()?,
+        ),
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| Ok((key, without_nulls(value)?)))
+                .collect::<io::Result<_>>()?,
+        ),
+        value => value,
+    })
+}
+
+/// File format of a blueprint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    Toml,
+    Json,
+    Yaml,
+    Cbor,
+}
+
+impl Format {
+    /// Detect the format from the extension of `path`
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+        Ok(match extension.to_lowercase().as_str() {
+            "toml" => Self::Toml,
+            "json" => Self::Json,
+            "yaml" | "yml" => Self::Yaml,
+            "cbor" => Self::Cbor,
+            _ => {
+                return Err(invalid_data(format!(
+                    "the format of {path:?} is unknown, expected .toml, .json, .yaml, .yml or .cbor"
+                )))
+            }
+        })
+    }
+
+    /// Parse `bytes` into a generic value
+    ///
+    /// TOML has no `null`, a key with a `null` value is treated as if it was left out.
+    pub fn parse(self, bytes: &[u8]) -> io::Result<toml::Value> {
+        let text = || core::str::from_utf8(bytes).map_err(|e| invalid_data(e.to_string()));
+        let value: serde_json::Value = match self {
+            Self::Toml => return toml::from_str(text()?).map_err(|e| invalid_data(e.to_string())),
+            Self::Json => serde_json::from_slice(bytes).map_err(|e| invalid_data(e.to_string()))?,
+            Self::Yaml => {
+                let value =
+                    serde_yaml::from_slice(bytes).map_err(|e| invalid_data(e.to_string()))?;
+                serde_json::to_value(untagged(value)).map_err(|e| invalid_data(e.to_string()))?
+            }
+            Self::Cbor => {
+                minicbor_serde::from_slice(bytes).map_err(|e| invalid_data(e.to_string()))?
+            }
+        };
+        toml::Value::try_from(without_nulls(value)?).map_err(|e| invalid_data(e.to_string()))
+    }
+
+    /// Serialize `value` in this format
+    pub fn serialize<T: Serialize>(self, value: &T) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Toml => toml::to_string_pretty(value)
+                .map(String::into_bytes)
+                .map_err(|e| invalid_data(e.to_string())),
+            Self::Json => serde_json::to_vec_pretty(value).map_err(|e| invalid_data(e.to_string())),
+            Self::Yaml => serde_yaml::to_string(value)
+                .map(String::into_bytes)
+                .map_err(|e| invalid_data(e.to_string())),
+            Self::Cbor => minicbor_serde::to_vec(value).map_err(|e| invalid_data(e.to_string())),
+        }
+    }
+}
+
+/// Values overriding those of a blueprint, see [Blueprint::with_overrides]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Overrides {
+    /// Values of parameters by name, as strings
+    parameters: Vec<(String, String)>,
+
+    /// Values by dotted path into the resolved blueprint
+    paths: Vec<(String, toml::Value)>,
+}
+
+impl Overrides {
+    /// Override `key` with `value`
+    ///
+    /// A `key` containing a dot is a path into the resolved blueprint, e.g. `io.speed_in.bind`,
+    /// with array elements addressed by index, and must name an element the blueprint knows. Its
+    /// value is parsed as TOML value, falling back to a string. Any other `key` names a parameter, e.g. `port` or `gnc/port` if included in the
+    /// namespace `gnc`, with `value` parsed according to the parameter's type.
+    pub fn set(&mut self, key: &str, value: &str) -> &mut Self {
+        if key.contains('.') {
+            let value = toml::from_str::<toml::Table>(&format!("value = {value}"))
+                .ok()
+                .and_then(|mut table| table.remove("value"))
+                .unwrap_or_else(|| toml::Value::String(value.into()));
+            self.paths.push((key.into(), value));
+        } else {
+            self.parameters.push((key.into(), value.into()));
+        }
+        self
+    }
+
+    /// The last override of the parameter `name`, if any
+    fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .rev()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Loads blueprints and the blueprints they include
+struct Loader<'a> {
+    overrides: &'a Overrides,
+
+    /// Blueprints being loaded, each including the next
+    stack: Vec<PathBuf>,
+
+    /// Names of all parameters declared so far
+    parameters: Vec<String>,
+
+    /// File defining each function and schedule loaded so far, see [Blueprint::origins]
+    origins: BTreeMap<(&'static str, String), PathBuf>,
+}
+
+impl Loader<'_> {
+    /// Load the blueprint at `path`, whose names will be prefixed by `namespace`
+    fn load(&mut self, path: &Path, namespace: &str) -> io::Result<Blueprint> {
+        let canonical = fs::canonicalize(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{path:?}: {e}")))?;
+        if self.stack.contains(&canonical) {
+            return Err(invalid_data(format!("{path:?} includes itself")));
+        }
+
+        let toml::Value::Table(mut table) = Format::from_path(path)?.parse(&fs::read(path)?)?
+        else {
+            return Err(invalid_data(format!("{path:?} is no table")));
+        };
+        let parameters: BTreeMap<String, ParameterBp> = table
+            .remove("parameters")
+            .map(toml::Value::try_into)
+            .transpose()
+            .map_err(|e| invalid_data(format!("parameters of {path:?} are invalid: {e}")))?
+            .unwrap_or_default();
+
+        let mut values = BTreeMap::new();
+        for (name, parameter) in parameters {
+            let full_name = namespaced(namespace, &name);
+            let value = parameter.value(&full_name, self.overrides.parameter(&full_name))?;
+            debug!("parameter {full_name:?} is {value}");
+            self.parameters.push(full_name);
+            values.insert(name, value);
+        }
+
+        let mut table = toml::Value::Table(table);
+        substitute(&mut table, &values)
+            .map_err(|e| invalid_data(format!("{path:?} is invalid: {e}")))?;
+        let mut bp: Blueprint = table
+            .try_into()
+            .map_err(|e| invalid_data(format!("{path:?} is invalid: {e}")))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        bp.resolve_paths(dir);
+        for name in bp.functions.keys() {
+            let origin = ("function", namespaced(namespace, name));
+            self.origins.insert(origin, path.to_path_buf());
+        }
+        for name in bp.schedules.keys() {
+            let origin = ("schedule", namespaced(namespace, name));
+            self.origins.insert(origin, path.to_path_buf());
+        }
+
+        self.stack.push(canonical);
+        for include in core::mem::take(&mut bp.include) {
+            let (include_path, include_namespace) = match include {
+                IncludeBp::Path(path) => (dir.join(path), None),
+                IncludeBp::Namespaced { path, namespace } => (dir.join(path), namespace),
+            };
+            debug!("including {include_path:?} into {path:?}");
+
+            let include_namespace = include_namespace.unwrap_or_else(|| {
+                include_path
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            let mut included =
+                self.load(&include_path, &namespaced(namespace, &include_namespace))?;
+            if !include_namespace.is_empty() {
+                included.prefix(&include_namespace);
+            }
+            bp.merge(included, &include_path)?;
+        }
+        self.stack.pop();
+
+        Ok(bp)
+    }
+}
+
+/// Join `name` to `namespace`, unless the latter is empty
+fn namespaced(namespace: &str, name: &str) -> String {
+    match (namespace, name) {
+        ("", name) | (name, "") => name.into(),
+        (namespace, name) => format!("{namespace}/{name}"),
+    }
+}
+
+/// A parameter of a blueprint, either just its default or a table declaring it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum ParameterBp {
+    Declared(ParameterDeclBp),
+    Default(#[schemars(with = "serde_json::Value")] toml::Value),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ParameterDeclBp {
+    /// Type of the value, derived from the default if not given
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    ty: Option<ParameterType>,
+
+    /// Value if not overridden, the parameter is required if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<serde_json::Value>")]
+    default: Option<toml::Value>,
+
+    /// Environment variable overriding the default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    env: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ParameterType {
+    String,
+    Integer,
+    Float,
+    Boolean,
+}
+
+impl ParameterType {
+    fn of(value: &toml::Value) -> Option<Self> {
+        match value {
+            toml::Value::String(_) => Some(Self::String),
+            toml::Value::Integer(_) => Some(Self::Integer),
+            toml::Value::Float(_) => Some(Self::Float),
+            toml::Value::Boolean(_) => Some(Self::Boolean),
+            _ => None,
+        }
+    }
+
+    /// Parse `s` as value of this type
+    fn parse(self, s: &str) -> Option<toml::Value> {
+        Some(match self {
+            Self::String => toml::Value::String(s.into()),
+            Self::Integer => toml::Value::Integer(s.parse().ok()?),
+            Self::Float => toml::Value::Float(s.parse().ok()?),
+            Self::Boolean => toml::Value::Boolean(s.parse().ok()?),
+        })
+    }
+}
+
+impl ParameterBp {
+    /// Value of the parameter `name`, given the override `set` if any
+    fn value(&self, name: &str, set: Option<&str>) -> io::Result<toml::Value> {
+        let (ty, default, env) = match self {
+            ParameterBp::Declared(ParameterDeclBp {
+                ty, default, env, ..
+            }) => (
+                ty.or_else(|| default.as_ref().and_then(ParameterType::of)),
+                default.as_ref(),
+                env.as_deref(),
+            ),
+            ParameterBp::Default(default) => (ParameterType::of(default), Some(default), None),
+        };
+        let Some(ty) = ty else {
+            return Err(invalid_data(format!(
+                "parameter {name:?} has neither a type nor a string, integer, float or boolean default"
+            )));
+        };
+
+        let parse = |s: &str, source: &str| {
+            ty.parse(s).ok_or_else(|| {
+                invalid_data(format!(
+                    "{source} value {s:?} of parameter {name:?} is no {ty:?}"
+                ))
+            })
+        };
+        if let Some(s) = set {
+            return parse(s, "overriding");
+        }
+        if let Some(s) = env.and_then(|env| std::env::var(env).ok()) {
+            return parse(&s, "environment");
+        }
+        match default {
+            Some(default) if ParameterType::of(default) == Some(ty) => Ok(default.clone()),
+            Some(default) => Err(invalid_data(format!(
+                "default {default} of parameter {name:?} is no {ty:?}"
+            ))),
+            None => Err(invalid_data(format!(
+                "parameter {name:?} is required, but was not set"
+            ))),
+        }
+    }
+}
+
+/// Substitute references to `parameters` in all strings within `value`
+fn substitute(
+    value: &mut toml::Value,
+    parameters: &BTreeMap<String, toml::Value>,
+) -> Result<(), String> {
+    let lookup = |name: &str| {
+        parameters
+            .get(name)
+            .ok_or_else(|| format!("no parameter {name:?} is declared"))
+    };
+
+    match value {
+        toml::Value::String(s) => {
+            let whole = s
+                .strip_prefix("${")
+                .and_then(|rest| rest.strip_suffix('}'))
+                .filter(|name| !name.contains(['$', '{', '}']));
+            if let Some(name) = whole {
+                *value = lookup(name)?.clone();
+                return Ok(());
+            }
+
+            let mut substituted = String::with_capacity(s.len());
+            let mut rest = s.as_str();
+            while let Some(start) = rest.find('$') {
+                substituted.push_str(&rest[..start]);
+                rest = &rest[start..];
+                if let Some(after) = rest.strip_prefix("$$") {
+                    substituted.push('$');
+                    rest = after;
+                } else if let Some(after) = rest.strip_prefix("${") {
+                    let end = after
+                        .find('}')
+                        .ok_or_else(|| format!("unterminated reference in {s:?}"))?;
+                    match lookup(&after[..end])? {
+                        toml::Value::String(v) => substituted.push_str(v),
+                        v => substituted.push_str(&v.to_string()),
+                    }
+                    rest = &after[end + 1..];
+                } else {
+                    substituted.push('$');
+                    rest = &rest[1..];
+                }
+            }
+            substituted.push_str(rest);
+            *s = substituted;
+        }
+        toml::Value::Array(values) => {
+            for value in values {
+                substitute(value, parameters)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                substitute(value, parameters)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Set the element at the dotted `path` within `value`, creating missing tables on the way
+fn set_path(value: &mut toml::Value, path: &str, new: toml::Value) -> io::Result<()> {
+    let mut current = value;
+    for key in path.split('.') {
+        current = match current {
+            toml::Value::Table(table) => table
+                .entry(key)
+                .or_insert_with(|| toml::Value::Table(toml::Table::new())),
+            toml::Value::Array(array) => key
+                .parse::<usize>()
+                .ok()
+                .and_then(|idx| array.get_mut(idx))
+                .ok_or_else(|| invalid_data(format!("{path:?} has no element {key:?}")))?,
+            _ => return Err(invalid_data(format!("{path:?} has no element {key:?}"))),
+        };
+    }
+    *current = new;
+    Ok(())
+}
+
+/// Get the element at the dotted `path` within `value`, if any
+fn get_path<'a>(value: &'a toml::Value, path: &str) -> Option<&'a toml::Value> {
+    path.split('.')
+        .try_fold(value, |current, key| match current {
+            toml::Value::Table(table) => table.get(key),
+            toml::Value::Array(array) => array.get(key.parse::<usize>().ok()?),
+            _ => None,
+        })
+}
+
+/// State shared between the IO drivers of one [KernelConfig]
+pub struct IoContext<'a> {
+    /// Factories of drivers which are not built in
+    registry: &'a DriverRegistry,
+
+    /// CCSDS links by their transport
+    ccsds_links: HashMap<IoBp, Rc<RefCell<crate::io::ccsds::Link>>>,
+
+    /// Modbus servers by their listening address
+    modbus_servers: HashMap<std::net::SocketAddr, Rc<RefCell<crate::io::modbus::ServerState>>>,
+
+    /// Wasm modules by the path they are referred to by, used instead of reading the file
+    modules: HashMap<String, Vec<u8>>,
+}
+
+impl<'a> IoContext<'a> {
+    pub fn new(registry: &'a DriverRegistry) -> Self {
+        Self {
+            registry,
+            ccsds_links: HashMap::new(),
+            modbus_servers: HashMap::new(),
+            modules: HashMap::new(),
+        }
+    }
+
+    /// Use `wasm` wherever a driver refers to the Wasm module at `path`, e.g. as bundled in an
+    /// [Image]
+    pub fn bundle(&mut self, path: &str, wasm: &[u8]) {
+        self.modules.insert(path.into(), wasm.into());
+    }
+}
+
+impl From<&FieldBp> for Field {
+    fn from(bp: &FieldBp) -> Self {
+        match bp {
+            FieldBp::Type(ty) => Field {
+                name: None,
+                ty: *ty,
+            },
+            FieldBp::Named { name, ty } => Field {
+                name: Some(name.clone()),
+                ty: *ty,
+            },
+        }
+    }
+}
+
+impl StaticConfigBp {
+    /// Serialize the values according to the layout
+    pub fn to_bytes(&self) -> LwskResult<Vec<u8>> {
+        let values = match (&self.values, &self.file) {
+            (Some(values), None) => values.clone(),
+            (None, Some(file)) => {
+                let path = Path::new(file);
+                Format::from_path(path)
+                    .and_then(|format| format.parse(&fs::read(path)?))
+                    .map_err(|e| {
+                        error!("could not read {path:?}: {e}");
+                        LwskError::InvalidStaticConfig
+                    })?
+            }
+            _ => {
+                error!("exactly one of values and file must be given");
+                return Err(LwskError::InvalidStaticConfig);
+            }
+        };
+
+        let fields: Vec<Field> = self.layout.iter().map(Into::into).collect();
+        let values: Vec<&toml::Value> = match &values {
+            toml::Value::Array(values) if values.len() == fields.len() => values.iter().collect(),
+            toml::Value::Array(values) => {
+                error!(
+                    "{} values are given for a layout of {} fields",
+                    values.len(),
+                    fields.len()
+                );
+                return Err(LwskError::InvalidStaticConfig);
+            }
+            toml::Value::Table(table) => {
+                if let Some(key) = table
+                    .keys()
+                    .find(|key| !fields.iter().any(|f| f.name.as_ref() == Some(*key)))
+                {
+                    error!("the layout has no field named {key:?}");
+                    return Err(LwskError::InvalidStaticConfig);
+                }
+                let mut values = Vec::with_capacity(fields.len());
+                for (idx, field) in fields.iter().enumerate() {
+                    let Some(value) = field.name.as_ref().and_then(|name| table.get(name)) else {
+                        error!("no value is given for field {idx} of the layout");
+                        return Err(LwskError::InvalidStaticConfig);
+                    };
+                    values.push(value);
+                }
+                values
+            }
+            _ => {
+                error!("the values must be a table or an array");
+                return Err(LwskError::InvalidStaticConfig);
+            }
+        };
+
+        let mut bytes = Vec::new();
+        for (idx, (field, value)) in fields.iter().zip(values).enumerate() {
+            let converted = match value {
+                toml::Value::Integer(n) => field.ty.from_i128(*n as i128),
+                toml::Value::Float(n) => field.ty.from_f64(*n),
+                toml::Value::Boolean(b) => field.ty.from_i128(*b as i128),
+                _ => None,
+            };
+            let Some(converted) = converted else {
+                error!("{value} does not fit field {idx} of type {:?}", field.ty);
+                return Err(LwskError::InvalidStaticConfig);
+            };
+            converted.write(&mut bytes, Endian::Little);
+        }
+        Ok(bytes)
+    }
+}
+
+impl CodecBp {
+    pub fn to_codec(&self) -> Box<dyn Codec> {
+        use crate::io::codec::{Cbor, Crc, Json, Packed, Postcard};
+
+        let fields = |fields: &Vec<FieldBp>| fields.iter().map(Into::into).collect();
+        match self {
+            CodecBp::Packed { fields: f, endian } => Box::new(Packed {
+                fields: fields(f),
+                endian: *endian,
+            }),
+            CodecBp::Cbor { fields: f } => Box::new(Cbor { fields: fields(f) }),
+            CodecBp::Postcard { fields: f } => Box::new(Postcard { fields: fields(f) }),
+            CodecBp::Json { fields: f } => Box::new(Json { fields: fields(f) }),
+            CodecBp::Crc { algorithm } => Box::new(Crc {
+                algorithm: *algorithm,
+            }),
+        }
+    }
+}
+
+impl IoBindingBp {
+    pub fn policy(&self) -> ErrorPolicy {
+        ErrorPolicy {
+            retries: self.retries,
+            action: self.on_error,
+        }
+    }
+
+    /// Create the driver described by this binding, including its codecs
+    pub fn to_driver(&self, ctx: &mut IoContext<'_>) -> LwskResult<Box<dyn crate::io::IoDriver>> {
+        if !self.threaded {
+            return self.to_unthreaded_driver(ctx);
+        }
+
+        let binding = self.clone();
+        let registry = ctx.registry.clone();
+        let modules = ctx.modules.clone();
+        Ok(Box::new(crate::io::threaded::Threaded::spawn(
+            "lwsk-io",
+            std::time::Duration::from_micros(self.poll_interval_us),
+            move || {
+                let mut ctx = IoContext::new(&registry);
+                ctx.modules = modules;
+                binding.to_unthreaded_driver(&mut ctx)
+            },
+        )?))
+    }
+
+    fn to_unthreaded_driver(
+        &self,
+        ctx: &mut IoContext<'_>,
+    ) -> LwskResult<Box<dyn crate::io::IoDriver>> {
+        let driver = self.driver.to_driver(ctx)?;
+        if self.codec.is_empty() {
+            return Ok(driver);
+        }
+
+        let codecs = self.codec.iter().map(CodecBp::to_codec).collect();
+        Ok(Box::new(crate::io::codec::Coded::new(driver, codecs)))
+    }
+}
+
+impl IoBp {
+    /// Paths of the Wasm modules this driver refers to, including those of its transport
+    fn modules(&self) -> Vec<&str> {
+        match self {
+            IoBp::WasmPlant { wasm, .. } => vec![wasm],
+            IoBp::Mavlink { transport, .. } | IoBp::Ccsds { transport, .. } => transport.modules(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Make relative paths of this driver relative to `dir` instead
+    ///
+    /// Paths in the configuration of drivers which are not built in are left as they are.
+    fn resolve_paths(&mut self, dir: &Path) {
+        match self {
+            IoBp::WasmPlant { wasm, .. } => resolve_path(wasm, dir),
+            IoBp::Mavlink { transport, .. } | IoBp::Ccsds { transport, .. } => {
+                transport.resolve_paths(dir)
+            }
+            _ => {}
+        }
+    }
+
+    /// Create the driver described by this blueprint
+    pub fn to_driver(&self, ctx: &mut IoContext<'_>) -> LwskResult<Box<dyn crate::io::IoDriver>> {
+        Ok(match self {
+            IoBp::Udp {
+                bind,
+                connect,
+                send_to,
+                join,
+                allow_from,
+                ttl,
+                interface,
+                multicast_loop,
+                broadcast,
+            } => {
+                use crate::io::udp::{resolve, Udp, UdpOptions};
+
+                let options = UdpOptions {
+                    connect: connect.as_deref().map(resolve).transpose()?,
+                    send_to: send_to
+                        .iter()
+                        .map(|addr| resolve(addr.as_str()))
+                        .collect::<LwskResult<_>>()?,
+                    join: join
+                        .iter()
+                        .map(|group| {
+                            group.parse().map_err(|_| {
+                                error!("could not parse multicast group {group:?}");
+                                LwskError::InvalidAddress
+                            })
+                        })
+                        .collect::<LwskResult<_>>()?,
+                    allow_from: allow_from
+                        .iter()
+                        .map(|source| source.parse())
+                        .collect::<LwskResult<_>>()?,
+                    ttl: *ttl,
+                    interface: interface.as_deref().map(str::parse).transpose()?,
+                    multicast_loop: *multicast_loop,
+                    broadcast: *broadcast,
+                };
+                Box::new(Udp::with_options(resolve(bind.as_str())?, &options)?)
+            }
+            IoBp::SharedMemory { name, size } => {
+                Box::new(crate::io::shm::SharedMemory::new(name, *size)?)
+            }
+            IoBp::Serial { path, baud } => Box::new(crate::io::serial::Serial::new(path, *baud)?),
+            IoBp::Mavlink {
+                transport,
+                system_id,
+                component_id,
+                rx,
+                tx,
+            } => Box::new(crate::io::mavlink::Mavlink::new(
+                transport.to_driver(ctx)?,
+                *system_id,
+                *component_id,
+                rx.iter().map(Into::into).collect(),
+                tx.iter().map(Into::into).collect(),
+            )?),
+            IoBp::Ccsds {
+                transport,
+                apid,
+                telecommand,
+                time_code,
+            } => {
+                use crate::io::ccsds::{Ccsds, Link, TimeCode};
+
+                let link = match ctx.ccsds_links.get(transport.as_ref()) {
+                    Some(link) => link.clone(),
+                    None => {
+                        let link = Rc::new(RefCell::new(Link::new(transport.to_driver(ctx)?)));
+                        ctx.ccsds_links
+                            .insert(transport.as_ref().clone(), link.clone());
+                        link
+                    }
+                };
+                let time_code = time_code
+                    .as_ref()
+                    .map(|bp| TimeCode::new(bp.coarse, bp.fine))
+                    .transpose()?;
+                Box::new(Ccsds::new(link, *apid, *telecommand, time_code)?)
+            }
+            IoBp::Modbus {
+                role,
+                address,
+                unit_id,
+                table,
+                start,
+                count,
+                timeout_ms,
+            } => {
+                use crate::io::modbus::{Client, Range, Server, ServerState};
+
+                let address = crate::io::udp::resolve(address.as_str())?;
+                let range = Range {
+                    table: *table,
+                    start: *start,
+                    count: *count,
+                };
+                match role {
+                    ModbusRoleBp::Client => Box::new(Client::new(
+                        address,
+                        *unit_id,
+                        range,
+                        std::time::Duration::from_millis(*timeout_ms),
+                    )?),
+                    ModbusRoleBp::Server => {
+                        let state = match ctx.modbus_servers.get(&address) {
+                            Some(state) => state.clone(),
+                            None => {
+                                let state = Rc::new(RefCell::new(ServerState::bind(address)?));
+                                ctx.modbus_servers.insert(address, state.clone());
+                                state
+                            }
+                        };
+                        Box::new(Server::new(state, range)?)
+                    }
+                }
+            }
+            IoBp::Mqtt {
+                broker,
+                client_id,
+                subscribe,
+                publish,
+                qos,
+                retain,
+                clean_session,
+                keep_alive_s,
+                reconnect_ms,
+                will,
+                username,
+                password,
+            } => {
+                use crate::io::mqtt::{Mqtt, Options, Will};
+                use std::time::Duration;
+
+                let options = Options {
+                    client_id: client_id.clone(),
+                    subscribe: subscribe.clone(),
+                    publish: publish.clone(),
+                    qos: *qos,
+                    retain: *retain,
+                    clean_session: *clean_session,
+                    keep_alive: Duration::from_secs(*keep_alive_s),
+                    reconnect: Duration::from_millis(*reconnect_ms),
+                    will: will.as_ref().map(|will| Will {
+                        topic: will.topic.clone(),
+                        payload: will.payload.clone().into_bytes(),
+                        qos: will.qos,
+                        retain: will.retain,
+                    }),
+                    username: username.clone(),
+                    password: password.clone(),
+                };
+                Box::new(Mqtt::new(
+                    crate::io::udp::resolve(broker.as_str())?,
+                    options,
+                )?)
+            }
+            IoBp::Constant { data } => Box::new(crate::io::memory::Constant::new(data.into())),
+            IoBp::Sequence { values, repeat } => Box::new(crate::io::memory::Sequence::new(
+                values.iter().map(Into::into).collect(),
+                *repeat,
+            )),
+            IoBp::Sink { limit } => Box::new(crate::io::memory::Sink::new(*limit)),
+            IoBp::Loopback { capacity } => Box::new(crate::io::memory::Loopback::new(*capacity)),
+            IoBp::WasmPlant {
+                wasm,
+                fuel_per_call,
+            } => Box::new(match ctx.modules.get(wasm) {
+                Some(bytes) => crate::io::plant::Plant::from_bytes(wasm, bytes, *fuel_per_call)?,
+                None => crate::io::plant::Plant::load(wasm, *fuel_per_call)?,
+            }),
+            #[cfg(target_os = "linux")]
+            IoBp::Timer {
+                period_ns,
+                delay_ns,
+            } => Box::new(crate::io::fd::Timer::new(
+                std::time::Duration::from_nanos(*period_ns),
+                std::time::Duration::from_nanos(*delay_ns),
+            )?),
+            #[cfg(target_os = "linux")]
+            IoBp::Event { semaphore } => Box::new(crate::io::fd::Event::new(*semaphore)?),
+            #[cfg(not(target_os = "linux"))]
+            IoBp::Timer { .. } | IoBp::Event { .. } => {
+                error!("timer and event IO drivers are only available on Linux");
+                return Err(crate::io::DriverError::Unsupported.into());
+            }
+            IoBp::Plugin(PluginBp { ty, config }) => ctx.registry.create(ty, config)?,
         })
     }
 }
 
 /// What to do with the linear memory of an interpreter when a timeout occured
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
 pub enum OnTimeAbort {
     /// Reset the linear memory to the initial state after loading the WASM
     Reset,
@@ -227,3 +2503,184 @@ pub enum OnTimeAbort {
     /// Warning: this is dangerous, you must ensure that all state is checked before usage
     Keep,
 }
+
+/// Let the type of an IO driver be called `alias` as well
+fn also_named(alias: &'static str) -> impl FnMut(&mut schemars::Schema) {
+    move |schema| {
+        let Some(ty) = schema.pointer_mut("/properties/type") else {
+            return;
+        };
+        if let Some(name) = ty.as_object_mut().and_then(|ty| ty.remove("const")) {
+            ty["enum"] = serde_json::json!([name, alias]);
+        }
+    }
+}
+
+/// Restrict the type of plugin IO drivers to names which are not built in, as otherwise a
+/// misconfigured built-in driver would pass as a plugin
+fn exclude_built_in_types(schema: &mut schemars::Schema) {
+    let Some(serde_json::Value::Array(variants)) = schema.get_mut("anyOf") else {
+        return;
+    };
+
+    let mut built_in = Vec::new();
+    for variant in variants.iter() {
+        match variant.pointer("/properties/type") {
+            Some(ty) if ty.get("const").is_some() => built_in.push(ty["const"].clone()),
+            Some(ty) => built_in.extend(ty["enum"].as_array().into_iter().flatten().cloned()),
+            None => {}
+        }
+    }
+
+    for variant in variants.iter_mut() {
+        if variant.pointer("/properties/type").is_none() {
+            let plugin = core::mem::take(variant);
+            *variant = serde_json::json!({
+                "allOf": [plugin, { "properties": { "type": { "not": { "enum": built_in } } } }],
+            });
+        }
+    }
+}
+
+/// Add `driver`, the schema of the configuration of the plugin IO driver type `type_name`, to the
+/// blueprint `schema`, excluding the type from other plugins
+fn add_plugin_schema(schema: &mut serde_json::Value, type_name: &str, driver: serde_json::Value) {
+    let serde_json::Value::Object(mut driver) = driver else {
+        // a boolean schema accepts or rejects any configuration, which the type tag refines
+        return add_plugin_schema(schema, type_name, serde_json::json!({}));
+    };
+    driver.remove("$schema");
+    driver.remove("title");
+    if let Some(serde_json::Value::Object(defs)) = driver.remove("$defs") {
+        if let Some(all) = schema["$defs"].as_object_mut() {
+            for (name, def) in defs {
+                all.entry(name).or_insert(def);
+            }
+        }
+    }
+
+    let mut driver = serde_json::Value::Object(driver);
+    driver["properties"]["type"] = serde_json::json!({ "const": type_name });
+    match driver["required"].as_array_mut() {
+        Some(required) => required.push("type".into()),
+        None => driver["required"] = serde_json::json!(["type"]),
+    }
+
+    let Some(serde_json::Value::Array(variants)) = schema.pointer_mut("/$defs/IoBindingBp/anyOf")
+    else {
+        return;
+    };
+    let plugin = variants
+        .iter()
+        .position(|variant| variant.get("allOf").is_some())
+        .unwrap_or(variants.len());
+    if let Some(serde_json::Value::Array(excluded)) = variants
+        .get_mut(plugin)
+        .and_then(|p| p.pointer_mut("/allOf/1/properties/type/not/enum"))
+    {
+        excluded.push(type_name.into());
+    }
+    variants.insert(plugin, driver);
+}
+
+/// Let all integer, float and boolean values in `schema` alternatively be a reference to a
+/// parameter
+fn allow_references(schema: &mut serde_json::Value) {
+    match schema {
+        serde_json::Value::Object(object) => {
+            object.values_mut().for_each(allow_references);
+
+            let is_scalar = |ty: &serde_json::Value| {
+                matches!(ty.as_str(), Some("integer" | "number" | "boolean"))
+            };
+            let is_scalar = match object.get("type") {
+                Some(serde_json::Value::Array(types)) => types.iter().any(is_scalar),
+                Some(ty) => is_scalar(ty),
+                None => false,
+            };
+            if is_scalar {
+                let description = object.remove("description");
+                let default = object.remove("default");
+                let scalar = serde_json::Value::Object(core::mem::take(object));
+                object.insert(
+                    "anyOf".into(),
+                    serde_json::json!([
+                        scalar,
+                        { "type": "string", "pattern": r"^\$\{[^}]+\}$" },
+                    ]),
+                );
+                object.extend(description.map(|d| ("description".into(), d)));
+                object.extend(default.map(|d| ("default".into(), d)));
+            }
+        }
+        serde_json::Value::Array(array) => array.iter_mut().for_each(allow_references),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOT: &str = r#"
+        include = [{ path = "inner.toml", namespace = "inner" }]
+
+        [channels.shared]
+        size = 1
+
+        [io.src]
+        type = "Loopback"
+    "#;
+
+    const INNER: &str = r#"
+        [channels.local]
+        size = 1
+
+        [[schedules.main]]
+        from_io = "../src"
+        to_channel = "local"
+        [[schedules.main]]
+        from_channel = "/shared"
+        to_io = "../src"
+        [[schedules.main]]
+        switch_to_schedule = "main"
+    "#;
+
+    #[test]
+    fn references_resolve_relative_to_the_namespace() {
+        let dir = std::env::temp_dir().join(format!("lwsk-blueprint-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("root.toml"), ROOT).unwrap();
+        fs::write(dir.join("inner.toml"), INNER).unwrap();
+
+        let bp = Blueprint::new(dir.join("root.toml")).unwrap();
+        let references: Vec<_> = bp.schedules["inner/main"]
+            .iter()
+            .flat_map(ScheduleBp::references)
+            .map(|(kind, name)| (kind, name.as_str()))
+            .collect();
+        assert_eq!(
+            references,
+            [
+                ("IO binding", "src"),
+                ("channel", "inner/local"),
+                ("channel", "shared"),
+                ("IO binding", "src"),
+                ("schedule", "inner/main"),
+            ]
+        );
+
+        // without `../`, the IO binding is looked up in the namespace of the included blueprint
+        fs::write(dir.join("inner.toml"), INNER.replacen("../src", "src", 1)).unwrap();
+        let e = Blueprint::new(dir.join("root.toml")).unwrap_err();
+        let Some(LwskError::UnknownReference { file, kind, name }) =
+            e.get_ref().and_then(|e| e.downcast_ref())
+        else {
+            panic!("{e}");
+        };
+        assert!(file.contains("inner.toml"), "{file}");
+        assert_eq!((*kind, name.as_str()), ("IO binding", "inner/src"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,13 +1,44 @@
-use std::path::PathBuf;
+//! Command line interface of the kernel
+//!
+//! Crates providing their own IO drivers can run the kernel through [run], handing in a
+//! [DriverRegistry] with their drivers registered.
 
-use clap::Parser;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use clap::{Parser, Subcommand};
+
+use crate::analysis::{self, ScheduleAnalysis};
+use crate::blueprint::{Blueprint, Format, Overrides};
+use crate::diff::Change;
+use crate::graph::GraphFormat;
+use crate::image::Image;
+use crate::io::memory::Sink;
+use crate::io::record::{Cursor, Position, Recorder, Recording};
+use crate::io::registry::DriverRegistry;
+use crate::io::{HealthEvent, IoDriver};
+use crate::resolved::Resolved;
+use crate::schedule::ScheduleEntry;
+use crate::{KernelConfig, LwskError, LwskResult, Step};
 
 /// The Linux Wasm Seperation Kernel. Or Lighweight Wucke13 & Seven Kernel?
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub struct Args {
-    /// Blueprint to load
-    pub blueprint: PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Blueprint to load, in TOML, JSON, YAML or CBOR as told by its extension, its resolved form
+    /// printed by `lwsk resolve`, or an image built by `lwsk build`
+    #[clap(required = true)]
+    pub blueprint: Option<PathBuf>,
 
     /// Just parse and validate the blueprint, terminate then
     #[clap(short, long)]
@@ -16,4 +47,492 @@ pub struct Args {
     /// Require every wasm function to parse successfully
     #[clap(short, long)]
     pub strict: bool,
+
+    /// Record all data pulled from IO drivers to this file
+    #[clap(long, conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// Replace all IO drivers by players of this recording
+    #[clap(long)]
+    pub replay: Option<PathBuf>,
+
+    /// Override a parameter, or a value at a dotted path like `io.speed_in.bind`
+    #[clap(long = "set", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub overrides: Vec<(String, String)>,
+}
+
+/// Tasks other than running the kernel
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Convert a blueprint between TOML, JSON, YAML and CBOR, as told by the file extensions
+    ///
+    /// Includes and parameters are kept as they are.
+    Convert {
+        /// Blueprint to convert
+        input: PathBuf,
+
+        /// Blueprint to write
+        output: PathBuf,
+    },
+
+    /// Validate a blueprint and build a self-contained image of it, bundling all Wasm modules
+    Build {
+        blueprint: PathBuf,
+
+        /// Image to write
+        #[clap(short, long)]
+        output: PathBuf,
+
+        /// Override a parameter, or a value at a dotted path like `io.speed_in.bind`
+        #[clap(long = "set", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        overrides: Vec<(String, String)>,
+    },
+
+    /// Analyze the worst-case timing of the schedules, as declared by the `timing` of a blueprint
+    ///
+    /// Fails if a frame exceeds its budget.
+    Analyze {
+        blueprint: PathBuf,
+
+        /// Override a parameter, or a value at a dotted path like `io.speed_in.bind`
+        #[clap(long = "set", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        overrides: Vec<(String, String)>,
+    },
+
+    /// Print the fully resolved form of a blueprint, which can be loaded like a blueprint
+    ///
+    /// The format is told by the extension of the output, TOML by default.
+    Resolve {
+        blueprint: PathBuf,
+
+        /// File to write the resolved form to instead
+        #[clap(short, long, conflicts_with = "verify")]
+        output: Option<PathBuf>,
+
+        /// Verify that this resolved form matches the blueprint instead
+        #[clap(long)]
+        verify: Option<PathBuf>,
+
+        /// Override a parameter, or a value at a dotted path like `io.speed_in.bind`
+        #[clap(long = "set", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        overrides: Vec<(String, String)>,
+    },
+
+    /// Report the semantic differences between two blueprints
+    ///
+    /// Fails if a change breaks the `INPUT` or `OUTPUT` layout of a function whose Wasm module
+    /// stays the same.
+    Diff { old: PathBuf, new: PathBuf },
+
+    /// Print the dataflow and the mode graph of a blueprint
+    ///
+    /// Channels without producer or consumer and functions which are never scheduled are
+    /// highlighted and reported.
+    Graph {
+        blueprint: PathBuf,
+
+        #[clap(short, long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+
+        /// File to write the graph to instead
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+
+        /// Override a parameter, or a value at a dotted path like `io.speed_in.bind`
+        #[clap(long = "set", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        overrides: Vec<(String, String)>,
+    },
+
+    /// Print the JSON Schema of blueprints, for editors to complete and check them
+    ///
+    /// Language servers pick it up from a `#:schema blueprint.json` comment in TOML, or a
+    /// `# yaml-language-server: $schema=blueprint.json` comment in YAML.
+    Schema {
+        /// File to write the schema to instead
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+impl Command {
+    pub fn run(&self, registry: &DriverRegistry) -> io::Result<()> {
+        match self {
+            Command::Convert { input, output } => {
+                let value = Format::from_path(input)?.parse(&fs::read(input)?)?;
+                fs::write(output, Format::from_path(output)?.serialize(&value)?)?;
+                info!("converted {input:?} to {output:?}");
+            }
+            Command::Build {
+                blueprint,
+                output,
+                overrides,
+            } => {
+                let bp = Blueprint::with_overrides(blueprint, &to_overrides(overrides))?;
+                let image = bp.to_image().map_err(io::Error::other)?;
+                fs::write(output, &image)?;
+                info!("built {output:?} of {} bytes", image.len());
+            }
+            Command::Analyze {
+                blueprint,
+                overrides,
+            } => {
+                let bp = Blueprint::with_overrides(blueprint, &to_overrides(overrides))?;
+                let timing = bp.timing().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{blueprint:?} declares no timing"),
+                    )
+                })?;
+                let kconfig = bp
+                    .to_kernel_config_with_io(|_, _, _| Ok(Box::new(Sink::new(Some(0)))))
+                    .map_err(io::Error::other)?;
+                kconfig.validate().map_err(io::Error::other)?;
+
+                let analyses = analysis::analyze(&kconfig, &timing).map_err(io::Error::other)?;
+                report(&mut io::stdout().lock(), &kconfig, &analyses)?;
+                if !analyses.iter().all(ScheduleAnalysis::fits) {
+                    return Err(io::Error::other("a frame exceeds its budget"));
+                }
+            }
+            Command::Resolve {
+                blueprint,
+                output,
+                verify,
+                overrides,
+            } => {
+                let bp = Blueprint::with_overrides(blueprint, &to_overrides(overrides))?;
+                let resolved = bp.resolve().map_err(io::Error::other)?;
+
+                if let Some(path) = verify {
+                    let differences = Resolved::load(path)?.differences(&resolved);
+                    for difference in &differences {
+                        error!("{difference} differs from {blueprint:?}");
+                    }
+                    if !differences.is_empty() {
+                        return Err(io::Error::other(format!("{path:?} does not match")));
+                    }
+                    info!("{path:?} matches {blueprint:?}");
+                    return Ok(());
+                }
+
+                match output {
+                    Some(path) => fs::write(path, Format::from_path(path)?.serialize(&resolved)?)?,
+                    None => io::stdout().write_all(&Format::Toml.serialize(&resolved)?)?,
+                }
+            }
+            Command::Diff { old, new } => {
+                let changes = Blueprint::new(old)?.diff(&Blueprint::new(new)?);
+                let mut out = io::stdout().lock();
+                for change in &changes {
+                    writeln!(out, "{change}")?;
+                }
+                if changes.iter().any(Change::is_breaking) {
+                    return Err(io::Error::other("a change breaks an unchanged function"));
+                }
+            }
+            Command::Graph {
+                blueprint,
+                format,
+                output,
+                overrides,
+            } => {
+                let graph = Blueprint::with_overrides(blueprint, &to_overrides(overrides))?.graph();
+                for channel in graph.unproduced_channels() {
+                    warn!("channel {channel:?} has no producer");
+                }
+                for channel in graph.unconsumed_channels() {
+                    warn!("channel {channel:?} has no consumer");
+                }
+                for function in graph.unscheduled_functions() {
+                    warn!("function {function:?} is never scheduled");
+                }
+
+                let rendered = graph.render(*format);
+                match output {
+                    Some(path) => fs::write(path, rendered)?,
+                    None => write!(io::stdout(), "{rendered}")?,
+                }
+            }
+            Command::Schema { output } => {
+                let schema =
+                    serde_json::to_string_pretty(&Blueprint::json_schema_with_registry(registry))?;
+                match output {
+                    Some(path) => fs::write(path, schema)?,
+                    None => writeln!(io::stdout(), "{schema}")?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write the result of a timing analysis in human readable form
+fn report<W: Write>(
+    out: &mut W,
+    kconfig: &KernelConfig,
+    analyses: &[ScheduleAnalysis],
+) -> io::Result<()> {
+    let describe = |entry: &ScheduleEntry| match *entry {
+        ScheduleEntry::FunctionInvocation(idx) | ScheduleEntry::SporadicInvocation(idx) => {
+            format!("function {:?}", kconfig.functions[idx].name)
+        }
+        ScheduleEntry::IoIn { from_io_idx, .. } => {
+            format!("pull from {:?}", kconfig.io[from_io_idx].name)
+        }
+        ScheduleEntry::IoOut { to_io_idx, .. } => {
+            format!("push to {:?}", kconfig.io[to_io_idx].name)
+        }
+        _ => format!("{entry:?}"),
+    };
+
+    for analysis in analyses {
+        let schedule = &kconfig.schedules[analysis.schedule_idx];
+        write!(
+            out,
+            "schedule {:?}: frame of {:?}",
+            schedule.name, analysis.frame
+        )?;
+        match analysis.budget {
+            Some(budget) if analysis.fits() => write!(out, " within budget of {budget:?}")?,
+            Some(budget) => write!(
+                out,
+                " EXCEEDS budget of {budget:?} by {:?}",
+                analysis.frame - budget
+            )?,
+            None => {}
+        }
+        if !analysis.cyclic {
+            write!(out, ", then switches")?;
+        }
+        writeln!(out)?;
+
+        for stale in &analysis.stale_reads {
+            writeln!(
+                out,
+                "  {} reads channel {:?} before {} writes it, yielding data of the previous frame",
+                describe(&schedule.sequence[stale.entry_idx]),
+                kconfig.channels[stale.channel_idx].name,
+                describe(&schedule.sequence[stale.writer_entry_idx]),
+            )?;
+        }
+        for latency in &analysis.latencies {
+            writeln!(
+                out,
+                "  latency from {:?} to {:?}: {:?}",
+                kconfig.io[latency.from_io_idx].name,
+                kconfig.io[latency.to_io_idx].name,
+                latency.latency
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn to_overrides(pairs: &[(String, String)]) -> Overrides {
+    let mut overrides = Overrides::default();
+    for (key, value) in pairs {
+        overrides.set(key, value);
+    }
+    overrides
+}
+
+/// What the kernel is configured from
+enum Source {
+    Blueprint(Blueprint),
+    Resolved(Resolved),
+
+    /// Encoded [Image]
+    Image(Vec<u8>),
+}
+
+impl Source {
+    fn open(path: &Path, overrides: &[(String, String)]) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        if Image::is_image(&bytes) {
+            if !overrides.is_empty() {
+                warn!("{path:?} is an image, ignoring overrides");
+            }
+            Image::decode(&bytes).map_err(io::Error::other)?;
+            return Ok(Self::Image(bytes));
+        }
+
+        let value = Format::from_path(path)?.parse(&bytes)?;
+        if Resolved::is_resolved(&value) {
+            if !overrides.is_empty() {
+                warn!("{path:?} is resolved, ignoring overrides");
+            }
+            return Resolved::from_value(value).map(Self::Resolved);
+        }
+
+        Blueprint::with_overrides(path, &to_overrides(overrides)).map(Self::Blueprint)
+    }
+
+    fn to_kernel_config_with_io<F>(&self, mut make_io: F) -> LwskResult<KernelConfig>
+    where
+        F: FnMut() -> LwskResult<Box<dyn IoDriver>>,
+    {
+        match self {
+            Source::Blueprint(bp) => bp.to_kernel_config_with_io(|_, _, _| make_io()),
+            Source::Resolved(resolved) => resolved.to_kernel_config_with_io(|_, _, _| make_io()),
+            Source::Image(bytes) => {
+                Image::decode(bytes)?.to_kernel_config_with_io(|_, _| make_io())
+            }
+        }
+    }
+
+    fn to_kernel_config(&self, registry: &DriverRegistry) -> LwskResult<KernelConfig> {
+        match self {
+            Source::Blueprint(bp) => bp.to_kernel_config_with_registry(registry),
+            Source::Resolved(resolved) => resolved.to_kernel_config(registry),
+            Source::Image(bytes) => Image::decode(bytes)?.to_kernel_config(registry),
+        }
+    }
+}
+
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(key, value)| (key.into(), value.into()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got {s:?}"))
+}
+
+/// Parse the command line and run the kernel, with the drivers in `registry` in addition to the
+/// built-in ones
+pub fn run(registry: &DriverRegistry) {
+    let level = std::env::var("RUST_LOG").unwrap_or_else(|_| "info".into());
+    std::env::set_var("RUST_LOG", level.clone());
+
+    pretty_env_logger::formatted_builder()
+        .parse_filters(&level)
+        .format_timestamp_secs()
+        .init();
+
+    let args: Args = clap::Parser::parse();
+
+    if let Some(command) = &args.command {
+        if let Err(e) = command.run(registry) {
+            error!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    let blueprint = args
+        .blueprint
+        .as_ref()
+        .expect("clap requires a blueprint without command");
+
+    info!("reading config");
+    let source = match Source::open(blueprint, &args.overrides) {
+        Ok(source) => source,
+        Err(e) => {
+            error!("{e}");
+            panic!("");
+        }
+    };
+
+    info!("configuring kernel");
+    let cursor = args.replay.as_ref().map(|_| Cursor::new());
+    let mut kconfig = match (&args.replay, &cursor) {
+        (Some(path), Some(cursor)) => {
+            info!("replaying {path:?}");
+            let recording = Recording::open(path).unwrap();
+            let mut players = recording.clone().into_players(cursor).into_iter();
+            let mut kconfig = source
+                .to_kernel_config_with_io(|| {
+                    players
+                        .next()
+                        .map(|player| Box::new(player) as Box<dyn IoDriver>)
+                        .ok_or(LwskError::InvalidRecording)
+                })
+                .unwrap();
+            let io_names = kconfig.io.iter().map(|binding| binding.name.as_str());
+            recording.validate(&kconfig, io_names).unwrap();
+            // each recorded pull is the outcome of all its attempts, retrying would consume the
+            // records of later pulls
+            for binding in &mut kconfig.io {
+                binding.policy.retries = 0;
+            }
+            kconfig
+        }
+        _ => source.to_kernel_config(registry).unwrap(),
+    };
+    kconfig.validate().unwrap();
+
+    if args.only_validate {
+        return;
+    }
+
+    let mut recorder = args.record.as_ref().map(|path| {
+        info!("recording io to {path:?}");
+        let io_names = kconfig.io.iter().map(|binding| binding.name.as_str());
+        Recorder::create(path, io_names).unwrap()
+    });
+
+    info!("entering main loop");
+    loop {
+        if let Some(cursor) = &cursor {
+            cursor.advance(&kconfig);
+        }
+        let step = kconfig.step();
+        if cursor.as_ref().is_some_and(Cursor::diverged) {
+            error!("replay diverged from the recording");
+            std::process::exit(1);
+        }
+
+        // keep file IO out of the frame, but lose at most one frame on a crash
+        let schedule = &kconfig.schedules[kconfig.current_schedule_idx];
+        let frame_end = matches!(
+            step,
+            Step::Switched { .. } | Step::Wait(_) | Step::AwaitIo { .. }
+        ) || schedule.current_action + 1 == schedule.sequence.len();
+        if let Some(recorder) = recorder.as_mut().filter(|_| frame_end) {
+            if let Err(e) = recorder.flush() {
+                error!("could not write the recording: {e}");
+            }
+        }
+
+        match step {
+            Step::Pulled {
+                io_idx,
+                channel_idx,
+                result,
+            } => {
+                if let Some(recorder) = &mut recorder {
+                    let position = Position {
+                        schedule_idx: kconfig.current_schedule_idx,
+                        action_idx: kconfig.schedules[kconfig.current_schedule_idx].current_action,
+                    };
+                    let channel = &kconfig.channels[channel_idx];
+                    if let Err(e) = recorder.record_pull(
+                        position,
+                        io_idx,
+                        &result,
+                        &channel.buf,
+                        channel.timestamp,
+                    ) {
+                        error!("could not record io[{io_idx}]: {e}");
+                    }
+                }
+            }
+            Step::Wait(duration) => {
+                std::thread::sleep(duration);
+            }
+            Step::AwaitIo { io_idx, timeout } => {
+                kconfig.io[io_idx].wait_ready(timeout);
+            }
+            // errors are handled according to the policy of the io binding, or logged by the kernel
+            Step::Invoked { .. }
+            | Step::Skipped { .. }
+            | Step::Pushed { .. }
+            | Step::Switched { .. } => {}
+        }
+
+        // act as health monitor
+        for HealthEvent { io_idx, error } in kconfig.take_health_events() {
+            let health = kconfig.io[io_idx].health();
+            error!(
+                "health event: {:?}/io[{io_idx}] failed {} times in a row: {error}, {:?}",
+                kconfig.io[io_idx].name, health.consecutive_failures, health.connection
+            );
+        }
+    }
 }
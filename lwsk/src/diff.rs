@@ -0,0 +1,288 @@
+//! Semantic differences between two blueprints, as reported by `lwsk diff`
+//!
+//! Changes of the layout of the `INPUT` or `OUTPUT` global a function is bound to, or of its static
+//! configuration, are breaking if the Wasm module of the function stays the same: the guest still
+//! expects the previous layout.
+
+use core::fmt;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+/// SHA-256 of a Wasm module
+pub type ModuleHash = [u8; 32];
+
+/// Hash the Wasm module at `path`, if it can be read
+pub fn module_hash(path: &Path) -> Option<ModuleHash> {
+    let wasm = std::fs::read(path)
+        .inspect_err(|e| warn!("could not read {path:?}: {e}"))
+        .ok()?;
+    Some(Sha256::digest(wasm).into())
+}
+
+/// What kind of definition changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Channel,
+    Function,
+    Schedule,
+    Io,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Kind::Channel => "channel",
+            Kind::Function => "function",
+            Kind::Schedule => "schedule",
+            Kind::Io => "IO binding",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added {
+        kind: Kind,
+        name: String,
+    },
+    Removed {
+        kind: Kind,
+        name: String,
+    },
+    ChannelSize {
+        channel: String,
+        old: usize,
+        new: usize,
+    },
+
+    /// The Wasm module of a function changed, a hash is missing if the module could not be read
+    Module {
+        function: String,
+        old: Option<ModuleHash>,
+        new: Option<ModuleHash>,
+    },
+
+    /// A function consumes or produces another channel
+    Binding {
+        function: String,
+        global: &'static str,
+        old: Option<String>,
+        new: Option<String>,
+    },
+
+    /// The size of the `INPUT` or `OUTPUT` global a function is bound to, or of its static
+    /// configuration, changed while its module did not, see [Change::is_breaking]
+    Layout {
+        function: String,
+        global: String,
+        old: Option<usize>,
+        new: Option<usize>,
+    },
+
+    /// The static configuration of a function changed
+    StaticConfig {
+        function: String,
+    },
+
+    /// A budget like the fuel per call of a function or the frame of a schedule changed
+    Budget {
+        what: String,
+        old: Option<u64>,
+        new: Option<u64>,
+    },
+
+    /// Entries of a schedule were removed or added, each with its index in the old or new schedule
+    ScheduleEntries {
+        schedule: String,
+        removed: Vec<(usize, String)>,
+        added: Vec<(usize, String)>,
+    },
+
+    /// The configuration of an IO binding changed
+    Io {
+        name: String,
+    },
+}
+
+impl Change {
+    /// Whether this change breaks the layout expected by an unchanged guest
+    pub fn is_breaking(&self) -> bool {
+        matches!(self, Change::Layout { .. })
+    }
+}
+
+struct Hex<'a>(&'a Option<ModuleHash>);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(hash) => hash[..8]
+                .iter()
+                .try_for_each(|byte| write!(f, "{byte:02x}")),
+            None => f.write_str("unreadable"),
+        }
+    }
+}
+
+struct Maybe<'a, T>(&'a Option<T>, &'static str);
+
+impl<T: fmt::Debug> fmt::Display for Maybe<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(value) => write!(f, "{value:?}"),
+            None => f.write_str(self.1),
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added { kind, name } => write!(f, "+ {kind} {name:?}"),
+            Change::Removed { kind, name } => write!(f, "- {kind} {name:?}"),
+            Change::ChannelSize { channel, old, new } => {
+                write!(f, "~ channel {channel:?}: size {old} -> {new}")
+            }
+            Change::Module { function, old, new } => write!(
+                f,
+                "~ function {function:?}: module {} -> {}",
+                Hex(old),
+                Hex(new)
+            ),
+            Change::Binding {
+                function,
+                global,
+                old,
+                new,
+            } => write!(
+                f,
+                "~ function {function:?}: {global} bound to {} -> {}",
+                Maybe(old, "nothing"),
+                Maybe(new, "nothing")
+            ),
+            Change::Layout {
+                function,
+                global,
+                old,
+                new,
+            } => write!(
+                f,
+                "! function {function:?}: {global} of {} bytes -> {} bytes, breaking the unchanged module",
+                Maybe(old, "no"),
+                Maybe(new, "no")
+            ),
+            Change::StaticConfig { function } => {
+                write!(f, "~ function {function:?}: static configuration changed")
+            }
+            Change::Budget { what, old, new } => write!(
+                f,
+                "~ {what}: {} -> {}",
+                Maybe(old, "none"),
+                Maybe(new, "none")
+            ),
+            Change::ScheduleEntries {
+                schedule,
+                removed,
+                added,
+            } => {
+                write!(f, "~ schedule {schedule:?}:")?;
+                for (idx, entry) in removed {
+                    write!(f, "\n    - [{idx}] {entry}")?;
+                }
+                for (idx, entry) in added {
+                    write!(f, "\n    + [{idx}] {entry}")?;
+                }
+                Ok(())
+            }
+            Change::Io { name } => write!(f, "~ IO binding {name:?}: configuration changed"),
+        }
+    }
+}
+
+/// Entries only in `old` and only in `new`, with their indices, by a longest common subsequence
+pub fn edits<T: PartialEq>(old: &[T], new: &[T]) -> (Vec<usize>, Vec<usize>) {
+    // lengths of the longest common subsequence of the suffixes
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = match old[i] == new[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            removed.push(i);
+            i += 1;
+        } else {
+            added.push(j);
+            j += 1;
+        }
+    }
+    (removed, added)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blueprint::Blueprint;
+
+    fn blueprint(wasm: &Path, size: usize) -> Blueprint {
+        toml::from_str(&format!(
+            r#"
+            [channels.input]
+            size = {size}
+
+            [functions.filter]
+            wasm = {wasm:?}
+            consumes = "input"
+            fuel_per_call = 100
+            "#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn layout_changes_of_unchanged_modules_are_breaking() {
+        let dir = std::env::temp_dir().join(format!("lwsk-diff-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (old_wasm, new_wasm) = (dir.join("old.wasm"), dir.join("new.wasm"));
+        std::fs::write(&old_wasm, b"\0asm\x01\0\0\0").unwrap();
+        std::fs::write(&new_wasm, b"\0asm\x01\0\0\0\0").unwrap();
+
+        let changes = blueprint(&old_wasm, 4).diff(&blueprint(&old_wasm, 8));
+        assert_eq!(
+            changes,
+            [
+                Change::ChannelSize {
+                    channel: "input".into(),
+                    old: 4,
+                    new: 8,
+                },
+                Change::Layout {
+                    function: "filter".into(),
+                    global: "INPUT".into(),
+                    old: Some(4),
+                    new: Some(8),
+                },
+            ]
+        );
+        assert!(!changes[0].is_breaking());
+        assert!(changes[1].is_breaking());
+
+        // a new module is expected to follow the new layout
+        let changes = blueprint(&old_wasm, 4).diff(&blueprint(&new_wasm, 8));
+        assert!(matches!(changes[1], Change::Module { .. }), "{changes:?}");
+        assert!(!changes.iter().any(Change::is_breaking), "{changes:?}");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,271 @@
+//! Graphs of a blueprint, for design reviews and documentation
+//!
+//! - the dataflow from IO drivers via channels and functions to IO drivers
+//! - the mode graph, i.e. which schedule switches to which
+//!
+//! Channels without producer or consumer and functions which are never scheduled are highlighted,
+//! as they hint at a mistake in the blueprint. Both graphs can be rendered as Graphviz DOT or as
+//! Mermaid flowchart.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// A node of the dataflow graph
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Node {
+    Io(String),
+    Channel(String),
+    Function(String),
+}
+
+impl Node {
+    pub fn name(&self) -> &str {
+        match self {
+            Node::Io(name) | Node::Channel(name) | Node::Function(name) => name,
+        }
+    }
+}
+
+/// Output format of a [Graph]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Graph {
+    pub io: BTreeSet<String>,
+    pub channels: BTreeSet<String>,
+    pub functions: BTreeSet<String>,
+
+    /// Schedules by name, each with the schedules it switches to
+    pub schedules: BTreeMap<String, BTreeSet<String>>,
+
+    /// Edges of the dataflow, from source to destination, between known nodes only
+    pub dataflow: BTreeSet<(Node, Node)>,
+
+    /// Functions invoked by any schedule
+    pub scheduled: BTreeSet<String>,
+}
+
+impl Graph {
+    /// Channels which are neither produced by a function nor pulled from an IO driver
+    pub fn unproduced_channels(&self) -> impl Iterator<Item = &str> {
+        self.channels
+            .iter()
+            .filter(|channel| !self.dataflow.iter().any(|(_, to)| is_channel(to, channel)))
+            .map(String::as_str)
+    }
+
+    /// Channels which are neither consumed by a function nor pushed to an IO driver
+    pub fn unconsumed_channels(&self) -> impl Iterator<Item = &str> {
+        self.channels
+            .iter()
+            .filter(|channel| {
+                !self
+                    .dataflow
+                    .iter()
+                    .any(|(from, _)| is_channel(from, channel))
+            })
+            .map(String::as_str)
+    }
+
+    /// Functions which are not invoked by any schedule
+    pub fn unscheduled_functions(&self) -> impl Iterator<Item = &str> {
+        self.functions
+            .difference(&self.scheduled)
+            .map(String::as_str)
+    }
+
+    /// The initial schedule, i.e. the first one by name
+    pub fn initial_schedule(&self) -> Option<&str> {
+        self.schedules.keys().next().map(String::as_str)
+    }
+
+    /// Why `node` is highlighted, if it is
+    fn finding(&self, node: &Node) -> Option<&'static str> {
+        match node {
+            Node::Channel(name) => {
+                let produced = !self.unproduced_channels().any(|c| c == name);
+                let consumed = !self.unconsumed_channels().any(|c| c == name);
+                match (produced, consumed) {
+                    (false, false) => Some("no producer, no consumer"),
+                    (false, true) => Some("no producer"),
+                    (true, false) => Some("no consumer"),
+                    (true, true) => None,
+                }
+            }
+            Node::Function(name) if !self.scheduled.contains(name) => Some("never scheduled"),
+            _ => None,
+        }
+    }
+
+    /// Switches between schedules, leaving out those to unknown schedules
+    fn switches(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.schedules.iter().flat_map(move |(from, targets)| {
+            targets
+                .iter()
+                .filter(|to| self.schedules.contains_key(*to))
+                .map(move |to| (from.as_str(), to.as_str()))
+        })
+    }
+
+    fn nodes(&self) -> impl Iterator<Item = Node> + '_ {
+        let io = self.io.iter().cloned().map(Node::Io);
+        let channels = self.channels.iter().cloned().map(Node::Channel);
+        let functions = self.functions.iter().cloned().map(Node::Function);
+        io.chain(channels).chain(functions)
+    }
+
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::Mermaid => self.to_mermaid(),
+        }
+    }
+
+    /// Render as Graphviz DOT, with the dataflow and the mode graph as clusters
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph blueprint {\n    rankdir=LR;\n\n");
+
+        dot.push_str("    subgraph cluster_dataflow {\n        label=\"Dataflow\";\n");
+        for node in self.nodes() {
+            let shape = match node {
+                Node::Io(_) => "box3d",
+                Node::Channel(_) => "cylinder",
+                Node::Function(_) => "component",
+            };
+            let mut attrs = format!("label={}, shape={shape}", dot_quote(node.name()));
+            if let Some(finding) = self.finding(&node) {
+                let label = format!("{}\n({finding})", node.name());
+                attrs = format!(
+                    "label={}, shape={shape}, color=red, fontcolor=red, style=dashed",
+                    dot_quote(&label)
+                );
+            }
+            let _ = writeln!(dot, "        {} [{attrs}];", dot_id(&node));
+        }
+        for (from, to) in &self.dataflow {
+            let _ = writeln!(dot, "        {} -> {};", dot_id(from), dot_id(to));
+        }
+        dot.push_str("    }\n\n");
+
+        dot.push_str("    subgraph cluster_modes {\n        label=\"Schedules\";\n");
+        if let Some(initial) = self.initial_schedule() {
+            dot.push_str("        start [shape=point];\n");
+            let _ = writeln!(
+                dot,
+                "        start -> {};",
+                dot_quote(&schedule_id(initial))
+            );
+        }
+        for name in self.schedules.keys() {
+            let id = dot_quote(&schedule_id(name));
+            let _ = writeln!(dot, "        {id} [label={}, shape=box];", dot_quote(name));
+        }
+        for (from, to) in self.switches() {
+            let from = dot_quote(&schedule_id(from));
+            let _ = writeln!(dot, "        {from} -> {};", dot_quote(&schedule_id(to)));
+        }
+        dot.push_str("    }\n}\n");
+
+        dot
+    }
+
+    /// Render as Mermaid flowchart, with the dataflow and the mode graph as subgraphs
+    pub fn to_mermaid(&self) -> String {
+        let mut ids = BTreeMap::new();
+        for (idx, node) in self.nodes().enumerate() {
+            ids.insert(node, format!("n{idx}"));
+        }
+        let schedule_ids: BTreeMap<_, _> = self
+            .schedules
+            .keys()
+            .enumerate()
+            .map(|(idx, name)| (name.as_str(), format!("s{idx}")))
+            .collect();
+
+        let mut mermaid = String::from("flowchart LR\n");
+        mermaid.push_str("    classDef finding stroke:#d00,color:#d00,stroke-dasharray:4\n\n");
+
+        mermaid.push_str("    subgraph dataflow [Dataflow]\n");
+        for (node, id) in &ids {
+            let mut label = node.name().to_owned();
+            if let Some(finding) = self.finding(node) {
+                label = format!("{label}<br>({finding})");
+            }
+            let label = mermaid_quote(&label);
+            let shape = match node {
+                Node::Io(_) => format!("[/{label}/]"),
+                Node::Channel(_) => format!("[({label})]"),
+                Node::Function(_) => format!("[[{label}]]"),
+            };
+            let _ = writeln!(mermaid, "        {id}{shape}");
+        }
+        for (from, to) in &self.dataflow {
+            let _ = writeln!(mermaid, "        {} --> {}", ids[from], ids[to]);
+        }
+        mermaid.push_str("    end\n\n");
+
+        mermaid.push_str("    subgraph modes [Schedules]\n");
+        if let Some(initial) = self.initial_schedule() {
+            let _ = writeln!(
+                mermaid,
+                "        start((start)) --> {}",
+                schedule_ids[initial]
+            );
+        }
+        for (name, id) in &schedule_ids {
+            let _ = writeln!(mermaid, "        {id}[{}]", mermaid_quote(name));
+        }
+        for (from, to) in self.switches() {
+            let _ = writeln!(
+                mermaid,
+                "        {} --> {}",
+                schedule_ids[from], schedule_ids[to]
+            );
+        }
+        mermaid.push_str("    end\n");
+
+        let findings: Vec<_> = ids
+            .iter()
+            .filter(|(node, _)| self.finding(node).is_some())
+            .map(|(_, id)| id.as_str())
+            .collect();
+        if !findings.is_empty() {
+            let _ = writeln!(mermaid, "\n    class {} finding", findings.join(","));
+        }
+
+        mermaid
+    }
+}
+
+fn is_channel(node: &Node, name: &str) -> bool {
+    matches!(node, Node::Channel(channel) if channel == name)
+}
+
+fn dot_id(node: &Node) -> String {
+    let kind = match node {
+        Node::Io(_) => "io",
+        Node::Channel(_) => "channel",
+        Node::Function(_) => "function",
+    };
+    dot_quote(&format!("{kind}:{}", node.name()))
+}
+
+fn schedule_id(name: &str) -> String {
+    format!("schedule:{name}")
+}
+
+fn dot_quote(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{escaped}\"")
+}
+
+fn mermaid_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "#quot;"))
+}
@@ -0,0 +1,415 @@
+//! Self-contained kernel images, as built by `lwsk build`
+//!
+//! An image bundles the Wasm modules of all functions with the channels, IO bindings and
+//! schedules of a validated blueprint, with all names already resolved to indices. It is encoded
+//! with postcard, and decoding borrows the Wasm modules and names from the encoded bytes instead
+//! of copying them, so that a target without file system can load an image linked into its
+//! binary.
+//!
+//! The configuration of each IO binding is kept as CBOR encoded blueprint, as drivers are specific
+//! to the platform. Wasm modules an IO binding refers to, like the model of a simulated plant, are
+//! bundled along with it. Without `std`, the caller creates the drivers via
+//! [Image::to_kernel_config_with_io].
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::io::{ErrorPolicy, IoBinding, IoDriver};
+use crate::schedule::{Schedule, ScheduleEntry};
+use crate::{Channel, Function, KernelConfig, LwskError, LwskResult};
+
+/// First bytes of every image
+pub const MAGIC: [u8; 4] = *b"LWSK";
+
+/// Version of the image layout, incremented on incompatible changes
+pub const IMAGE_VERSION: u32 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Image<'a> {
+    pub magic: [u8; 4],
+    pub version: u32,
+
+    #[serde(borrow)]
+    pub channels: Vec<ChannelImage<'a>>,
+
+    #[serde(borrow)]
+    pub functions: Vec<FunctionImage<'a>>,
+
+    #[serde(borrow)]
+    pub io: Vec<IoImage<'a>>,
+
+    #[serde(borrow)]
+    pub schedules: Vec<ScheduleImage<'a>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelImage<'a> {
+    pub name: &'a str,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FunctionImage<'a> {
+    pub name: &'a str,
+
+    /// The Wasm module
+    #[serde(serialize_with = "serialize_bytes")]
+    pub wasm: &'a [u8],
+
+    /// Index of the consumed channel, if any
+    pub consumes: Option<u32>,
+
+    /// Index of the produced channel, if any
+    pub produces: Option<u32>,
+
+    pub fuel_per_call: u64,
+
+    /// Static configuration, if any
+    #[serde(borrow)]
+    pub config: Option<StaticConfigImage<'a>>,
+}
+
+/// A [StaticConfig](crate::StaticConfig), already serialized according to its layout
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StaticConfigImage<'a> {
+    pub global: &'a str,
+
+    #[serde(serialize_with = "serialize_bytes")]
+    pub data: &'a [u8],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IoImage<'a> {
+    pub name: &'a str,
+
+    /// The binding's blueprint, CBOR encoded
+    #[serde(serialize_with = "serialize_bytes")]
+    pub config: &'a [u8],
+
+    pub policy: ErrorPolicy,
+
+    /// Wasm modules the binding refers to by path
+    #[serde(borrow)]
+    pub modules: Vec<ModuleImage<'a>>,
+}
+
+/// A Wasm module bundled in place of the file it was read from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleImage<'a> {
+    /// Path of the module as given in the blueprint
+    pub path: &'a str,
+
+    #[serde(serialize_with = "serialize_bytes")]
+    pub wasm: &'a [u8],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduleImage<'a> {
+    pub name: &'a str,
+    pub entries: Vec<EntryImage>,
+}
+
+/// A [ScheduleEntry], with durations in nanoseconds
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntryImage {
+    FunctionInvocation(u32),
+    SporadicInvocation(u32),
+    IoIn {
+        from_io_idx: u32,
+        to_channel_idx: u32,
+    },
+    IoOut {
+        from_channel_idx: u32,
+        to_io_idx: u32,
+    },
+    Wait(u64),
+    AwaitIo {
+        io_idx: u32,
+        timeout_ns: u64,
+    },
+    SwitchSchedule(u32),
+}
+
+fn serialize_bytes<S: serde::Serializer>(bytes: &&[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bytes(bytes)
+}
+
+impl From<&ScheduleEntry> for EntryImage {
+    fn from(entry: &ScheduleEntry) -> Self {
+        match *entry {
+            ScheduleEntry::FunctionInvocation(idx) => Self::FunctionInvocation(idx as u32),
+            ScheduleEntry::SporadicInvocation(idx) => Self::SporadicInvocation(idx as u32),
+            ScheduleEntry::IoIn {
+                from_io_idx,
+                to_channel_idx,
+            } => Self::IoIn {
+                from_io_idx: from_io_idx as u32,
+                to_channel_idx: to_channel_idx as u32,
+            },
+            ScheduleEntry::IoOut {
+                from_channel_idx,
+                to_io_idx,
+            } => Self::IoOut {
+                from_channel_idx: from_channel_idx as u32,
+                to_io_idx: to_io_idx as u32,
+            },
+            ScheduleEntry::Wait(duration) => Self::Wait(duration.as_nanos() as u64),
+            ScheduleEntry::AwaitIo { io_idx, timeout } => Self::AwaitIo {
+                io_idx: io_idx as u32,
+                timeout_ns: timeout.as_nanos() as u64,
+            },
+            ScheduleEntry::SwitchSchedule(idx) => Self::SwitchSchedule(idx as u32),
+        }
+    }
+}
+
+impl From<&EntryImage> for ScheduleEntry {
+    fn from(entry: &EntryImage) -> Self {
+        match *entry {
+            EntryImage::FunctionInvocation(idx) => Self::FunctionInvocation(idx as usize),
+            EntryImage::SporadicInvocation(idx) => Self::SporadicInvocation(idx as usize),
+            EntryImage::IoIn {
+                from_io_idx,
+                to_channel_idx,
+            } => Self::IoIn {
+                from_io_idx: from_io_idx as usize,
+                to_channel_idx: to_channel_idx as usize,
+            },
+            EntryImage::IoOut {
+                from_channel_idx,
+                to_io_idx,
+            } => Self::IoOut {
+                from_channel_idx: from_channel_idx as usize,
+                to_io_idx: to_io_idx as usize,
+            },
+            EntryImage::Wait(ns) => Self::Wait(Duration::from_nanos(ns)),
+            EntryImage::AwaitIo { io_idx, timeout_ns } => Self::AwaitIo {
+                io_idx: io_idx as usize,
+                timeout: Duration::from_nanos(timeout_ns),
+            },
+            EntryImage::SwitchSchedule(idx) => Self::SwitchSchedule(idx as usize),
+        }
+    }
+}
+
+impl<'a> Image<'a> {
+    /// Whether `bytes` start like an image
+    pub fn is_image(bytes: &[u8]) -> bool {
+        bytes.starts_with(&MAGIC)
+    }
+
+    /// Decode an image, borrowing from `bytes`
+    pub fn decode(bytes: &'a [u8]) -> LwskResult<Self> {
+        let image: Self = postcard::from_bytes(bytes).map_err(|e| {
+            error!("could not decode image: {e}");
+            LwskError::InvalidImage
+        })?;
+        if image.magic != MAGIC || image.version != IMAGE_VERSION {
+            error!(
+                "image has version {}, expected {IMAGE_VERSION}",
+                image.version
+            );
+            return Err(LwskError::InvalidImage);
+        }
+        Ok(image)
+    }
+
+    /// Encode this image
+    #[cfg(feature = "std")]
+    pub fn encode(&self) -> LwskResult<Vec<u8>> {
+        postcard::to_allocvec(self).map_err(|e| {
+            error!("could not encode image: {e}");
+            LwskError::InvalidImage
+        })
+    }
+
+    /// Derive a validated [KernelConfig], using `make_io` to create the driver for each IO binding
+    ///
+    /// `make_io` is called with the index and the image of each IO binding. As an image may come
+    /// from anywhere, the indices in it are checked by [KernelConfig::validate], an image failing
+    /// the check is an [LwskError::InvalidImage].
+    pub fn to_kernel_config_with_io<F>(&self, mut make_io: F) -> LwskResult<KernelConfig>
+    where
+        F: FnMut(usize, &IoImage<'a>) -> LwskResult<Box<dyn IoDriver>>,
+    {
+        let channels = self
+            .channels
+            .iter()
+            .map(|channel| Channel {
+                name: channel.name.into(),
+                buf: vec![0u8; channel.size as usize],
+                valid: true,
+                timestamp: None,
+                generation: 0,
+            })
+            .collect();
+
+        let mut functions = Vec::with_capacity(self.functions.len());
+        for function in &self.functions {
+            let mut f = Function::from_bytes(function.name, function.wasm)?;
+            f.consumes = function.consumes.map(|idx| idx as usize);
+            f.produces = function.produces.map(|idx| idx as usize);
+            f.fuel_per_call = function.fuel_per_call;
+            if let Some(config) = &function.config {
+                f.configure(config.global, config.data)?;
+            }
+            functions.push(f);
+        }
+
+        let mut io = Vec::with_capacity(self.io.len());
+        for (idx, binding) in self.io.iter().enumerate() {
+            let driver = make_io(idx, binding)?;
+            io.push(IoBinding::new(binding.name, driver, binding.policy));
+        }
+
+        let mut schedules = Vec::with_capacity(self.schedules.len());
+        for schedule in &self.schedules {
+            let entries: Vec<ScheduleEntry> = schedule.entries.iter().map(Into::into).collect();
+            schedules.push(Schedule::new(schedule.name.into(), entries)?);
+        }
+
+        let kconfig = KernelConfig {
+            channels,
+            functions,
+            schedules,
+            io,
+            current_schedule_idx: 0,
+            health_events: Vec::new(),
+        };
+        kconfig.validate().map_err(|e| {
+            error!("image is inconsistent: {e}");
+            LwskError::InvalidImage
+        })?;
+        Ok(kconfig)
+    }
+
+    /// Derive a [KernelConfig], creating the IO drivers from their blueprints, looking up those
+    /// which are not built in from `registry`
+    #[cfg(feature = "std")]
+    pub fn to_kernel_config(
+        &self,
+        registry: &crate::io::registry::DriverRegistry,
+    ) -> LwskResult<KernelConfig> {
+        use crate::blueprint::{IoBindingBp, IoContext};
+
+        let mut ctx = IoContext::new(registry);
+        self.to_kernel_config_with_io(|_, binding| {
+            let bp: IoBindingBp = minicbor_serde::from_slice(binding.config).map_err(|e| {
+                error!(
+                    "could not decode IO binding {:?} of image: {e}",
+                    binding.name
+                );
+                LwskError::InvalidImage
+            })?;
+            for module in &binding.modules {
+                ctx.bundle(module.path, module.wasm);
+            }
+            bp.to_driver(&mut ctx)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::memory::Loopback;
+
+    fn image(entries: Vec<EntryImage>) -> Image<'static> {
+        Image {
+            magic: MAGIC,
+            version: IMAGE_VERSION,
+            channels: vec![ChannelImage { name: "c", size: 2 }],
+            functions: Vec::new(),
+            io: vec![IoImage {
+                name: "loop",
+                config: &[],
+                policy: ErrorPolicy::default(),
+                modules: Vec::new(),
+            }],
+            schedules: vec![ScheduleImage {
+                name: "main",
+                entries,
+            }],
+        }
+    }
+
+    fn kernel(image: &Image) -> LwskResult<KernelConfig> {
+        image.to_kernel_config_with_io(|_, _| Ok(Box::new(Loopback::new(None))))
+    }
+
+    const ENTRIES: [EntryImage; 3] = [
+        EntryImage::IoOut {
+            from_channel_idx: 0,
+            to_io_idx: 0,
+        },
+        EntryImage::IoIn {
+            from_io_idx: 0,
+            to_channel_idx: 0,
+        },
+        EntryImage::Wait(1000),
+    ];
+
+    #[test]
+    fn images_round_trip() {
+        let image = image(ENTRIES.to_vec());
+        let bytes = image.encode().unwrap();
+        assert!(Image::is_image(&bytes));
+        assert_eq!(Image::decode(&bytes).unwrap(), image);
+
+        let kconfig = kernel(&Image::decode(&bytes).unwrap()).unwrap();
+        assert_eq!(kconfig.channels[0].name, "c");
+        assert_eq!(kconfig.io[0].name, "loop");
+        assert_eq!(
+            kconfig.schedules[0].sequence[2],
+            ScheduleEntry::Wait(Duration::from_micros(1))
+        );
+    }
+
+    #[test]
+    fn invalid_images_are_rejected() {
+        let bytes = image(ENTRIES.to_vec()).encode().unwrap();
+
+        let mut corrupted = bytes.clone();
+        corrupted[0] ^= 1;
+        assert!(matches!(
+            Image::decode(&corrupted),
+            Err(LwskError::InvalidImage)
+        ));
+        assert!(matches!(
+            Image::decode(&bytes[..bytes.len() - 1]),
+            Err(LwskError::InvalidImage)
+        ));
+
+        let mut newer = image(ENTRIES.to_vec());
+        newer.version += 1;
+        assert!(matches!(
+            Image::decode(&newer.encode().unwrap()),
+            Err(LwskError::InvalidImage)
+        ));
+
+        for entry in [
+            EntryImage::IoIn {
+                from_io_idx: 1,
+                to_channel_idx: 0,
+            },
+            EntryImage::IoOut {
+                from_channel_idx: 1,
+                to_io_idx: 0,
+            },
+            EntryImage::FunctionInvocation(0),
+            EntryImage::SwitchSchedule(1),
+        ] {
+            assert!(
+                matches!(
+                    kernel(&image(vec![entry.clone()])),
+                    Err(LwskError::InvalidImage)
+                ),
+                "{entry:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,474 @@
+//! CCSDS Space Packet driver layered over a byte transport
+//!
+//! Channel contents are carried as user data of space packets (CCSDS 133.0-B). Several drivers
+//! may share one [Link], in which case every incoming packet is routed to the driver with the
+//! matching APID. Gaps in the sequence count of incoming packets are detected and logged.
+//!
+//! Optionally, packets carry a secondary header with a CCSDS Unsegmented time Code (CUC) without
+//! preamble field, counting from the Unix epoch.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+use super::{ConnectionState, IoDriver};
+use crate::LwskError;
+
+/// Length of the primary header
+pub const PRIMARY_HEADER_LEN: usize = 6;
+
+/// Largest possible packet, limited by the 16 bit packet data length field
+pub const MAX_PACKET_LEN: usize = PRIMARY_HEADER_LEN + u16::MAX as usize + 1;
+
+/// APID reserved for idle packets
+pub const IDLE_APID: u16 = 0x7ff;
+
+/// Sequence flags of an unsegmented packet
+const SEQUENCE_FLAGS_UNSEGMENTED: u8 = 0b11;
+
+/// Modulus of the sequence count
+const SEQUENCE_COUNT_MODULUS: u16 = 1 << 14;
+
+/// Maximum number of pulls from the transport per receive, like for MAVLink
+///
+/// Transports which always yield data, like a constant, would otherwise never be drained.
+const MAX_TRANSPORT_PULLS: usize = 16;
+
+/// Format of the time code in the secondary header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeCode {
+    /// Number of octets of whole seconds, 1 to 4
+    pub coarse: usize,
+
+    /// Number of octets of the binary fraction of a second, 0 to 3
+    pub fine: usize,
+}
+
+/// Primary header of a space packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrimaryHeader {
+    /// Whether this is a telecommand (as opposed to telemetry) packet
+    pub telecommand: bool,
+    pub secondary_header: bool,
+    pub apid: u16,
+    pub sequence_flags: u8,
+    pub sequence_count: u16,
+
+    /// Length of the packet data field in byte
+    pub data_len: usize,
+}
+
+/// A space packet as received by a [Link]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub header: PrimaryHeader,
+
+    /// The packet data field, including the secondary header if any
+    pub data: Vec<u8>,
+}
+
+/// A transport shared by all drivers using the same APIDs space
+pub struct Link {
+    transport: Box<dyn IoDriver>,
+
+    /// Latest packet received per APID, not yet pulled
+    inbox: HashMap<u16, Packet>,
+
+    /// Sequence count of the last packet received per APID
+    rx_count: HashMap<u16, u16>,
+
+    /// Sequence count of the next packet to send per APID
+    tx_count: HashMap<u16, u16>,
+
+    /// Received bytes not yet parsed
+    rx_buf: Vec<u8>,
+
+    /// Buffer to receive data from the transport into
+    chunk: Vec<u8>,
+}
+
+pub struct Ccsds {
+    link: Rc<RefCell<Link>>,
+    apid: u16,
+    telecommand: bool,
+    time_code: Option<TimeCode>,
+}
+
+impl PrimaryHeader {
+    pub fn encode(&self) -> [u8; PRIMARY_HEADER_LEN] {
+        let id = ((self.telecommand as u16) << 12)
+            | ((self.secondary_header as u16) << 11)
+            | (self.apid & IDLE_APID);
+        let sequence = ((self.sequence_flags as u16) << 14) | (self.sequence_count & 0x3fff);
+        let len = (self.data_len - 1) as u16;
+
+        let mut header = [0u8; PRIMARY_HEADER_LEN];
+        header[0..2].copy_from_slice(&id.to_be_bytes());
+        header[2..4].copy_from_slice(&sequence.to_be_bytes());
+        header[4..6].copy_from_slice(&len.to_be_bytes());
+        header
+    }
+
+    /// Decode a primary header, returns [None] if the version is not supported
+    pub fn decode(header: &[u8; PRIMARY_HEADER_LEN]) -> Option<Self> {
+        let id = u16::from_be_bytes([header[0], header[1]]);
+        let sequence = u16::from_be_bytes([header[2], header[3]]);
+        let len = u16::from_be_bytes([header[4], header[5]]);
+
+        // only version 1, encoded as 0b000, is defined
+        if id >> 13 != 0 {
+            return None;
+        }
+
+        Some(Self {
+            telecommand: id & (1 << 12) != 0,
+            secondary_header: id & (1 << 11) != 0,
+            apid: id & IDLE_APID,
+            sequence_flags: (sequence >> 14) as u8,
+            sequence_count: sequence & 0x3fff,
+            data_len: len as usize + 1,
+        })
+    }
+}
+
+impl TimeCode {
+    pub fn new(coarse: usize, fine: usize) -> Result<Self, LwskError> {
+        if !(1..=4).contains(&coarse) || fine > 3 {
+            log::error!("unsupported CUC time code with {coarse} coarse and {fine} fine octets");
+            return Err(LwskError::IoChannelCreationError);
+        }
+        Ok(Self { coarse, fine })
+    }
+
+    /// Length of the encoded time code in byte
+    pub fn encoded_len(&self) -> usize {
+        self.coarse + self.fine
+    }
+
+    /// Encode `time` since the epoch, dropping overflowing seconds
+    pub fn encode(&self, time: Duration) -> Vec<u8> {
+        let seconds = time.as_secs().to_be_bytes();
+        let fraction = ((time.subsec_nanos() as u64) << 32) / 1_000_000_000;
+        let fraction = (fraction as u32).to_be_bytes();
+
+        let mut code = Vec::with_capacity(self.encoded_len());
+        code.extend_from_slice(&seconds[seconds.len() - self.coarse..]);
+        code.extend_from_slice(&fraction[..self.fine]);
+        code
+    }
+
+    /// Decode a time code into the time since the epoch
+    pub fn decode(&self, code: &[u8]) -> Duration {
+        let seconds = code[..self.coarse]
+            .iter()
+            .fold(0u64, |acc, b| (acc << 8) | *b as u64);
+        let fraction = code[self.coarse..self.encoded_len()]
+            .iter()
+            .chain(core::iter::repeat(&0))
+            .take(4)
+            .fold(0u64, |acc, b| (acc << 8) | *b as u64);
+        Duration::new(seconds, ((fraction * 1_000_000_000) >> 32) as u32)
+    }
+}
+
+impl Link {
+    pub fn new(transport: Box<dyn IoDriver>) -> Self {
+        Self {
+            transport,
+            inbox: HashMap::new(),
+            rx_count: HashMap::new(),
+            tx_count: HashMap::new(),
+            rx_buf: Vec::new(),
+            chunk: vec![0u8; MAX_PACKET_LEN],
+        }
+    }
+
+    /// Receive what is available from the transport, up to a bound, and sort it into the inbox
+    fn receive(&mut self) -> Result<(), LwskError> {
+        for _ in 0..MAX_TRANSPORT_PULLS {
+            let Some(n) = self.transport.pull(&mut self.chunk)? else {
+                break;
+            };
+            self.rx_buf.extend_from_slice(&self.chunk[..n]);
+        }
+
+        while let Some(packet) = self.parse_packet() {
+            let apid = packet.header.apid;
+            if apid == IDLE_APID {
+                continue;
+            }
+            if packet.header.sequence_flags != SEQUENCE_FLAGS_UNSEGMENTED {
+                log::warn!("dropping segmented packet of APID {apid}");
+                continue;
+            }
+
+            let count = packet.header.sequence_count;
+            if let Some(last) = self.rx_count.insert(apid, count) {
+                let gap = sequence_gap(last, count);
+                if gap != 0 {
+                    log::warn!("lost {gap} packets of APID {apid}");
+                }
+            }
+
+            if self.inbox.insert(apid, packet).is_some() {
+                log::debug!("overwriting unread packet of APID {apid}");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Try to parse the next packet from the receive buffer
+    fn parse_packet(&mut self) -> Option<Packet> {
+        loop {
+            let header: &[u8; PRIMARY_HEADER_LEN] =
+                self.rx_buf.get(..PRIMARY_HEADER_LEN)?.try_into().ok()?;
+            let Some(header) = PrimaryHeader::decode(header) else {
+                log::warn!("dropping byte not starting a valid space packet");
+                self.rx_buf.drain(..1);
+                continue;
+            };
+
+            let packet_len = PRIMARY_HEADER_LEN + header.data_len;
+            if self.rx_buf.len() < packet_len {
+                return None;
+            }
+
+            let data = self.rx_buf[PRIMARY_HEADER_LEN..packet_len].to_vec();
+            self.rx_buf.drain(..packet_len);
+            return Some(Packet { header, data });
+        }
+    }
+
+    /// Send `data` as packet data field, assigning the next sequence count of the APID
+    fn send(&mut self, mut header: PrimaryHeader, data: &[u8]) -> Result<(), LwskError> {
+        let count = self.tx_count.entry(header.apid).or_default();
+        header.sequence_count = *count;
+        *count = (*count + 1) % SEQUENCE_COUNT_MODULUS;
+
+        let mut packet = Vec::with_capacity(PRIMARY_HEADER_LEN + data.len());
+        packet.extend_from_slice(&header.encode());
+        packet.extend_from_slice(data);
+        self.transport.push(&packet)
+    }
+}
+
+/// Number of packets lost between the sequence counts `last` and `count`
+fn sequence_gap(last: u16, count: u16) -> u16 {
+    count.wrapping_sub(last).wrapping_sub(1) % SEQUENCE_COUNT_MODULUS
+}
+
+impl Ccsds {
+    pub fn new(
+        link: Rc<RefCell<Link>>,
+        apid: u16,
+        telecommand: bool,
+        time_code: Option<TimeCode>,
+    ) -> Result<Self, LwskError> {
+        if apid >= IDLE_APID {
+            log::error!("APID {apid} is not available for user data");
+            return Err(LwskError::IoChannelCreationError);
+        }
+        Ok(Self {
+            link,
+            apid,
+            telecommand,
+            time_code,
+        })
+    }
+}
+
+impl IoDriver for Ccsds {
+    fn connection(&self) -> ConnectionState {
+        self.link.borrow().transport.connection()
+    }
+
+    fn rx_timestamp(&self) -> Option<std::time::Duration> {
+        self.link.borrow().transport.rx_timestamp()
+    }
+
+    fn readiness_fd(&self) -> Option<i32> {
+        self.link.borrow().transport.readiness_fd()
+    }
+
+    fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+        let mut link = self.link.borrow_mut();
+        link.receive()?;
+
+        let Some(packet) = link.inbox.remove(&self.apid) else {
+            log::debug!("no new space packet of APID {}", self.apid);
+            return Ok(None);
+        };
+
+        let mut user_data = &packet.data[..];
+        if let (true, Some(time_code)) = (packet.header.secondary_header, self.time_code) {
+            let Some((code, rest)) = user_data.split_at_checked(time_code.encoded_len()) else {
+                log::error!("space packet of APID {} is too short", self.apid);
+                return Err(LwskError::CodecError);
+            };
+            log::trace!(
+                "space packet of APID {} was sent at {:?}",
+                self.apid,
+                time_code.decode(code)
+            );
+            user_data = rest;
+        }
+
+        if user_data.len() != buf.len() {
+            log::error!(
+                "space packet of APID {} carries {} bytes, but the channel has {} bytes",
+                self.apid,
+                user_data.len(),
+                buf.len()
+            );
+            return Err(LwskError::CodecError);
+        }
+        buf.copy_from_slice(user_data);
+        log::debug!("received {} bytes via APID {}", buf.len(), self.apid);
+
+        Ok(Some(buf.len()))
+    }
+
+    fn push(&mut self, buf: &[u8]) -> Result<(), LwskError> {
+        let mut data = Vec::with_capacity(buf.len() + 8);
+        if let Some(time_code) = self.time_code {
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            data.extend_from_slice(&time_code.encode(now));
+        }
+        data.extend_from_slice(buf);
+
+        if data.is_empty() || data.len() > u16::MAX as usize + 1 {
+            log::error!("{} bytes do not fit into a space packet", data.len());
+            return Err(LwskError::BufferTooSmall {
+                expected: data.len(),
+                got: u16::MAX as usize + 1,
+            });
+        }
+
+        let header = PrimaryHeader {
+            telecommand: self.telecommand,
+            secondary_header: self.time_code.is_some(),
+            apid: self.apid,
+            sequence_flags: SEQUENCE_FLAGS_UNSEGMENTED,
+            sequence_count: 0,
+            data_len: data.len(),
+        };
+        self.link.borrow_mut().send(header, &data)?;
+        log::debug!("sent {} bytes via APID {}", buf.len(), self.apid);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::memory::Loopback;
+
+    fn link() -> Rc<RefCell<Link>> {
+        Rc::new(RefCell::new(Link::new(Box::new(Loopback::new(None)))))
+    }
+
+    /// Take the next packet sent over `link`, without routing it
+    fn sent(link: &Rc<RefCell<Link>>) -> Vec<u8> {
+        let mut packet = vec![0u8; MAX_PACKET_LEN];
+        let n = link
+            .borrow_mut()
+            .transport
+            .pull(&mut packet)
+            .unwrap()
+            .unwrap();
+        packet.truncate(n);
+        packet
+    }
+
+    #[test]
+    fn primary_header_round_trips() {
+        let header = PrimaryHeader {
+            telecommand: true,
+            secondary_header: true,
+            apid: 0x123,
+            sequence_flags: SEQUENCE_FLAGS_UNSEGMENTED,
+            sequence_count: 0x2345,
+            data_len: 0x100,
+        };
+        let encoded = header.encode();
+        assert_eq!(encoded, [0x19, 0x23, 0xe3, 0x45, 0x00, 0xff]);
+        assert_eq!(PrimaryHeader::decode(&encoded), Some(header));
+
+        // versions other than 1 are not supported
+        assert_eq!(PrimaryHeader::decode(&[0x20, 0, 0, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn packets_are_routed_by_apid() {
+        let link = link();
+        let mut a = Ccsds::new(link.clone(), 1, false, None).unwrap();
+        let mut b = Ccsds::new(link.clone(), 2, false, None).unwrap();
+        assert!(Ccsds::new(link.clone(), IDLE_APID, false, None).is_err());
+
+        a.push(&[1, 1]).unwrap();
+        b.push(&[2, 2]).unwrap();
+        let mut buf = [0u8; 2];
+        assert_eq!(b.pull(&mut buf).unwrap(), Some(2));
+        assert_eq!(buf, [2, 2]);
+        assert_eq!(a.pull(&mut buf).unwrap(), Some(2));
+        assert_eq!(buf, [1, 1]);
+        assert_eq!(a.pull(&mut buf).unwrap(), None);
+
+        // the user data must fill the channel exactly
+        a.push(&[1, 1, 1]).unwrap();
+        assert!(matches!(a.pull(&mut buf), Err(LwskError::CodecError)));
+    }
+
+    #[test]
+    fn sequence_counts_wrap_around() {
+        assert_eq!(sequence_gap(5, 6), 0);
+        assert_eq!(sequence_gap(5, 8), 2);
+        assert_eq!(sequence_gap(SEQUENCE_COUNT_MODULUS - 1, 0), 0);
+        assert_eq!(sequence_gap(SEQUENCE_COUNT_MODULUS - 2, 1), 2);
+        // a repeated count is taken for a full cycle of lost packets
+        assert_eq!(sequence_gap(5, 5), SEQUENCE_COUNT_MODULUS - 1);
+
+        let link = link();
+        let mut ccsds = Ccsds::new(link.clone(), 1, false, None).unwrap();
+        link.borrow_mut()
+            .tx_count
+            .insert(1, SEQUENCE_COUNT_MODULUS - 1);
+        for count in [SEQUENCE_COUNT_MODULUS - 1, 0] {
+            ccsds.push(&[0]).unwrap();
+            let packet = sent(&link);
+            let header = PrimaryHeader::decode(packet[..PRIMARY_HEADER_LEN].try_into().unwrap());
+            assert_eq!(header.unwrap().sequence_count, count);
+        }
+    }
+
+    #[test]
+    fn time_code_round_trips() {
+        let time = Duration::new(0x6543_2109, 500_000_000);
+        let full = TimeCode::new(4, 3).unwrap();
+        let code = full.encode(time);
+        assert_eq!(code, [0x65, 0x43, 0x21, 0x09, 0x80, 0x00, 0x00]);
+        assert_eq!(full.decode(&code), time);
+
+        // overflowing seconds are dropped, the fraction is truncated to its octets
+        let short = TimeCode::new(1, 1).unwrap();
+        let code = short.encode(Duration::new(0x1ff, 999_999_999));
+        assert_eq!(code, [0xff, 0xff]);
+        assert_eq!(short.decode(&code), Duration::new(0xff, 996_093_750));
+
+        assert!(TimeCode::new(0, 0).is_err());
+        assert!(TimeCode::new(4, 4).is_err());
+
+        // the time code is stripped from the user data
+        let link = link();
+        let mut ccsds = Ccsds::new(link.clone(), 1, true, Some(full)).unwrap();
+        ccsds.push(&[7]).unwrap();
+        let mut buf = [0u8; 1];
+        assert_eq!(ccsds.pull(&mut buf).unwrap(), Some(1));
+        assert_eq!(buf, [7]);
+    }
+}
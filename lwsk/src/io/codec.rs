@@ -0,0 +1,760 @@
+//! Codecs transforming between the wire representation of IO data and the channel layout
+//!
+//! Channels are laid out as packed little endian structs, as this is what a Wasm guest sees in its
+//! linear memory. A [Coded] driver wraps a transport-only [IoDriver] and passes all data through
+//! a chain of [Codec]s. The first codec of the chain is closest to the channel, the last one is
+//! closest to the wire.
+
+use super::{ConnectionState, IoDriver};
+use crate::LwskError;
+
+/// Size of the buffer to receive wire data into
+const WIRE_BUF_SIZE: usize = 65_536;
+
+/// A transformation between two representations of the same data
+pub trait Codec {
+    /// Transform `inner` data into its outer representation, closer to the wire
+    fn encode(&mut self, inner: &[u8], outer: &mut Vec<u8>) -> Result<(), LwskError>;
+
+    /// Transform `outer` data back into its inner representation, closer to the channel
+    fn decode(&mut self, outer: &[u8], inner: &mut Vec<u8>) -> Result<(), LwskError>;
+}
+
+/// Type of a scalar field in the channel layout
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+}
+
+/// A field of the channel layout
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Field {
+    /// Name of the field, used by self-describing wire formats
+    pub name: Option<String>,
+    pub ty: FieldType,
+}
+
+/// The value of a scalar field
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    U8(u8),
+    I8(i8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    U64(u64),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+/// Byte order of a packed struct on the wire
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+/// Checksum algorithms
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Checksum {
+    /// CRC-16/CCITT-FALSE, appended big endian
+    Crc16Ccitt,
+
+    /// CRC-32 as used by Ethernet and zlib, appended big endian
+    Crc32,
+}
+
+impl FieldType {
+    /// Size of this type in the channel layout
+    pub fn size(&self) -> usize {
+        match self {
+            FieldType::U8 | FieldType::I8 => 1,
+            FieldType::U16 | FieldType::I16 => 2,
+            FieldType::U32 | FieldType::I32 | FieldType::F32 => 4,
+            FieldType::U64 | FieldType::I64 | FieldType::F64 => 8,
+        }
+    }
+
+    /// Read a value of this type from `bytes` in the given byte order
+    pub fn read(&self, bytes: &[u8], endian: Endian) -> Value {
+        let mut raw = [0u8; 8];
+        let raw = &mut raw[..self.size()];
+        raw.copy_from_slice(&bytes[..self.size()]);
+        if endian == Endian::Big {
+            raw.reverse();
+        }
+
+        match self {
+            FieldType::U8 => Value::U8(raw[0]),
+            FieldType::I8 => Value::I8(raw[0] as i8),
+            FieldType::U16 => Value::U16(u16::from_le_bytes(raw.try_into().unwrap())),
+            FieldType::I16 => Value::I16(i16::from_le_bytes(raw.try_into().unwrap())),
+            FieldType::U32 => Value::U32(u32::from_le_bytes(raw.try_into().unwrap())),
+            FieldType::I32 => Value::I32(i32::from_le_bytes(raw.try_into().unwrap())),
+            FieldType::U64 => Value::U64(u64::from_le_bytes(raw.try_into().unwrap())),
+            FieldType::I64 => Value::I64(i64::from_le_bytes(raw.try_into().unwrap())),
+            FieldType::F32 => Value::F32(f32::from_le_bytes(raw.try_into().unwrap())),
+            FieldType::F64 => Value::F64(f64::from_le_bytes(raw.try_into().unwrap())),
+        }
+    }
+
+    /// Convert a number into a value of this type, failing if it is out of range
+    pub fn from_i128(&self, n: i128) -> Option<Value> {
+        Some(match self {
+            FieldType::U8 => Value::U8(n.try_into().ok()?),
+            FieldType::I8 => Value::I8(n.try_into().ok()?),
+            FieldType::U16 => Value::U16(n.try_into().ok()?),
+            FieldType::I16 => Value::I16(n.try_into().ok()?),
+            FieldType::U32 => Value::U32(n.try_into().ok()?),
+            FieldType::I32 => Value::I32(n.try_into().ok()?),
+            FieldType::U64 => Value::U64(n.try_into().ok()?),
+            FieldType::I64 => Value::I64(n.try_into().ok()?),
+            FieldType::F32 => Value::F32(n as f32),
+            FieldType::F64 => Value::F64(n as f64),
+        })
+    }
+
+    /// Convert a floating point number into a value of this type
+    ///
+    /// Integers are only accepted if `n` is integral and in range.
+    pub fn from_f64(&self, n: f64) -> Option<Value> {
+        match self {
+            FieldType::F32 => Some(Value::F32(n as f32)),
+            FieldType::F64 => Some(Value::F64(n)),
+            _ if n.fract() == 0.0 && n.abs() < 2f64.powi(64) => self.from_i128(n as i128),
+            _ => None,
+        }
+    }
+}
+
+impl Value {
+    /// Write this value to `bytes` in the given byte order
+    pub fn write(&self, bytes: &mut Vec<u8>, endian: Endian) {
+        let start = bytes.len();
+        match self {
+            Value::U8(v) => bytes.extend_from_slice(&v.to_le_bytes()),
+            Value::I8(v) => bytes.extend_from_slice(&v.to_le_bytes()),
+            Value::U16(v) => bytes.extend_from_slice(&v.to_le_bytes()),
+            Value::I16(v) => bytes.extend_from_slice(&v.to_le_bytes()),
+            Value::U32(v) => bytes.extend_from_slice(&v.to_le_bytes()),
+            Value::I32(v) => bytes.extend_from_slice(&v.to_le_bytes()),
+            Value::U64(v) => bytes.extend_from_slice(&v.to_le_bytes()),
+            Value::I64(v) => bytes.extend_from_slice(&v.to_le_bytes()),
+            Value::F32(v) => bytes.extend_from_slice(&v.to_le_bytes()),
+            Value::F64(v) => bytes.extend_from_slice(&v.to_le_bytes()),
+        }
+        if endian == Endian::Big {
+            bytes[start..].reverse();
+        }
+    }
+}
+
+impl serde::Serialize for Value {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::U8(v) => serializer.serialize_u8(*v),
+            Value::I8(v) => serializer.serialize_i8(*v),
+            Value::U16(v) => serializer.serialize_u16(*v),
+            Value::I16(v) => serializer.serialize_i16(*v),
+            Value::U32(v) => serializer.serialize_u32(*v),
+            Value::I32(v) => serializer.serialize_i32(*v),
+            Value::U64(v) => serializer.serialize_u64(*v),
+            Value::I64(v) => serializer.serialize_i64(*v),
+            Value::F32(v) => serializer.serialize_f32(*v),
+            Value::F64(v) => serializer.serialize_f64(*v),
+        }
+    }
+}
+
+/// Split channel contents into values according to `fields`
+pub fn read_fields(fields: &[Field], bytes: &[u8]) -> Result<Vec<Value>, LwskError> {
+    let size: usize = fields.iter().map(|f| f.ty.size()).sum();
+    if bytes.len() != size {
+        error!(
+            "expected {size} bytes for the channel layout, got {}",
+            bytes.len()
+        );
+        return Err(LwskError::CodecError);
+    }
+
+    let mut offset = 0;
+    Ok(fields
+        .iter()
+        .map(|field| {
+            let value = field.ty.read(&bytes[offset..], Endian::Little);
+            offset += field.ty.size();
+            value
+        })
+        .collect())
+}
+
+/// Packed struct in a given byte order
+pub struct Packed {
+    pub fields: Vec<Field>,
+    pub endian: Endian,
+}
+
+impl Codec for Packed {
+    fn encode(&mut self, inner: &[u8], outer: &mut Vec<u8>) -> Result<(), LwskError> {
+        for value in read_fields(&self.fields, inner)? {
+            value.write(outer, self.endian);
+        }
+        Ok(())
+    }
+
+    fn decode(&mut self, outer: &[u8], inner: &mut Vec<u8>) -> Result<(), LwskError> {
+        let size: usize = self.fields.iter().map(|f| f.ty.size()).sum();
+        if outer.len() != size {
+            error!("expected {size} bytes of packed data, got {}", outer.len());
+            return Err(LwskError::CodecError);
+        }
+
+        let mut offset = 0;
+        for field in &self.fields {
+            field
+                .ty
+                .read(&outer[offset..], self.endian)
+                .write(inner, Endian::Little);
+            offset += field.ty.size();
+        }
+        Ok(())
+    }
+}
+
+/// CBOR array of numbers
+pub struct Cbor {
+    pub fields: Vec<Field>,
+}
+
+impl Codec for Cbor {
+    fn encode(&mut self, inner: &[u8], outer: &mut Vec<u8>) -> Result<(), LwskError> {
+        let values = read_fields(&self.fields, inner)?;
+        let mut encoder = minicbor::Encoder::new(outer);
+        let encoded: Result<_, minicbor::encode::Error<_>> = (|| {
+            encoder.array(values.len() as u64)?;
+            for value in values {
+                match value {
+                    Value::U8(v) => encoder.u8(v)?,
+                    Value::I8(v) => encoder.i8(v)?,
+                    Value::U16(v) => encoder.u16(v)?,
+                    Value::I16(v) => encoder.i16(v)?,
+                    Value::U32(v) => encoder.u32(v)?,
+                    Value::I32(v) => encoder.i32(v)?,
+                    Value::U64(v) => encoder.u64(v)?,
+                    Value::I64(v) => encoder.i64(v)?,
+                    Value::F32(v) => encoder.f32(v)?,
+                    Value::F64(v) => encoder.f64(v)?,
+                };
+            }
+            Ok(())
+        })();
+        encoded.map_err(|e| {
+            error!("could not encode CBOR: {e}");
+            LwskError::CodecError
+        })
+    }
+
+    fn decode(&mut self, outer: &[u8], inner: &mut Vec<u8>) -> Result<(), LwskError> {
+        use minicbor::data::Type;
+
+        let mut decoder = minicbor::Decoder::new(outer);
+        let decoded: Result<_, minicbor::decode::Error> = (|| {
+            if decoder.array()? != Some(self.fields.len() as u64) {
+                return Err(minicbor::decode::Error::message("unexpected array length"));
+            }
+            for field in &self.fields {
+                let value = match decoder.datatype()? {
+                    Type::F32 => field.ty.from_f64(decoder.f32()? as f64),
+                    Type::F64 => field.ty.from_f64(decoder.f64()?),
+                    _ => field.ty.from_i128(decoder.int()?.into()),
+                };
+                value
+                    .ok_or_else(|| minicbor::decode::Error::message("value out of range"))?
+                    .write(inner, Endian::Little);
+            }
+            Ok(())
+        })();
+        decoded.map_err(|e| {
+            error!("could not decode CBOR: {e}");
+            LwskError::CodecError
+        })?;
+
+        if decoder.position() != outer.len() {
+            error!(
+                "{} trailing bytes after CBOR data",
+                outer.len() - decoder.position()
+            );
+            return Err(LwskError::CodecError);
+        }
+        Ok(())
+    }
+}
+
+/// Sequence of numbers in the postcard wire format
+pub struct Postcard {
+    pub fields: Vec<Field>,
+}
+
+impl Codec for Postcard {
+    fn encode(&mut self, inner: &[u8], outer: &mut Vec<u8>) -> Result<(), LwskError> {
+        for value in read_fields(&self.fields, inner)? {
+            let encoded = postcard::to_stdvec(&value).map_err(|e| {
+                error!("could not encode postcard: {e}");
+                LwskError::CodecError
+            })?;
+            outer.extend_from_slice(&encoded);
+        }
+        Ok(())
+    }
+
+    fn decode(&mut self, mut outer: &[u8], inner: &mut Vec<u8>) -> Result<(), LwskError> {
+        fn take<'a, T: serde::Deserialize<'a>>(bytes: &mut &'a [u8]) -> Result<T, postcard::Error> {
+            let (value, rest) = postcard::take_from_bytes(bytes)?;
+            *bytes = rest;
+            Ok(value)
+        }
+
+        let decoded: Result<_, postcard::Error> = (|| {
+            for field in &self.fields {
+                let value = match field.ty {
+                    FieldType::U8 => Value::U8(take(&mut outer)?),
+                    FieldType::I8 => Value::I8(take(&mut outer)?),
+                    FieldType::U16 => Value::U16(take(&mut outer)?),
+                    FieldType::I16 => Value::I16(take(&mut outer)?),
+                    FieldType::U32 => Value::U32(take(&mut outer)?),
+                    FieldType::I32 => Value::I32(take(&mut outer)?),
+                    FieldType::U64 => Value::U64(take(&mut outer)?),
+                    FieldType::I64 => Value::I64(take(&mut outer)?),
+                    FieldType::F32 => Value::F32(take(&mut outer)?),
+                    FieldType::F64 => Value::F64(take(&mut outer)?),
+                };
+                value.write(inner, Endian::Little);
+            }
+            Ok(())
+        })();
+        decoded.map_err(|e| {
+            error!("could not decode postcard: {e}");
+            LwskError::CodecError
+        })?;
+
+        if !outer.is_empty() {
+            error!("{} trailing bytes after postcard data", outer.len());
+            return Err(LwskError::CodecError);
+        }
+        Ok(())
+    }
+}
+
+/// JSON object if all fields are named, JSON array otherwise
+pub struct Json {
+    pub fields: Vec<Field>,
+}
+
+impl Codec for Json {
+    fn encode(&mut self, inner: &[u8], outer: &mut Vec<u8>) -> Result<(), LwskError> {
+        let values = read_fields(&self.fields, inner)?;
+        let names: Option<Vec<_>> = self.fields.iter().map(|f| f.name.as_ref()).collect();
+        let encoded = match names {
+            Some(names) => serde_json::to_writer(
+                outer,
+                &names
+                    .into_iter()
+                    .zip(values)
+                    .collect::<std::collections::BTreeMap<_, _>>(),
+            ),
+            None => serde_json::to_writer(outer, &values),
+        };
+        encoded.map_err(|e| {
+            error!("could not encode JSON: {e}");
+            LwskError::CodecError
+        })
+    }
+
+    fn decode(&mut self, outer: &[u8], inner: &mut Vec<u8>) -> Result<(), LwskError> {
+        use serde_json::Value as JsonValue;
+
+        let json: JsonValue = serde_json::from_slice(outer).map_err(|e| {
+            error!("could not decode JSON: {e}");
+            LwskError::CodecError
+        })?;
+
+        for (idx, field) in self.fields.iter().enumerate() {
+            let item = match (&field.name, &json) {
+                (Some(name), JsonValue::Object(object)) => object.get(name),
+                (_, JsonValue::Array(array)) => array.get(idx),
+                _ => None,
+            };
+            let value = match item {
+                Some(JsonValue::Number(n)) => match (n.as_i64(), n.as_u64(), n.as_f64()) {
+                    (Some(n), _, _) => field.ty.from_i128(n.into()),
+                    (_, Some(n), _) => field.ty.from_i128(n.into()),
+                    (_, _, Some(n)) => field.ty.from_f64(n),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let Some(value) = value else {
+                error!(
+                    "JSON lacks a valid value for field {idx} ({:?})",
+                    field.name
+                );
+                return Err(LwskError::CodecError);
+            };
+            value.write(inner, Endian::Little);
+        }
+        Ok(())
+    }
+}
+
+/// Append a checksum on encode, verify and strip it on decode
+pub struct Crc {
+    pub algorithm: Checksum,
+}
+
+impl Checksum {
+    /// Size of the checksum in byte
+    pub fn size(&self) -> usize {
+        match self {
+            Checksum::Crc16Ccitt => 2,
+            Checksum::Crc32 => 4,
+        }
+    }
+
+    /// Compute the checksum of `data`, big endian
+    pub fn compute(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Checksum::Crc16Ccitt => {
+                let mut crc: u16 = 0xffff;
+                for byte in data {
+                    crc ^= (*byte as u16) << 8;
+                    for _ in 0..8 {
+                        crc = if crc & 0x8000 != 0 {
+                            (crc << 1) ^ 0x1021
+                        } else {
+                            crc << 1
+                        };
+                    }
+                }
+                crc.to_be_bytes().to_vec()
+            }
+            Checksum::Crc32 => {
+                let mut crc: u32 = 0xffff_ffff;
+                for byte in data {
+                    crc ^= *byte as u32;
+                    for _ in 0..8 {
+                        crc = if crc & 1 != 0 {
+                            (crc >> 1) ^ 0xedb8_8320
+                        } else {
+                            crc >> 1
+                        };
+                    }
+                }
+                (!crc).to_be_bytes().to_vec()
+            }
+        }
+    }
+}
+
+impl Codec for Crc {
+    fn encode(&mut self, inner: &[u8], outer: &mut Vec<u8>) -> Result<(), LwskError> {
+        outer.extend_from_slice(inner);
+        outer.extend_from_slice(&self.algorithm.compute(inner));
+        Ok(())
+    }
+
+    fn decode(&mut self, outer: &[u8], inner: &mut Vec<u8>) -> Result<(), LwskError> {
+        let Some(split) = outer.len().checked_sub(self.algorithm.size()) else {
+            error!("data is too short to contain a checksum");
+            return Err(LwskError::CodecError);
+        };
+        let (data, checksum) = outer.split_at(split);
+        if self.algorithm.compute(data) != checksum {
+            error!("checksum mismatch");
+            return Err(LwskError::CodecError);
+        }
+        inner.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+/// A transport-only [IoDriver] with a chain of codecs in front of it
+pub struct Coded {
+    driver: Box<dyn IoDriver>,
+    codecs: Vec<Box<dyn Codec>>,
+    wire_buf: Vec<u8>,
+}
+
+impl Coded {
+    pub fn new(driver: Box<dyn IoDriver>, codecs: Vec<Box<dyn Codec>>) -> Self {
+        Self {
+            driver,
+            codecs,
+            wire_buf: vec![0u8; WIRE_BUF_SIZE],
+        }
+    }
+}
+
+impl IoDriver for Coded {
+    fn connection(&self) -> ConnectionState {
+        self.driver.connection()
+    }
+
+    fn rx_timestamp(&self) -> Option<std::time::Duration> {
+        self.driver.rx_timestamp()
+    }
+
+    fn readiness_fd(&self) -> Option<i32> {
+        self.driver.readiness_fd()
+    }
+
+    fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+        let Some(n) = self.driver.pull(&mut self.wire_buf)? else {
+            return Ok(None);
+        };
+
+        let mut outer = self.wire_buf[..n].to_vec();
+        let mut inner = Vec::new();
+        for codec in self.codecs.iter_mut().rev() {
+            inner.clear();
+            codec.decode(&outer, &mut inner)?;
+            core::mem::swap(&mut outer, &mut inner);
+        }
+
+        if outer.len() != buf.len() {
+            error!(
+                "decoded {} bytes, but the channel has {} bytes",
+                outer.len(),
+                buf.len()
+            );
+            return Err(LwskError::CodecError);
+        }
+        buf.copy_from_slice(&outer);
+
+        Ok(Some(outer.len()))
+    }
+
+    fn push(&mut self, buf: &[u8]) -> Result<(), LwskError> {
+        let mut inner = buf.to_vec();
+        let mut outer = Vec::new();
+        for codec in self.codecs.iter_mut() {
+            outer.clear();
+            codec.encode(&inner, &mut outer)?;
+            core::mem::swap(&mut outer, &mut inner);
+        }
+
+        self.driver.push(&inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::memory::Loopback;
+
+    fn fields() -> Vec<Field> {
+        [
+            ("a", FieldType::U16),
+            ("b", FieldType::I32),
+            ("c", FieldType::F32),
+        ]
+        .into_iter()
+        .map(|(name, ty)| Field {
+            name: Some(name.into()),
+            ty,
+        })
+        .collect()
+    }
+
+    /// Channel contents for `fields`
+    fn channel() -> Vec<u8> {
+        let mut channel = Vec::new();
+        channel.extend_from_slice(&0x0102u16.to_le_bytes());
+        channel.extend_from_slice(&(-2i32).to_le_bytes());
+        channel.extend_from_slice(&1.5f32.to_le_bytes());
+        channel
+    }
+
+    fn encode(codec: &mut dyn Codec, inner: &[u8]) -> Vec<u8> {
+        let mut outer = Vec::new();
+        codec.encode(inner, &mut outer).unwrap();
+        outer
+    }
+
+    fn decode(codec: &mut dyn Codec, outer: &[u8]) -> Result<Vec<u8>, LwskError> {
+        let mut inner = Vec::new();
+        codec.decode(outer, &mut inner).map(|()| inner)
+    }
+
+    #[test]
+    fn packed_round_trips_in_both_byte_orders() {
+        let channel = channel();
+        let mut little = Packed {
+            fields: fields(),
+            endian: Endian::Little,
+        };
+        assert_eq!(encode(&mut little, &channel), channel);
+        assert_eq!(decode(&mut little, &channel).unwrap(), channel);
+
+        let mut big = Packed {
+            fields: fields(),
+            endian: Endian::Big,
+        };
+        let wire = encode(&mut big, &channel);
+        assert_eq!(wire[..6], [0x01, 0x02, 0xff, 0xff, 0xff, 0xfe]);
+        assert_eq!(wire[6..], 1.5f32.to_be_bytes());
+        assert_eq!(decode(&mut big, &wire).unwrap(), channel);
+
+        assert!(decode(&mut big, &wire[1..]).is_err());
+    }
+
+    #[test]
+    fn self_describing_formats_round_trip() {
+        let channel = channel();
+        let mut codecs: [Box<dyn Codec>; 4] = [
+            Box::new(Cbor { fields: fields() }),
+            Box::new(Postcard { fields: fields() }),
+            Box::new(Json { fields: fields() }),
+            Box::new(Json {
+                fields: fields()
+                    .into_iter()
+                    .map(|field| Field {
+                        name: None,
+                        ..field
+                    })
+                    .collect(),
+            }),
+        ];
+        for codec in &mut codecs {
+            let wire = encode(codec.as_mut(), &channel);
+            assert_eq!(decode(codec.as_mut(), &wire).unwrap(), channel);
+        }
+
+        assert_eq!(
+            encode(codecs[2].as_mut(), &channel),
+            br#"{"a":258,"b":-2,"c":1.5}"#
+        );
+        assert_eq!(encode(codecs[3].as_mut(), &channel), b"[258,-2,1.5]");
+    }
+
+    #[test]
+    fn trailing_bytes_are_rejected() {
+        let channel = channel();
+        let mut cbor = Cbor { fields: fields() };
+        let mut postcard = Postcard { fields: fields() };
+        for codec in [&mut cbor as &mut dyn Codec, &mut postcard] {
+            let mut wire = encode(codec, &channel);
+            wire.push(0);
+            assert!(matches!(decode(codec, &wire), Err(LwskError::CodecError)));
+        }
+    }
+
+    #[test]
+    fn checksums_are_verified() {
+        // check values of the CRC catalogue
+        assert_eq!(Checksum::Crc16Ccitt.compute(b"123456789"), [0x29, 0xb1]);
+        assert_eq!(
+            Checksum::Crc32.compute(b"123456789"),
+            [0xcb, 0xf4, 0x39, 0x26]
+        );
+
+        let mut crc = Crc {
+            algorithm: Checksum::Crc32,
+        };
+        let mut wire = encode(&mut crc, b"123456789");
+        assert_eq!(wire[9..], [0xcb, 0xf4, 0x39, 0x26]);
+        assert_eq!(decode(&mut crc, &wire).unwrap(), b"123456789");
+
+        wire[0] ^= 1;
+        assert!(matches!(
+            decode(&mut crc, &wire),
+            Err(LwskError::CodecError)
+        ));
+        assert!(decode(&mut crc, &wire[..3]).is_err());
+    }
+
+    #[test]
+    fn first_codec_is_nearest_to_the_channel() {
+        let mut coded = Coded::new(
+            Box::new(Loopback::new(None)),
+            vec![
+                Box::new(Packed {
+                    fields: fields(),
+                    endian: Endian::Big,
+                }),
+                Box::new(Crc {
+                    algorithm: Checksum::Crc16Ccitt,
+                }),
+            ],
+        );
+        let channel = channel();
+        coded.push(&channel).unwrap();
+
+        let mut wire = [0u8; 12];
+        assert_eq!(coded.driver.pull(&mut wire).unwrap(), Some(12));
+        let packed = encode(
+            &mut Packed {
+                fields: fields(),
+                endian: Endian::Big,
+            },
+            &channel,
+        );
+        assert_eq!(wire[..10], packed);
+        assert_eq!(wire[10..], Checksum::Crc16Ccitt.compute(&packed));
+
+        coded.driver.push(&wire).unwrap();
+        let mut buf = [0u8; 10];
+        assert_eq!(coded.pull(&mut buf).unwrap(), Some(10));
+        assert_eq!(buf[..], channel);
+
+        // the decoded data must fill the channel exactly
+        coded.driver.push(&wire).unwrap();
+        assert!(matches!(
+            coded.pull(&mut [0u8; 11]),
+            Err(LwskError::CodecError)
+        ));
+    }
+}
@@ -0,0 +1,298 @@
+//! Event sources backed by Linux file descriptors
+//!
+//! - [Timer] expires periodically (`timerfd`), yielding the number of expirations
+//! - [Event] is signalled by pushes (`eventfd`), yielding the number of signals
+//!
+//! Both yield their counter as little endian `u64`, truncated to the channel, and only when it is
+//! non-zero. Their file descriptor is their readiness fd, thus an `await_io` schedule entry on
+//! them blocks until they fire, which allows to trigger sporadic functions.
+
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::Duration;
+
+use super::{DriverError, IoDriver};
+use crate::LwskError;
+
+/// Turn the result of a libc call into an [OwnedFd]
+fn owned_fd(fd: libc::c_int, what: &str) -> Result<OwnedFd, LwskError> {
+    if fd < 0 {
+        let e = std::io::Error::last_os_error();
+        log::error!("could not create {what}: {e}");
+        return Err(LwskError::DriverError(e.into()));
+    }
+    // SAFETY: the fd was just created and is owned by nobody else
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Read the 8 byte counter of a timerfd or eventfd, yielding zero if it is not set
+fn read_counter(fd: &OwnedFd) -> Result<u64, LwskError> {
+    let mut counter = [0u8; 8];
+    // SAFETY: counter is a writable buffer of the given length
+    let n = unsafe { libc::read(fd.as_raw_fd(), counter.as_mut_ptr().cast(), counter.len()) };
+    if n < 0 {
+        let e = std::io::Error::last_os_error();
+        if e.kind() == std::io::ErrorKind::WouldBlock {
+            return Ok(0);
+        }
+        log::error!("could not read counter: {e}");
+        return Err(LwskError::DriverError(e.into()));
+    }
+    Ok(u64::from_ne_bytes(counter))
+}
+
+/// Pull the counter of a timerfd or eventfd into `buf`
+fn pull_counter(fd: &OwnedFd, buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+    let counter = read_counter(fd)?;
+    if counter == 0 {
+        return Ok(None);
+    }
+
+    let counter = counter.to_le_bytes();
+    let n = counter.len().min(buf.len());
+    buf[..n].copy_from_slice(&counter[..n]);
+    Ok(Some(n))
+}
+
+fn timespec(duration: Duration) -> libc::timespec {
+    libc::timespec {
+        tv_sec: duration.as_secs() as libc::time_t,
+        tv_nsec: duration.subsec_nanos() as libc::c_long,
+    }
+}
+
+/// Periodic timer on the monotonic clock
+pub struct Timer {
+    fd: OwnedFd,
+}
+
+impl Timer {
+    /// Start a timer first expiring after `delay`, then every `period`
+    ///
+    /// A zero `delay` expires after the first `period`. A zero `period` is rejected, as it would
+    /// disarm the timer.
+    pub fn new(period: Duration, delay: Duration) -> Result<Self, LwskError> {
+        if period.is_zero() {
+            log::error!("the period of a timer must not be zero");
+            return Err(LwskError::InvalidDriverConfig);
+        }
+
+        // SAFETY: plain syscall without pointers
+        let fd = unsafe {
+            libc::timerfd_create(
+                libc::CLOCK_MONOTONIC,
+                libc::TFD_NONBLOCK | libc::TFD_CLOEXEC,
+            )
+        };
+        let fd = owned_fd(fd, "timerfd")?;
+
+        let spec = libc::itimerspec {
+            it_interval: timespec(period),
+            it_value: timespec(if delay.is_zero() { period } else { delay }),
+        };
+        // SAFETY: spec is a valid itimerspec, the old value is not requested
+        let result =
+            unsafe { libc::timerfd_settime(fd.as_raw_fd(), 0, &spec, core::ptr::null_mut()) };
+        if result != 0 {
+            let e = std::io::Error::last_os_error();
+            log::error!("could not arm timerfd: {e}");
+            return Err(LwskError::DriverError(e.into()));
+        }
+
+        Ok(Self { fd })
+    }
+}
+
+impl IoDriver for Timer {
+    fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+        pull_counter(&self.fd, buf)
+    }
+
+    fn push(&mut self, _buf: &[u8]) -> Result<(), LwskError> {
+        log::error!("can not push to a timer");
+        Err(DriverError::Unsupported.into())
+    }
+
+    fn readiness_fd(&self) -> Option<i32> {
+        Some(self.fd.as_raw_fd())
+    }
+}
+
+/// Event counter, incremented by each push and reset by each pull
+pub struct Event {
+    fd: OwnedFd,
+}
+
+impl Event {
+    /// Create an event, which in `semaphore` mode is only decremented by one per pull
+    pub fn new(semaphore: bool) -> Result<Self, LwskError> {
+        let mut flags = libc::EFD_NONBLOCK | libc::EFD_CLOEXEC;
+        if semaphore {
+            flags |= libc::EFD_SEMAPHORE;
+        }
+        // SAFETY: plain syscall without pointers
+        let fd = owned_fd(unsafe { libc::eventfd(0, flags) }, "eventfd")?;
+        Ok(Self { fd })
+    }
+
+    /// Increment the counter by one
+    pub fn signal(&self) -> Result<(), LwskError> {
+        let one = 1u64.to_ne_bytes();
+        // SAFETY: one is a readable buffer of the given length
+        let n = unsafe { libc::write(self.fd.as_raw_fd(), one.as_ptr().cast(), one.len()) };
+        if n < 0 {
+            let e = std::io::Error::last_os_error();
+            log::error!("could not signal eventfd: {e}");
+            return Err(LwskError::DriverError(e.into()));
+        }
+        Ok(())
+    }
+
+    /// Reset the counter, or decrement it by one in semaphore mode, returning its previous value
+    pub fn reset(&self) -> Result<u64, LwskError> {
+        read_counter(&self.fd)
+    }
+}
+
+impl IoDriver for Event {
+    fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+        pull_counter(&self.fd, buf)
+    }
+
+    fn push(&mut self, _buf: &[u8]) -> Result<(), LwskError> {
+        self.signal()
+    }
+
+    fn readiness_fd(&self) -> Option<i32> {
+        Some(self.fd.as_raw_fd())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blueprint::Blueprint;
+    use crate::io::{ErrorPolicy, IoBinding};
+    use crate::Step;
+
+    #[test]
+    fn timer_ticks_periodically() {
+        assert!(matches!(
+            Timer::new(Duration::ZERO, Duration::ZERO),
+            Err(LwskError::InvalidDriverConfig)
+        ));
+
+        let timer = Timer::new(Duration::from_millis(5), Duration::ZERO).unwrap();
+        let mut binding = IoBinding::new("timer", Box::new(timer), ErrorPolicy::default());
+        let mut buf = [0u8; 8];
+        assert_eq!(binding.pull(&mut buf).unwrap(), None);
+
+        assert!(binding.wait_ready(Duration::from_secs(1)));
+        assert_eq!(binding.pull(&mut buf).unwrap(), Some(8));
+        assert!(u64::from_le_bytes(buf) >= 1);
+        assert_eq!(binding.pull(&mut buf).unwrap(), None);
+
+        // the counter is truncated to the channel
+        let mut short = [0u8; 1];
+        assert!(binding.wait_ready(Duration::from_secs(1)));
+        assert_eq!(binding.pull(&mut short).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn event_counts_pushes() {
+        let mut event = Event::new(false).unwrap();
+        let mut buf = [0u8; 8];
+        assert_eq!(event.pull(&mut buf).unwrap(), None);
+        event.push(&[]).unwrap();
+        event.push(&[]).unwrap();
+        assert_eq!(event.pull(&mut buf).unwrap(), Some(8));
+        assert_eq!(u64::from_le_bytes(buf), 2);
+        assert_eq!(event.pull(&mut buf).unwrap(), None);
+
+        let mut semaphore = Event::new(true).unwrap();
+        semaphore.push(&[]).unwrap();
+        semaphore.push(&[]).unwrap();
+        for _ in 0..2 {
+            assert_eq!(semaphore.pull(&mut buf).unwrap(), Some(8));
+            assert_eq!(u64::from_le_bytes(buf), 1);
+        }
+        assert_eq!(semaphore.pull(&mut buf).unwrap(), None);
+    }
+
+    /// Copies its input to its output
+    const COPY: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global (export "INPUT") i32 (i32.const 0))
+          (global (export "OUTPUT") i32 (i32.const 8))
+          (func (export "process") (result i32)
+            (i64.store (i32.const 8) (i64.load (i32.const 0)))
+            (i32.const 0)))
+    "#;
+
+    const BLUEPRINT: &str = r#"
+        [channels.count]
+        size = 8
+        [channels.copy]
+        size = 8
+
+        [functions.copy]
+        wasm = "copy.wasm"
+        consumes = "count"
+        produces = "copy"
+        fuel_per_call = 1000
+
+        [io.event]
+        type = "Event"
+
+        [[schedules.main]]
+        from_io = "event"
+        to_channel = "count"
+        [[schedules.main]]
+        sporadic_function = "copy"
+    "#;
+
+    #[test]
+    fn sporadic_function_runs_once_per_event() {
+        let dir = std::env::temp_dir().join(format!("lwsk-fd-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("copy.wasm"), wat::parse_str(COPY).unwrap()).unwrap();
+        std::fs::write(dir.join("blueprint.toml"), BLUEPRINT).unwrap();
+        let mut kernel = Blueprint::new(dir.join("blueprint.toml"))
+            .unwrap()
+            .to_kernel_config()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let channel = |name| kernel.channels.iter().position(|c| c.name == name).unwrap();
+        let (count, copy) = (channel("count"), channel("copy"));
+
+        for signals in [0, 3, 0, 1] {
+            for _ in 0..signals {
+                kernel.io[0].push(&[]).unwrap();
+            }
+            let generation = kernel.channels[count].generation;
+            let step = kernel.step();
+            assert!(matches!(step, Step::Pulled { .. }), "{step:?}");
+            let step = kernel.step();
+            if signals == 0 {
+                assert_eq!(kernel.channels[count].generation, generation);
+                assert!(
+                    matches!(step, Step::Skipped { function_idx: 0 }),
+                    "{step:?}"
+                );
+            } else {
+                assert_eq!(kernel.channels[count].generation, generation + 1);
+                assert!(
+                    matches!(
+                        step,
+                        Step::Invoked {
+                            function_idx: 0,
+                            result: Ok(_)
+                        }
+                    ),
+                    "{step:?}"
+                );
+                assert_eq!(kernel.channels[copy].buf, (signals as u64).to_le_bytes());
+            }
+        }
+    }
+}
@@ -0,0 +1,495 @@
+//! MAVLink v2 driver layered over a byte transport
+//!
+//! Incoming frames are parsed from whatever the transport yields, checked for their CRC and
+//! sequence, and selected fields of selected messages are copied into the channel. On push, the
+//! channel contents are scattered into the payloads of the configured messages, which are then
+//! framed and sent.
+//!
+//! Only the messages configured in a [Mavlink] are understood, as checking the CRC of a message
+//! requires its `CRC_EXTRA` seed from the dialect definition. Field offsets refer to the payload
+//! on the wire, i.e. after MAVLink reordered the fields by size.
+
+use std::collections::HashMap;
+
+use super::{ConnectionState, IoDriver};
+use crate::LwskError;
+
+/// Start marker of a MAVLink v2 frame
+pub const STX_V2: u8 = 0xFD;
+
+/// Length of the header, from the start marker to the message id
+const HEADER_LEN: usize = 10;
+
+/// Length of the checksum
+const CHECKSUM_LEN: usize = 2;
+
+/// Length of the optional signature
+const SIGNATURE_LEN: usize = 13;
+
+/// Incompatibility flag signaling a signed frame
+const INCOMPAT_FLAG_SIGNED: u8 = 0x01;
+
+/// Size of the buffer to receive data from the transport into
+const RX_CHUNK_SIZE: usize = 4096;
+
+/// Maximum number of pulls from the transport per pull of the driver
+///
+/// Transports which always yield data, like a constant, would otherwise never be drained.
+const MAX_TRANSPORT_PULLS: usize = 16;
+
+/// A contiguous range of bytes to copy between a message payload and the channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldMap {
+    /// Offset of the field in the payload
+    pub offset: usize,
+
+    /// Size of the field in byte
+    pub size: usize,
+
+    /// Offset of the field in the channel
+    pub channel_offset: usize,
+}
+
+/// A message known to the driver
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// MAVLink message id
+    pub id: u32,
+
+    /// Seed of the CRC, as given by the dialect
+    pub crc_extra: u8,
+
+    /// Length of the untruncated payload, only relevant for transmitted messages
+    pub payload_len: usize,
+
+    /// Fields copied between payload and channel
+    pub fields: Vec<FieldMap>,
+}
+
+/// A decoded MAVLink v2 frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub seq: u8,
+    pub system_id: u8,
+    pub component_id: u8,
+    pub message_id: u32,
+    pub payload: Vec<u8>,
+}
+
+pub struct Mavlink {
+    transport: Box<dyn IoDriver>,
+    system_id: u8,
+    component_id: u8,
+
+    /// Messages copied to the channel on pull, by message id
+    rx: HashMap<u32, Message>,
+
+    /// Messages emitted on push
+    tx: Vec<Message>,
+
+    /// Sequence number of the next transmitted frame
+    tx_seq: u8,
+
+    /// Last sequence number seen per system and component
+    rx_seq: HashMap<(u8, u8), u8>,
+
+    /// Received bytes not yet parsed
+    rx_buf: Vec<u8>,
+}
+
+/// CRC-16/MCRF4XX as used by MAVLink, accumulating `data` into `crc`
+pub fn crc_accumulate(mut crc: u16, data: &[u8]) -> u16 {
+    for byte in data {
+        let mut tmp = byte ^ (crc & 0xff) as u8;
+        tmp ^= tmp << 4;
+        crc = (crc >> 8) ^ ((tmp as u16) << 8) ^ ((tmp as u16) << 3) ^ ((tmp as u16) >> 4);
+    }
+    crc
+}
+
+/// Checksum of a frame with the header and payload `data`
+pub fn checksum(data: &[u8], crc_extra: u8) -> u16 {
+    crc_accumulate(crc_accumulate(0xffff, data), &[crc_extra])
+}
+
+impl Frame {
+    /// Serialize into a frame, truncating trailing zeros of the payload
+    pub fn encode(&self, crc_extra: u8) -> Vec<u8> {
+        let payload_len = self
+            .payload
+            .iter()
+            .rposition(|b| *b != 0)
+            .map_or(1, |last| last + 1)
+            .min(self.payload.len());
+
+        let mut frame = Vec::with_capacity(HEADER_LEN + payload_len + CHECKSUM_LEN);
+        frame.extend_from_slice(&[
+            STX_V2,
+            payload_len as u8,
+            0, // incompatibility flags
+            0, // compatibility flags
+            self.seq,
+            self.system_id,
+            self.component_id,
+        ]);
+        frame.extend_from_slice(&self.message_id.to_le_bytes()[..3]);
+        frame.extend_from_slice(&self.payload[..payload_len]);
+        frame.extend_from_slice(&checksum(&frame[1..], crc_extra).to_le_bytes());
+        frame
+    }
+}
+
+impl Mavlink {
+    pub fn new(
+        transport: Box<dyn IoDriver>,
+        system_id: u8,
+        component_id: u8,
+        rx: Vec<Message>,
+        tx: Vec<Message>,
+    ) -> Result<Self, LwskError> {
+        for message in &tx {
+            if message.payload_len > u8::MAX as usize || message.id > 0xff_ffff {
+                log::error!("message {} can not be represented in MAVLink", message.id);
+                return Err(LwskError::IoChannelCreationError);
+            }
+            if message
+                .fields
+                .iter()
+                .any(|f| f.offset + f.size > message.payload_len)
+            {
+                log::error!("message {} has fields beyond its payload", message.id);
+                return Err(LwskError::IoChannelCreationError);
+            }
+        }
+        for message in rx.iter().chain(&tx) {
+            if message
+                .fields
+                .iter()
+                .any(|f| f.offset + f.size > u8::MAX as usize)
+            {
+                log::error!("message {} has fields beyond its payload", message.id);
+                return Err(LwskError::IoChannelCreationError);
+            }
+        }
+
+        Ok(Self {
+            transport,
+            system_id,
+            component_id,
+            rx: rx.into_iter().map(|m| (m.id, m)).collect(),
+            tx,
+            tx_seq: 0,
+            rx_seq: HashMap::new(),
+            rx_buf: Vec::new(),
+        })
+    }
+
+    /// Try to parse the next frame from the receive buffer
+    ///
+    /// Returns [None] if more data is needed. Bytes which can not be the start of a valid frame
+    /// are discarded.
+    fn parse_frame(&mut self) -> Option<Frame> {
+        loop {
+            // resynchronize to the next start marker
+            match self.rx_buf.iter().position(|b| *b == STX_V2) {
+                Some(start) => drop(self.rx_buf.drain(..start)),
+                None => {
+                    self.rx_buf.clear();
+                    return None;
+                }
+            }
+
+            if self.rx_buf.len() < HEADER_LEN {
+                return None;
+            }
+
+            let payload_len = self.rx_buf[1] as usize;
+            let signature_len = if self.rx_buf[2] & INCOMPAT_FLAG_SIGNED != 0 {
+                SIGNATURE_LEN
+            } else {
+                0
+            };
+            let frame_len = HEADER_LEN + payload_len + CHECKSUM_LEN + signature_len;
+            if self.rx_buf.len() < frame_len {
+                return None;
+            }
+
+            let (seq, system_id, component_id) = (self.rx_buf[4], self.rx_buf[5], self.rx_buf[6]);
+            let message_id =
+                u32::from_le_bytes([self.rx_buf[7], self.rx_buf[8], self.rx_buf[9], 0]);
+            let Some(message) = self.rx.get(&message_id) else {
+                log::trace!("skipping MAVLink message {message_id}");
+                self.check_sequence(system_id, component_id, seq);
+                self.rx_buf.drain(..frame_len);
+                continue;
+            };
+
+            let crc_end = HEADER_LEN + payload_len;
+            let expected = checksum(&self.rx_buf[1..crc_end], message.crc_extra);
+            let got = u16::from_le_bytes([self.rx_buf[crc_end], self.rx_buf[crc_end + 1]]);
+            if expected != got {
+                log::warn!("dropping MAVLink message {message_id} with invalid checksum");
+                // the start marker may have been part of a payload, so only skip it
+                self.rx_buf.drain(..1);
+                continue;
+            }
+
+            // zero-extend truncated payloads
+            let mut payload = self.rx_buf[HEADER_LEN..crc_end].to_vec();
+            payload.resize(u8::MAX as usize, 0);
+
+            self.check_sequence(system_id, component_id, seq);
+            let frame = Frame {
+                seq,
+                system_id,
+                component_id,
+                message_id,
+                payload,
+            };
+            self.rx_buf.drain(..frame_len);
+            return Some(frame);
+        }
+    }
+
+    /// Track the sequence of frames from a system and component, including skipped messages
+    fn check_sequence(&mut self, system_id: u8, component_id: u8, seq: u8) {
+        if let Some(last) = self.rx_seq.insert((system_id, component_id), seq) {
+            let lost = seq.wrapping_sub(last).wrapping_sub(1);
+            if lost != 0 {
+                log::warn!(
+                    "lost {lost} MAVLink frames from system {system_id} component {component_id}"
+                );
+            }
+        }
+    }
+}
+
+impl IoDriver for Mavlink {
+    fn connection(&self) -> ConnectionState {
+        self.transport.connection()
+    }
+
+    fn rx_timestamp(&self) -> Option<std::time::Duration> {
+        self.transport.rx_timestamp()
+    }
+
+    fn readiness_fd(&self) -> Option<i32> {
+        self.transport.readiness_fd()
+    }
+
+    fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+        // drain the transport, but only so much per call
+        let mut chunk = [0u8; RX_CHUNK_SIZE];
+        for _ in 0..MAX_TRANSPORT_PULLS {
+            let Some(n) = self.transport.pull(&mut chunk)? else {
+                break;
+            };
+            self.rx_buf.extend_from_slice(&chunk[..n]);
+        }
+
+        let mut updated = false;
+        while let Some(frame) = self.parse_frame() {
+            let message = &self.rx[&frame.message_id];
+            for field in &message.fields {
+                let Some(dst) =
+                    buf.get_mut(field.channel_offset..field.channel_offset + field.size)
+                else {
+                    log::error!(
+                        "field of MAVLink message {} does not fit into the channel",
+                        message.id
+                    );
+                    return Err(LwskError::BufferTooSmall {
+                        expected: field.channel_offset + field.size,
+                        got: buf.len(),
+                    });
+                };
+                dst.copy_from_slice(&frame.payload[field.offset..field.offset + field.size]);
+            }
+            log::debug!(
+                "received MAVLink message {} from system {} component {}",
+                frame.message_id,
+                frame.system_id,
+                frame.component_id
+            );
+            updated = true;
+        }
+
+        Ok(updated.then_some(buf.len()))
+    }
+
+    fn push(&mut self, buf: &[u8]) -> Result<(), LwskError> {
+        for message in &self.tx {
+            let mut payload = vec![0u8; message.payload_len];
+            for field in &message.fields {
+                let Some(src) = buf.get(field.channel_offset..field.channel_offset + field.size)
+                else {
+                    log::error!(
+                        "field of MAVLink message {} is not contained in the channel",
+                        message.id
+                    );
+                    return Err(LwskError::BufferTooSmall {
+                        expected: field.channel_offset + field.size,
+                        got: buf.len(),
+                    });
+                };
+                payload[field.offset..field.offset + field.size].copy_from_slice(src);
+            }
+
+            let frame = Frame {
+                seq: self.tx_seq,
+                system_id: self.system_id,
+                component_id: self.component_id,
+                message_id: message.id,
+                payload,
+            };
+            self.tx_seq = self.tx_seq.wrapping_add(1);
+
+            self.transport.push(&frame.encode(message.crc_extra))?;
+            log::debug!("sent MAVLink message {}", message.id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::memory::Loopback;
+
+    /// HEARTBEAT of a quadrotor running ArduPilot, from system 1 component 1
+    const HEARTBEAT: [u8; 21] = [
+        0xfd, 0x09, 0x00, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+        0x03, 0x51, 0x04, 0x03, 0xe7, 0x1e,
+    ];
+
+    /// Maps `custom_mode` and `type` of HEARTBEAT to the first 5 bytes of the channel
+    fn heartbeat() -> Message {
+        Message {
+            id: 0,
+            crc_extra: 50,
+            payload_len: 9,
+            fields: vec![
+                FieldMap {
+                    offset: 0,
+                    size: 4,
+                    channel_offset: 0,
+                },
+                FieldMap {
+                    offset: 4,
+                    size: 1,
+                    channel_offset: 4,
+                },
+            ],
+        }
+    }
+
+    fn mavlink() -> Mavlink {
+        let transport = Box::new(Loopback::new(None));
+        Mavlink::new(transport, 1, 1, vec![heartbeat()], vec![heartbeat()]).unwrap()
+    }
+
+    /// Pull after feeding `bytes` to the transport
+    fn receive(mavlink: &mut Mavlink, bytes: &[u8]) -> Option<[u8; 5]> {
+        mavlink.transport.push(bytes).unwrap();
+        let mut buf = [0xaa; 5];
+        mavlink.pull(&mut buf).unwrap().map(|_| buf)
+    }
+
+    #[test]
+    fn checksum_matches_known_frames() {
+        // check value of CRC-16/MCRF4XX
+        assert_eq!(crc_accumulate(0xffff, b"123456789"), 0x6f91);
+
+        let frame = Frame {
+            seq: 0,
+            system_id: 1,
+            component_id: 1,
+            message_id: 0,
+            payload: HEARTBEAT[HEADER_LEN..HEADER_LEN + 9].to_vec(),
+        };
+        assert_eq!(frame.encode(50), HEARTBEAT);
+        assert_eq!(
+            receive(&mut mavlink(), &HEARTBEAT),
+            Some([0, 0, 0, 0, 0x02])
+        );
+    }
+
+    #[test]
+    fn trailing_zeros_are_truncated_and_restored() {
+        let mut frame = Frame {
+            seq: 0,
+            system_id: 1,
+            component_id: 1,
+            message_id: 0,
+            payload: vec![7, 0, 0, 0, 0, 0, 0, 0, 0],
+        };
+        let encoded = frame.encode(50);
+        assert_eq!(encoded[1], 1);
+        assert_eq!(encoded.len(), HEADER_LEN + 1 + CHECKSUM_LEN);
+        assert_eq!(receive(&mut mavlink(), &encoded), Some([7, 0, 0, 0, 0]));
+
+        // an all-zero payload keeps one byte
+        frame.payload = vec![0; 9];
+        assert_eq!(frame.encode(50)[1], 1);
+    }
+
+    #[test]
+    fn receiver_resynchronizes() {
+        let mut mavlink = mavlink();
+        let mut garbage = vec![0x00, 0x42, 0x13];
+        garbage.extend_from_slice(&HEARTBEAT);
+        assert_eq!(receive(&mut mavlink, &garbage), Some([0, 0, 0, 0, 0x02]));
+
+        let mut corrupted = HEARTBEAT;
+        corrupted[14] = 0x0a;
+        assert_eq!(receive(&mut mavlink, &corrupted), None);
+        let mut bytes = corrupted.to_vec();
+        bytes.extend_from_slice(&HEARTBEAT);
+        assert_eq!(receive(&mut mavlink, &bytes), Some([0, 0, 0, 0, 0x02]));
+    }
+
+    #[test]
+    fn signatures_are_skipped() {
+        let frame = Frame {
+            seq: 0,
+            system_id: 1,
+            component_id: 1,
+            message_id: 0,
+            payload: vec![1, 2, 3, 4, 5, 0, 0, 0, 0],
+        };
+        let mut signed = frame.encode(50);
+        signed[2] |= INCOMPAT_FLAG_SIGNED;
+        let crc_end = signed.len() - CHECKSUM_LEN;
+        let crc = checksum(&signed[1..crc_end], 50);
+        signed[crc_end..].copy_from_slice(&crc.to_le_bytes());
+        // a signature containing a start marker must not be taken for a frame
+        signed.extend_from_slice(&[STX_V2; SIGNATURE_LEN]);
+        signed.extend_from_slice(&HEARTBEAT);
+
+        let mut mavlink = mavlink();
+        mavlink.transport.push(&signed).unwrap();
+        let mut buf = [0xaa; 5];
+        assert_eq!(mavlink.pull(&mut buf).unwrap(), Some(5));
+        assert_eq!(buf, [0, 0, 0, 0, 0x02]);
+        assert!(mavlink.rx_buf.is_empty());
+    }
+
+    #[test]
+    fn fields_are_mapped_both_ways() {
+        let mut mavlink = mavlink();
+        mavlink.push(&[1, 2, 3, 4, 5]).unwrap();
+        let mut buf = [0u8; 5];
+        assert_eq!(mavlink.pull(&mut buf).unwrap(), Some(5));
+        assert_eq!(buf, [1, 2, 3, 4, 5]);
+        assert_eq!(mavlink.pull(&mut buf).unwrap(), None);
+
+        // fields beyond the channel are rejected
+        assert!(matches!(
+            mavlink.push(&[1, 2, 3, 4]),
+            Err(LwskError::BufferTooSmall {
+                expected: 5,
+                got: 4
+            })
+        ));
+    }
+}
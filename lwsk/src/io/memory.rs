@@ -0,0 +1,269 @@
+//! In-memory drivers, to exercise blueprints without any external IO
+//!
+//! - [Constant] yields the same data on every pull
+//! - [Sequence] yields a scripted list of data, one entry per pull
+//! - [Sink] collects all pushed data for later inspection
+//! - [Loopback] yields pushed data on later pulls, in order
+//!
+//! Data whose length differs from the channel is truncated or only partially overwrites it.
+
+use std::any::Any;
+use std::collections::VecDeque;
+
+use super::IoDriver;
+use crate::LwskError;
+
+fn copy_into(data: &[u8], buf: &mut [u8]) -> usize {
+    if data.len() != buf.len() {
+        log::warn!(
+            "yielding {} bytes, but the channel has {} bytes",
+            data.len(),
+            buf.len()
+        );
+    }
+    let n = data.len().min(buf.len());
+    buf[..n].copy_from_slice(&data[..n]);
+    n
+}
+
+/// Yields the same data on every pull, discarding pushes
+pub struct Constant {
+    data: Vec<u8>,
+}
+
+impl Constant {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self { data }
+    }
+}
+
+impl IoDriver for Constant {
+    fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+        Ok(Some(copy_into(&self.data, buf)))
+    }
+
+    fn push(&mut self, buf: &[u8]) -> Result<(), LwskError> {
+        log::debug!("discarding {} bytes pushed to constant", buf.len());
+        Ok(())
+    }
+}
+
+/// Yields one entry of a scripted list per pull, discarding pushes
+pub struct Sequence {
+    values: Vec<Vec<u8>>,
+    next: usize,
+
+    /// Whether to start over once all values were yielded
+    repeat: bool,
+}
+
+impl Sequence {
+    pub fn new(values: Vec<Vec<u8>>, repeat: bool) -> Self {
+        Self {
+            values,
+            next: 0,
+            repeat,
+        }
+    }
+}
+
+impl IoDriver for Sequence {
+    fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+        if self.repeat && self.next == self.values.len() {
+            self.next = 0;
+        }
+        let Some(value) = self.values.get(self.next) else {
+            log::debug!("sequence is exhausted");
+            return Ok(None);
+        };
+        self.next += 1;
+
+        Ok(Some(copy_into(value, buf)))
+    }
+
+    fn push(&mut self, buf: &[u8]) -> Result<(), LwskError> {
+        log::debug!("discarding {} bytes pushed to sequence", buf.len());
+        Ok(())
+    }
+}
+
+/// Collects pushed data, never yielding any
+///
+/// Obtain it from a [crate::KernelConfig] via [IoDriver::as_any] to inspect the collected data.
+pub struct Sink {
+    pushed: VecDeque<Vec<u8>>,
+
+    /// Number of most recent pushes to keep, if limited
+    limit: Option<usize>,
+}
+
+impl Sink {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            pushed: VecDeque::new(),
+            limit,
+        }
+    }
+
+    /// All data pushed so far, oldest first
+    pub fn pushed(&self) -> impl Iterator<Item = &[u8]> {
+        self.pushed.iter().map(Vec::as_slice)
+    }
+}
+
+impl IoDriver for Sink {
+    fn pull(&mut self, _buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+        Ok(None)
+    }
+
+    fn push(&mut self, buf: &[u8]) -> Result<(), LwskError> {
+        if self.limit.is_some_and(|limit| self.pushed.len() >= limit) {
+            self.pushed.pop_front();
+        }
+        if self.limit != Some(0) {
+            self.pushed.push_back(buf.to_vec());
+        }
+        log::debug!("collected {} bytes in sink", buf.len());
+        Ok(())
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
+
+/// Yields pushed data on later pulls, first in first out
+pub struct Loopback {
+    queue: VecDeque<Vec<u8>>,
+
+    /// Number of pushes to buffer at most, dropping the oldest ones beyond
+    capacity: Option<usize>,
+}
+
+impl Loopback {
+    pub fn new(capacity: Option<usize>) -> Self {
+        Self {
+            queue: VecDeque::new(),
+            capacity,
+        }
+    }
+}
+
+impl IoDriver for Loopback {
+    fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+        let Some(data) = self.queue.pop_front() else {
+            log::debug!("loopback is empty");
+            return Ok(None);
+        };
+        Ok(Some(copy_into(&data, buf)))
+    }
+
+    fn push(&mut self, buf: &[u8]) -> Result<(), LwskError> {
+        if self
+            .capacity
+            .is_some_and(|capacity| self.queue.len() >= capacity)
+        {
+            log::warn!("loopback is full, dropping the oldest data");
+            self.queue.pop_front();
+        }
+        if self.capacity != Some(0) {
+            self.queue.push_back(buf.to_vec());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_yields_its_data_on_every_pull() {
+        let mut constant = Constant::new(vec![1, 2, 3]);
+        let mut buf = [0u8; 3];
+        for _ in 0..3 {
+            assert_eq!(constant.pull(&mut buf).unwrap(), Some(3));
+            assert_eq!(buf, [1, 2, 3]);
+        }
+        constant.push(&[4, 5, 6]).unwrap();
+        assert_eq!(constant.pull(&mut buf).unwrap(), Some(3));
+        assert_eq!(buf, [1, 2, 3]);
+
+        let mut short = [0u8; 2];
+        assert_eq!(constant.pull(&mut short).unwrap(), Some(2));
+        assert_eq!(short, [1, 2]);
+        let mut long = [9u8; 4];
+        assert_eq!(constant.pull(&mut long).unwrap(), Some(3));
+        assert_eq!(long, [1, 2, 3, 9]);
+    }
+
+    #[test]
+    fn sequence_yields_its_values_in_order() {
+        let mut sequence = Sequence::new(vec![vec![1], vec![2]], false);
+        let mut buf = [0u8; 1];
+        assert_eq!(sequence.pull(&mut buf).unwrap(), Some(1));
+        assert_eq!(buf, [1]);
+        assert_eq!(sequence.pull(&mut buf).unwrap(), Some(1));
+        assert_eq!(buf, [2]);
+        assert_eq!(sequence.pull(&mut buf).unwrap(), None);
+        assert_eq!(buf, [2]);
+
+        let mut repeated = Sequence::new(vec![vec![1], vec![2]], true);
+        let pulled: Vec<u8> = (0..5)
+            .map(|_| {
+                repeated.pull(&mut buf).unwrap();
+                buf[0]
+            })
+            .collect();
+        assert_eq!(pulled, [1, 2, 1, 2, 1]);
+
+        let mut empty = Sequence::new(Vec::new(), true);
+        assert_eq!(empty.pull(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn sink_keeps_the_most_recent_pushes() {
+        let mut sink = Sink::new(None);
+        sink.push(&[1]).unwrap();
+        sink.push(&[2, 3]).unwrap();
+        assert_eq!(sink.pull(&mut [0u8; 2]).unwrap(), None);
+        assert_eq!(sink.pushed().collect::<Vec<_>>(), [&[1][..], &[2, 3]]);
+
+        let mut limited = Sink::new(Some(2));
+        for i in 0..5 {
+            limited.push(&[i]).unwrap();
+        }
+        assert_eq!(limited.pushed().collect::<Vec<_>>(), [[3], [4]]);
+
+        let mut discarding = Sink::new(Some(0));
+        discarding.push(&[1]).unwrap();
+        assert_eq!(discarding.pushed().count(), 0);
+
+        let any = limited.as_any().unwrap();
+        assert!(any.downcast_ref::<Sink>().is_some());
+    }
+
+    #[test]
+    fn loopback_yields_pushes_first_in_first_out() {
+        let mut loopback = Loopback::new(None);
+        let mut buf = [0u8; 1];
+        assert_eq!(loopback.pull(&mut buf).unwrap(), None);
+        loopback.push(&[1]).unwrap();
+        loopback.push(&[2]).unwrap();
+        assert_eq!(loopback.pull(&mut buf).unwrap(), Some(1));
+        assert_eq!(buf, [1]);
+        assert_eq!(loopback.pull(&mut buf).unwrap(), Some(1));
+        assert_eq!(buf, [2]);
+        assert_eq!(loopback.pull(&mut buf).unwrap(), None);
+
+        let mut bounded = Loopback::new(Some(2));
+        for i in 0..3 {
+            bounded.push(&[i]).unwrap();
+        }
+        assert_eq!(bounded.pull(&mut buf).unwrap(), Some(1));
+        assert_eq!(buf, [1]);
+        assert_eq!(bounded.pull(&mut buf).unwrap(), Some(1));
+        assert_eq!(buf, [2]);
+        assert_eq!(bounded.pull(&mut buf).unwrap(), None);
+    }
+}
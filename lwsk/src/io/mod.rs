@@ -1,3 +1,9 @@
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use core::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
 use crate::LwskError;
 
 /// Driver for IO
@@ -5,13 +11,303 @@ use crate::LwskError;
 pub trait IoDriver {
     /// Pull data from this IO source, if any
     ///
-    /// If this driver has no new data present since the last call to [Self::pull], `buf` shall not be
-    /// changed.
-    fn pull(&mut self, buf: &mut [u8]) -> Result<(), LwskError>;
+    /// Returns the number of bytes written to `buf`, or [None] if this driver has no new data
+    /// present since the last call to [Self::pull]. In the latter case `buf` shall not be changed.
+    fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, LwskError>;
 
     /// Push data to this IO sink
     fn push(&mut self, buf: &[u8]) -> Result<(), LwskError>;
+
+    /// State of the connection to the peer or device, if this driver has one
+    fn connection(&self) -> ConnectionState {
+        ConnectionState::Connectionless
+    }
+
+    /// Time at which the data yielded by the last successful [Self::pull] was received
+    ///
+    /// Given as time since the Unix epoch. Drivers which can not tell better return [None], in
+    /// which case the time of the pull is used.
+    fn rx_timestamp(&self) -> Option<Duration> {
+        None
+    }
+
+    /// File descriptor which becomes readable once this driver has data to pull, if it has one
+    ///
+    /// Allows to wait for data instead of polling, see [IoBinding::wait_ready].
+    fn readiness_fd(&self) -> Option<i32> {
+        None
+    }
+
+    /// This driver as [core::any::Any], for drivers which allow to inspect their state
+    fn as_any(&self) -> Option<&dyn core::any::Any> {
+        None
+    }
+}
+
+/// Structured error reported by an [IoDriver]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, Serialize, Deserialize)]
+pub enum DriverError {
+    /// The operating system reported the contained `errno`
+    #[error("OS error {0}")]
+    Os(i32),
+
+    /// The connection to the peer is lost or not yet established
+    #[error("not connected")]
+    Disconnected,
+
+    /// The peer did not respond in time
+    #[error("timed out")]
+    Timeout,
+
+    /// The peer or device violated the protocol or reported an error
+    #[error("protocol error: {0}")]
+    Protocol(String),
+
+    /// The driver does not support the operation, e.g. pushing to a read-only source
+    #[error("unsupported operation")]
+    Unsupported,
+
+    #[error("{0}")]
+    Other(String),
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for DriverError {
+    fn from(e: std::io::Error) -> Self {
+        use std::io::ErrorKind;
+        match e.kind() {
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => Self::Timeout,
+            ErrorKind::NotConnected
+            | ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+            | ErrorKind::UnexpectedEof => Self::Disconnected,
+            _ => match e.raw_os_error() {
+                Some(errno) => Self::Os(errno),
+                None => Self::Other(e.to_string()),
+            },
+        }
+    }
+}
+
+/// State of the connection of an [IoDriver] to its peer or device
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ConnectionState {
+    /// The driver does not maintain a connection
+    #[default]
+    Connectionless,
+    Connecting,
+    Connected,
+    Disconnected,
 }
 
+/// What to do about a failed IO operation, once all retries are exhausted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorAction {
+    /// Only log the error
+    #[default]
+    Ignore,
+
+    /// Mark the pulled channel as invalid until the next successful pull
+    Invalidate,
+
+    /// Raise a [HealthEvent]
+    HealthEvent,
+}
+
+/// How an [IoBinding] deals with errors of its driver
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ErrorPolicy {
+    /// Number of immediate retries of a failed operation
+    pub retries: u32,
+
+    pub action: ErrorAction,
+}
+
+/// Failure of an IO binding, raised according to its [ErrorPolicy]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthEvent {
+    /// Index of the failed IO binding
+    pub io_idx: usize,
+
+    pub error: DriverError,
+}
+
+/// Health of an [IoBinding]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Health {
+    pub connection: ConnectionState,
+
+    /// Number of pulls and pushes, including retries
+    pub operations: u64,
+
+    /// Number of failed pulls and pushes, including retries
+    pub failures: u64,
+
+    /// Number of failures since the last successful operation
+    pub consecutive_failures: u64,
+
+    pub last_error: Option<DriverError>,
+}
+
+/// An [IoDriver] as bound into a [crate::KernelConfig]
+pub struct IoBinding {
+    /// Name of this binding
+    pub name: String,
+
+    pub driver: Box<dyn IoDriver>,
+
+    pub policy: ErrorPolicy,
+
+    health: Health,
+
+    /// Receive time of the data yielded by the last successful pull
+    rx_timestamp: Option<Duration>,
+}
+
+impl IoBinding {
+    pub fn new(name: &str, driver: Box<dyn IoDriver>, policy: ErrorPolicy) -> Self {
+        Self {
+            name: name.into(),
+            driver,
+            policy,
+            health: Health::default(),
+            rx_timestamp: None,
+        }
+    }
+
+    /// Pull from the driver, retrying according to the policy
+    pub fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, DriverError> {
+        let result = self.retry(|driver| driver.pull(buf));
+        if let Ok(Some(_)) = result {
+            self.rx_timestamp = self.driver.rx_timestamp();
+
+            #[cfg(feature = "std")]
+            if self.rx_timestamp.is_none() {
+                self.rx_timestamp = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .ok();
+            }
+        }
+        result
+    }
+
+    /// Receive time of the data yielded by the last successful pull, since the Unix epoch
+    pub fn rx_timestamp(&self) -> Option<Duration> {
+        self.rx_timestamp
+    }
+
+    /// Block until the driver has data to pull or `timeout` elapsed, returning whether it has
+    ///
+    /// Drivers without [IoDriver::readiness_fd] can not tell, neither can any driver on platforms
+    /// other than Unix, thus this waits for the whole `timeout` and returns `false`.
+    #[cfg(feature = "std")]
+    pub fn wait_ready(&self, timeout: Duration) -> bool {
+        #[cfg(unix)]
+        if let Some(fd) = self.driver.readiness_fd() {
+            let mut pollfd = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // round up, to not return before the timeout elapsed
+            let timeout_ms = timeout.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32;
+            // SAFETY: pollfd is a valid array of exactly one element
+            return match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+                0 => false,
+                n if n > 0 => true,
+                _ => {
+                    warn!(
+                        "could not wait for {:?}: {}",
+                        self.name,
+                        std::io::Error::last_os_error()
+                    );
+                    false
+                }
+            };
+        }
+
+        trace!(
+            "{:?} can not tell its readiness, waiting {timeout:?}",
+            self.name
+        );
+        std::thread::sleep(timeout);
+        false
+    }
+
+    /// Push to the driver, retrying according to the policy
+    pub fn push(&mut self, buf: &[u8]) -> Result<(), DriverError> {
+        self.retry(|driver| driver.push(buf))
+    }
+
+    /// Current health, including the connection state reported by the driver
+    pub fn health(&self) -> Health {
+        Health {
+            connection: self.driver.connection(),
+            ..self.health.clone()
+        }
+    }
+
+    fn retry<T, F>(&mut self, mut operation: F) -> Result<T, DriverError>
+    where
+        F: FnMut(&mut dyn IoDriver) -> Result<T, LwskError>,
+    {
+        let mut attempt = 0;
+        loop {
+            self.health.operations += 1;
+            let error = match operation(self.driver.as_mut()) {
+                Ok(value) => {
+                    self.health.consecutive_failures = 0;
+                    return Ok(value);
+                }
+                Err(LwskError::DriverError(e)) => e,
+                Err(e) => DriverError::Other(e.to_string()),
+            };
+
+            self.health.failures += 1;
+            self.health.consecutive_failures += 1;
+            self.health.last_error = Some(error.clone());
+
+            if attempt >= self.policy.retries {
+                return Err(error);
+            }
+            attempt += 1;
+            debug!(
+                "retrying {:?} after {error}, attempt {attempt} of {}",
+                self.name, self.policy.retries
+            );
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+pub mod ccsds;
+#[cfg(feature = "std")]
+pub mod codec;
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub mod fd;
+#[cfg(feature = "std")]
+pub mod mavlink;
+#[cfg(feature = "std")]
+pub mod memory;
+#[cfg(feature = "std")]
+pub mod modbus;
+#[cfg(feature = "std")]
+pub mod mqtt;
+#[cfg(feature = "std")]
+pub mod plant;
+#[cfg(feature = "std")]
+pub mod record;
+#[cfg(feature = "std")]
+pub mod registry;
+#[cfg(feature = "std")]
+pub mod serial;
+#[cfg(feature = "std")]
+pub mod shm;
+#[cfg(feature = "std")]
+pub mod threaded;
 #[cfg(feature = "std")]
 pub mod udp;
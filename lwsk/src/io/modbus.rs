@@ -0,0 +1,714 @@
+//! Modbus TCP driver, acting either as client or as server
+//!
+//! A binding maps a contiguous range of one [Table] onto a channel. Registers appear in the
+//! channel as on the wire, i.e. as big endian 16 bit words. Coils and discrete inputs are packed
+//! into bits, the first one being the least significant bit of the first byte.
+//!
+//! As [Client], the driver polls the range from a remote server on every pull and writes the
+//! channel contents back on every push. Note that this blocks for up to the configured timeout.
+//!
+//! As [Server], the driver exposes the range in a register map. Pushed data becomes visible to
+//! clients, while writes by clients are yielded on the next pull. All server bindings with the
+//! same listening address share one [ServerState], so one server can expose multiple channels.
+
+use std::cell::RefCell;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::{ConnectionState, DriverError, IoDriver};
+use crate::LwskError;
+
+/// Length of the MBAP header, including the unit id
+const MBAP_LEN: usize = 7;
+
+/// Largest possible PDU
+const MAX_PDU_LEN: usize = 253;
+
+const FC_READ_COILS: u8 = 0x01;
+const FC_READ_DISCRETE_INPUTS: u8 = 0x02;
+const FC_READ_HOLDING_REGISTERS: u8 = 0x03;
+const FC_READ_INPUT_REGISTERS: u8 = 0x04;
+const FC_WRITE_SINGLE_COIL: u8 = 0x05;
+const FC_WRITE_SINGLE_REGISTER: u8 = 0x06;
+const FC_WRITE_MULTIPLE_COILS: u8 = 0x0f;
+const FC_WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+const EXCEPTION_ILLEGAL_FUNCTION: u8 = 0x01;
+const EXCEPTION_ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+const EXCEPTION_ILLEGAL_DATA_VALUE: u8 = 0x03;
+
+/// The four tables of the Modbus data model
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Table {
+    Coils,
+    Discrete,
+    Input,
+    Holding,
+}
+
+/// A contiguous range in one table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Range {
+    pub table: Table,
+
+    /// Address of the first register or coil
+    pub start: u16,
+
+    /// Number of registers or coils
+    pub count: u16,
+}
+
+impl Table {
+    fn is_bits(&self) -> bool {
+        matches!(self, Table::Coils | Table::Discrete)
+    }
+
+    fn is_writable(&self) -> bool {
+        matches!(self, Table::Coils | Table::Holding)
+    }
+
+    fn read_function(&self) -> u8 {
+        match self {
+            Table::Coils => FC_READ_COILS,
+            Table::Discrete => FC_READ_DISCRETE_INPUTS,
+            Table::Input => FC_READ_INPUT_REGISTERS,
+            Table::Holding => FC_READ_HOLDING_REGISTERS,
+        }
+    }
+}
+
+impl Range {
+    /// Size of this range in a channel
+    pub fn byte_len(&self) -> usize {
+        if self.table.is_bits() {
+            (self.count as usize).div_ceil(8)
+        } else {
+            self.count as usize * 2
+        }
+    }
+
+    fn check_buf(&self, len: usize) -> Result<(), LwskError> {
+        if len != self.byte_len() {
+            error!(
+                "Modbus range of {} bytes does not match the channel of {len} bytes",
+                self.byte_len()
+            );
+            return Err(LwskError::BufferTooSmall {
+                expected: self.byte_len(),
+                got: len,
+            });
+        }
+        Ok(())
+    }
+
+    /// Address after the last register or coil, which may be just beyond the address space
+    fn end(&self) -> u32 {
+        self.start as u32 + self.count as u32
+    }
+
+    /// Check that this range is not empty and fits into the address space
+    fn check(&self) -> Result<(), LwskError> {
+        if self.count == 0 || self.end() > u16::MAX as u32 + 1 {
+            error!("Modbus range {self:?} is empty or exceeds the address space");
+            return Err(LwskError::IoChannelCreationError);
+        }
+        Ok(())
+    }
+
+    fn contains(&self, table: Table, start: u16, count: u16) -> bool {
+        self.table == table && start >= self.start && start as u32 + count as u32 <= self.end()
+    }
+}
+
+fn get_bit(bits: &[u8], idx: usize) -> bool {
+    bits[idx / 8] & (1 << (idx % 8)) != 0
+}
+
+fn set_bit(bits: &mut [u8], idx: usize, value: bool) {
+    if value {
+        bits[idx / 8] |= 1 << (idx % 8);
+    } else {
+        bits[idx / 8] &= !(1 << (idx % 8));
+    }
+}
+
+/// Copy `count` bits from `src` starting at `src_idx` to `dst` starting at `dst_idx`
+fn copy_bits(src: &[u8], src_idx: usize, dst: &mut [u8], dst_idx: usize, count: usize) {
+    for i in 0..count {
+        set_bit(dst, dst_idx + i, get_bit(src, src_idx + i));
+    }
+}
+
+fn encode_adu(transaction_id: u16, unit_id: u8, pdu: &[u8]) -> Vec<u8> {
+    let mut adu = Vec::with_capacity(MBAP_LEN + pdu.len());
+    adu.extend_from_slice(&transaction_id.to_be_bytes());
+    adu.extend_from_slice(&0u16.to_be_bytes()); // protocol id
+    adu.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+    adu.push(unit_id);
+    adu.extend_from_slice(pdu);
+    adu
+}
+
+/// Parse the MBAP header, yielding transaction id, unit id and PDU length
+fn decode_mbap(header: &[u8]) -> Option<(u16, u8, usize)> {
+    let transaction_id = u16::from_be_bytes([header[0], header[1]]);
+    let protocol_id = u16::from_be_bytes([header[2], header[3]]);
+    let len = u16::from_be_bytes([header[4], header[5]]) as usize;
+    if protocol_id != 0 || len < 2 || len - 1 > MAX_PDU_LEN {
+        return None;
+    }
+    Some((transaction_id, header[6], len - 1))
+}
+
+/// Polls a range from a remote Modbus server
+pub struct Client {
+    address: SocketAddr,
+    unit_id: u8,
+    range: Range,
+    timeout: Duration,
+    stream: Option<TcpStream>,
+    transaction_id: u16,
+}
+
+impl Client {
+    pub fn new(
+        address: SocketAddr,
+        unit_id: u8,
+        range: Range,
+        timeout: Duration,
+    ) -> Result<Self, LwskError> {
+        range.check()?;
+        let max_count = if range.table.is_bits() { 1968 } else { 123 };
+        if range.count > max_count {
+            error!("can not transfer {} Modbus items at once", range.count);
+            return Err(LwskError::IoChannelCreationError);
+        }
+
+        Ok(Self {
+            address,
+            unit_id,
+            range,
+            timeout,
+            stream: None,
+            transaction_id: 0,
+        })
+    }
+
+    fn connect(&mut self) -> std::io::Result<&mut TcpStream> {
+        if self.stream.is_none() {
+            debug!("connecting to Modbus server {}", self.address);
+            let stream = TcpStream::connect_timeout(&self.address, self.timeout)?;
+            stream.set_read_timeout(Some(self.timeout))?;
+            stream.set_write_timeout(Some(self.timeout))?;
+            stream.set_nodelay(true)?;
+            self.stream = Some(stream);
+        }
+        Ok(self.stream.as_mut().unwrap())
+    }
+
+    /// Send `pdu` and wait for the matching response PDU
+    fn transact(&mut self, pdu: &[u8]) -> Result<Vec<u8>, LwskError> {
+        self.transaction_id = self.transaction_id.wrapping_add(1);
+        let transaction_id = self.transaction_id;
+        let adu = encode_adu(transaction_id, self.unit_id, pdu);
+
+        let result = (|| {
+            let stream = self.connect()?;
+            stream.write_all(&adu)?;
+            loop {
+                let mut header = [0u8; MBAP_LEN];
+                stream.read_exact(&mut header)?;
+                let (response_id, _, len) = decode_mbap(&header)
+                    .ok_or_else(|| std::io::Error::from(ErrorKind::InvalidData))?;
+                let mut response = vec![0u8; len];
+                stream.read_exact(&mut response)?;
+                if response_id == transaction_id {
+                    return Ok(response);
+                }
+                debug!("skipping stale Modbus response {response_id}");
+            }
+        })();
+
+        let response = result.map_err(|e: std::io::Error| {
+            error!("Modbus transaction with {} failed: {e}", self.address);
+            // reconnect on the next transaction
+            self.stream = None;
+            LwskError::DriverError(e.into())
+        })?;
+
+        match response.first() {
+            Some(fc) if *fc == pdu[0] => Ok(response),
+            Some(fc) if *fc == pdu[0] | 0x80 => {
+                let code = response.get(1).copied().unwrap_or_default();
+                error!(
+                    "Modbus server {} responded with exception {code}",
+                    self.address
+                );
+                Err(DriverError::Protocol(format!("Modbus exception {code}")).into())
+            }
+            _ => {
+                error!("unexpected Modbus response from {}", self.address);
+                Err(DriverError::Protocol("unexpected Modbus response".into()).into())
+            }
+        }
+    }
+}
+
+impl IoDriver for Client {
+    fn connection(&self) -> ConnectionState {
+        match self.stream {
+            Some(_) => ConnectionState::Connected,
+            None => ConnectionState::Disconnected,
+        }
+    }
+
+    fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+        self.range.check_buf(buf.len())?;
+
+        let mut pdu = vec![self.range.table.read_function()];
+        pdu.extend_from_slice(&self.range.start.to_be_bytes());
+        pdu.extend_from_slice(&self.range.count.to_be_bytes());
+        let response = self.transact(&pdu)?;
+
+        let data = response.get(2..).unwrap_or_default();
+        if response.get(1).map(|n| *n as usize) != Some(buf.len()) || data.len() != buf.len() {
+            error!(
+                "Modbus response from {} has unexpected length",
+                self.address
+            );
+            return Err(
+                DriverError::Protocol("Modbus response of unexpected length".into()).into(),
+            );
+        }
+        buf.copy_from_slice(data);
+        debug!("received {} bytes from Modbus", buf.len());
+
+        Ok(Some(buf.len()))
+    }
+
+    fn push(&mut self, buf: &[u8]) -> Result<(), LwskError> {
+        self.range.check_buf(buf.len())?;
+
+        let function = match self.range.table {
+            Table::Coils => FC_WRITE_MULTIPLE_COILS,
+            Table::Holding => FC_WRITE_MULTIPLE_REGISTERS,
+            table => {
+                error!("Modbus {table:?} are read-only");
+                return Err(DriverError::Unsupported.into());
+            }
+        };
+
+        let mut pdu = vec![function];
+        pdu.extend_from_slice(&self.range.start.to_be_bytes());
+        pdu.extend_from_slice(&self.range.count.to_be_bytes());
+        pdu.push(buf.len() as u8);
+        pdu.extend_from_slice(buf);
+        self.transact(&pdu)?;
+        debug!("wrote {} bytes to Modbus", buf.len());
+
+        Ok(())
+    }
+}
+
+/// A region of the register map of a [ServerState], backed by one binding
+struct Region {
+    range: Range,
+    data: Vec<u8>,
+
+    /// Whether a client wrote to this region since the last pull
+    written: bool,
+}
+
+/// A connection to a client
+struct Connection {
+    stream: TcpStream,
+    peer: SocketAddr,
+    rx_buf: Vec<u8>,
+}
+
+/// Register map and connections of a Modbus server
+pub struct ServerState {
+    listener: TcpListener,
+    connections: Vec<Connection>,
+    regions: Vec<Region>,
+}
+
+impl ServerState {
+    /// Listen on `address` for clients
+    pub fn bind(address: SocketAddr) -> Result<Self, LwskError> {
+        let listener = TcpListener::bind(address)
+            .inspect_err(|e| error!("could not listen on {address}: {e}"))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            connections: Vec::new(),
+            regions: Vec::new(),
+        })
+    }
+
+    /// Add `range` to the register map, returning the index of the new region
+    fn add_region(&mut self, range: Range) -> Result<usize, LwskError> {
+        if self.regions.iter().any(|region| {
+            region.range.table == range.table
+                && (range.start as u32) < region.range.end()
+                && (region.range.start as u32) < range.end()
+        }) {
+            error!("Modbus range {range:?} overlaps with another one");
+            return Err(LwskError::IoChannelCreationError);
+        }
+
+        self.regions.push(Region {
+            range,
+            data: vec![0u8; range.byte_len()],
+            written: false,
+        });
+        Ok(self.regions.len() - 1)
+    }
+
+    /// Accept new clients and answer all complete requests
+    fn serve(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, peer)) => {
+                    debug!("Modbus client {peer} connected");
+                    if let Err(e) = stream.set_nonblocking(true) {
+                        warn!("dropping Modbus client {peer}: {e}");
+                        continue;
+                    }
+                    let _ = stream.set_nodelay(true);
+                    self.connections.push(Connection {
+                        stream,
+                        peer,
+                        rx_buf: Vec::new(),
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("could not accept Modbus client: {e}");
+                    break;
+                }
+            }
+        }
+
+        let mut connections = core::mem::take(&mut self.connections);
+        connections.retain_mut(|connection| match self.serve_connection(connection) {
+            Ok(open) => open,
+            Err(e) => {
+                warn!("dropping Modbus client {}: {e}", connection.peer);
+                false
+            }
+        });
+        self.connections = connections;
+    }
+
+    /// Answer all complete requests of a client, returns whether the connection is still open
+    fn serve_connection(&mut self, connection: &mut Connection) -> std::io::Result<bool> {
+        let mut chunk = [0u8; MBAP_LEN + MAX_PDU_LEN];
+        loop {
+            match connection.stream.read(&mut chunk) {
+                Ok(0) => {
+                    debug!("Modbus client {} disconnected", connection.peer);
+                    return Ok(false);
+                }
+                Ok(n) => connection.rx_buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        while connection.rx_buf.len() >= MBAP_LEN {
+            let (transaction_id, unit_id, len) = decode_mbap(&connection.rx_buf)
+                .ok_or_else(|| std::io::Error::from(ErrorKind::InvalidData))?;
+            if connection.rx_buf.len() < MBAP_LEN + len {
+                break;
+            }
+
+            let request: Vec<u8> = connection
+                .rx_buf
+                .drain(..MBAP_LEN + len)
+                .skip(MBAP_LEN)
+                .collect();
+            let response = self.handle(&request);
+            connection
+                .stream
+                .write_all(&encode_adu(transaction_id, unit_id, &response))?;
+        }
+
+        Ok(true)
+    }
+
+    /// Handle a request PDU, returning the response PDU
+    fn handle(&mut self, request: &[u8]) -> Vec<u8> {
+        let function = request[0];
+        let exception = |code: u8| vec![function | 0x80, code];
+        let word = |idx: usize| {
+            request
+                .get(idx..idx + 2)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        };
+        let (Some(address), Some(value)) = (word(1), word(3)) else {
+            return exception(EXCEPTION_ILLEGAL_DATA_VALUE);
+        };
+
+        let (table, count) = match function {
+            FC_READ_COILS => (Table::Coils, value),
+            FC_READ_DISCRETE_INPUTS => (Table::Discrete, value),
+            FC_READ_HOLDING_REGISTERS => (Table::Holding, value),
+            FC_READ_INPUT_REGISTERS => (Table::Input, value),
+            FC_WRITE_SINGLE_COIL | FC_WRITE_MULTIPLE_COILS => (Table::Coils, value),
+            FC_WRITE_SINGLE_REGISTER | FC_WRITE_MULTIPLE_REGISTERS => (Table::Holding, value),
+            _ => return exception(EXCEPTION_ILLEGAL_FUNCTION),
+        };
+        let count = match function {
+            FC_WRITE_SINGLE_COIL | FC_WRITE_SINGLE_REGISTER => 1,
+            _ => count,
+        };
+        if count == 0 {
+            return exception(EXCEPTION_ILLEGAL_DATA_VALUE);
+        }
+
+        let Some(region) = self
+            .regions
+            .iter_mut()
+            .find(|region| region.range.contains(table, address, count))
+        else {
+            return exception(EXCEPTION_ILLEGAL_DATA_ADDRESS);
+        };
+        let offset = (address - region.range.start) as usize;
+
+        match function {
+            FC_READ_COILS | FC_READ_DISCRETE_INPUTS => {
+                let mut bits = vec![0u8; (count as usize).div_ceil(8)];
+                copy_bits(&region.data, offset, &mut bits, 0, count as usize);
+                let mut response = vec![function, bits.len() as u8];
+                response.extend_from_slice(&bits);
+                response
+            }
+            FC_READ_HOLDING_REGISTERS | FC_READ_INPUT_REGISTERS => {
+                let words = &region.data[offset * 2..(offset + count as usize) * 2];
+                let mut response = vec![function, words.len() as u8];
+                response.extend_from_slice(words);
+                response
+            }
+            FC_WRITE_SINGLE_COIL => {
+                let bit = match value {
+                    0xff00 => true,
+                    0x0000 => false,
+                    _ => return exception(EXCEPTION_ILLEGAL_DATA_VALUE),
+                };
+                set_bit(&mut region.data, offset, bit);
+                region.written = true;
+                request.to_vec()
+            }
+            FC_WRITE_SINGLE_REGISTER => {
+                region.data[offset * 2..offset * 2 + 2].copy_from_slice(&value.to_be_bytes());
+                region.written = true;
+                request.to_vec()
+            }
+            FC_WRITE_MULTIPLE_COILS | FC_WRITE_MULTIPLE_REGISTERS => {
+                let data = request.get(6..).unwrap_or_default();
+                let expected = if table.is_bits() {
+                    (count as usize).div_ceil(8)
+                } else {
+                    count as usize * 2
+                };
+                if request.get(5).map(|n| *n as usize) != Some(expected) || data.len() != expected {
+                    return exception(EXCEPTION_ILLEGAL_DATA_VALUE);
+                }
+
+                if table.is_bits() {
+                    copy_bits(data, 0, &mut region.data, offset, count as usize);
+                } else {
+                    region.data[offset * 2..(offset + count as usize) * 2].copy_from_slice(data);
+                }
+                region.written = true;
+                request[..5].to_vec()
+            }
+            _ => exception(EXCEPTION_ILLEGAL_FUNCTION),
+        }
+    }
+}
+
+/// Exposes a range in the register map of a shared [ServerState]
+pub struct Server {
+    state: Rc<RefCell<ServerState>>,
+    region_idx: usize,
+}
+
+impl Server {
+    /// Expose `range` in the register map of `state`
+    pub fn new(state: Rc<RefCell<ServerState>>, range: Range) -> Result<Self, LwskError> {
+        range.check()?;
+        let region_idx = state.borrow_mut().add_region(range)?;
+        Ok(Self { state, region_idx })
+    }
+}
+
+impl IoDriver for Server {
+    fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+        let mut state = self.state.borrow_mut();
+        state.serve();
+
+        let region = &mut state.regions[self.region_idx];
+        region.range.check_buf(buf.len())?;
+        if !region.range.table.is_writable() || !region.written {
+            debug!("no new data written by Modbus clients");
+            return Ok(None);
+        }
+
+        buf.copy_from_slice(&region.data);
+        region.written = false;
+        debug!("received {} bytes from Modbus clients", buf.len());
+
+        Ok(Some(buf.len()))
+    }
+
+    fn push(&mut self, buf: &[u8]) -> Result<(), LwskError> {
+        let mut state = self.state.borrow_mut();
+
+        let region = &mut state.regions[self.region_idx];
+        region.range.check_buf(buf.len())?;
+        region.data.copy_from_slice(buf);
+        debug!("exposed {} bytes to Modbus clients", buf.len());
+
+        state.serve();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(ranges: &[Range]) -> (SocketAddr, Vec<Server>) {
+        let state = ServerState::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = state.listener.local_addr().unwrap();
+        let state = Rc::new(RefCell::new(state));
+        let servers = ranges
+            .iter()
+            .map(|range| Server::new(state.clone(), *range).unwrap())
+            .collect();
+        (address, servers)
+    }
+
+    /// Serve via `server` until `client` is done, returning its result and the last pulled data
+    fn serve<T: Send + 'static>(
+        server: &mut Server,
+        len: usize,
+        client: impl FnOnce() -> T + Send + 'static,
+    ) -> (T, Option<Vec<u8>>) {
+        let client = std::thread::spawn(client);
+        let mut pulled = None;
+        while !client.is_finished() {
+            let mut buf = vec![0u8; len];
+            if server.pull(&mut buf).unwrap().is_some() {
+                pulled = Some(buf);
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let mut buf = vec![0u8; len];
+        if server.pull(&mut buf).unwrap().is_some() {
+            pulled = Some(buf);
+        }
+        (client.join().unwrap(), pulled)
+    }
+
+    #[test]
+    fn client_reads_and_writes_registers_of_server() {
+        let holding = Range {
+            table: Table::Holding,
+            start: 100,
+            count: 2,
+        };
+        let (address, mut servers) = server(&[holding]);
+        let server = &mut servers[0];
+        server.push(&[0x12, 0x34, 0x56, 0x78]).unwrap();
+
+        let (read, written) = serve(server, 4, move || {
+            let mut client = Client::new(address, 1, holding, Duration::from_secs(1)).unwrap();
+            let mut buf = [0u8; 4];
+            client.pull(&mut buf).unwrap();
+            client.push(&[0xab, 0xcd, 0x00, 0x01]).unwrap();
+            buf
+        });
+
+        assert_eq!(read, [0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(written, Some(vec![0xab, 0xcd, 0x00, 0x01]));
+    }
+
+    #[test]
+    fn client_writes_coils_of_server() {
+        let coils = Range {
+            table: Table::Coils,
+            start: 0,
+            count: 10,
+        };
+        let (address, mut servers) = server(&[coils]);
+
+        let (_, written) = serve(&mut servers[0], 2, move || {
+            let mut client = Client::new(address, 1, coils, Duration::from_secs(1)).unwrap();
+            client.push(&[0b1000_0001, 0b10]).unwrap();
+        });
+
+        assert_eq!(written, Some(vec![0b1000_0001, 0b10]));
+    }
+
+    #[test]
+    fn server_answers_reads_outside_of_its_ranges_with_exception() {
+        let input = Range {
+            table: Table::Input,
+            start: 0,
+            count: 1,
+        };
+        let (address, mut servers) = server(&[input]);
+
+        let outside = Range { start: 1, ..input };
+        let (result, _) = serve(&mut servers[0], 2, move || {
+            let mut client = Client::new(address, 1, outside, Duration::from_secs(1)).unwrap();
+            client.pull(&mut [0u8; 2])
+        });
+
+        assert!(matches!(
+            result,
+            Err(LwskError::DriverError(DriverError::Protocol(_)))
+        ));
+    }
+
+    #[test]
+    fn ranges_at_the_end_of_the_address_space() {
+        let last = Range {
+            table: Table::Holding,
+            start: u16::MAX,
+            count: 1,
+        };
+        let before = Range {
+            start: u16::MAX - 1,
+            ..last
+        };
+        let (_, servers) = server(&[last, before]);
+        assert_eq!(servers.len(), 2);
+
+        let state = Rc::new(RefCell::new(
+            ServerState::bind("127.0.0.1:0".parse().unwrap()).unwrap(),
+        ));
+        let beyond = Range { count: 2, ..last };
+        assert!(Server::new(state.clone(), beyond).is_err());
+        let empty = Range { count: 0, ..last };
+        assert!(Server::new(state.clone(), empty).is_err());
+        Server::new(state.clone(), last).unwrap();
+        assert!(Server::new(state, last).is_err());
+    }
+}
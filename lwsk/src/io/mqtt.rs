@@ -0,0 +1,1042 @@
+//! MQTT 3.1.1 driver
+//!
+//! Pushed channel contents are published to a topic, and the payload of the latest message
+//! received on the subscribed topic filter is yielded on pull. The client is entirely
+//! non-blocking: connecting, sending and receiving only ever progress as far as possible without
+//! waiting, so a slow or unreachable broker never stalls the schedule. Lost connections are
+//! re-established after [Options::reconnect], unacknowledged QoS 1 messages are then resent.
+
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use super::{ConnectionState, IoDriver};
+use crate::LwskError;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const SUBSCRIBE: u8 = 0x82;
+const SUBACK: u8 = 0x90;
+const PINGREQ: u8 = 0xc0;
+const PINGRESP: u8 = 0xd0;
+const DISCONNECT: u8 = 0xe0;
+
+/// Flag of a resent PUBLISH
+const PUBLISH_DUP: u8 = 0x08;
+
+/// Flag of a retained PUBLISH
+const PUBLISH_RETAIN: u8 = 0x01;
+
+/// Largest packet accepted from the broker
+const MAX_PACKET_LEN: usize = 1 << 20;
+
+/// Largest amount of unsent data, beyond which QoS 0 messages are dropped
+const MAX_TX_BUF_LEN: usize = 1 << 16;
+
+/// Largest number of unacknowledged QoS 1 messages, beyond which the oldest one is dropped
+const MAX_INFLIGHT: usize = 16;
+
+/// Source of unique default client ids
+static CLIENT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Delivery guarantee of a message
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum Qos {
+    AtMostOnce = 0,
+    AtLeastOnce = 1,
+}
+
+impl TryFrom<u8> for Qos {
+    type Error = String;
+
+    fn try_from(qos: u8) -> Result<Self, Self::Error> {
+        match qos {
+            0 => Ok(Qos::AtMostOnce),
+            1 => Ok(Qos::AtLeastOnce),
+            _ => Err(format!("unsupported QoS {qos}, expected 0 or 1")),
+        }
+    }
+}
+
+impl From<Qos> for u8 {
+    fn from(qos: Qos) -> Self {
+        qos as u8
+    }
+}
+
+impl schemars::JsonSchema for Qos {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "Qos".into()
+    }
+
+    fn json_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        schemars::json_schema!({
+            "description": "Delivery guarantee of a message: 0 at most once, 1 at least once",
+            "type": "integer",
+            "enum": [0, 1],
+        })
+    }
+}
+
+/// A message published on behalf of the client when it disconnects ungracefully
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Will {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: Qos,
+    pub retain: bool,
+}
+
+/// Configuration of an [Mqtt] driver
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Client identifier, must be unique per broker
+    pub client_id: Option<String>,
+
+    /// Topic filter to subscribe to, if any
+    pub subscribe: Option<String>,
+
+    /// Topic to publish pushed data to, if any
+    pub publish: Option<String>,
+
+    /// QoS of the subscription and of published messages
+    pub qos: Qos,
+
+    /// Whether the broker retains published messages for future subscribers
+    pub retain: bool,
+
+    /// Whether the broker discards the session when connecting
+    pub clean_session: bool,
+
+    /// Maximum interval between two packets sent to the broker
+    pub keep_alive: Duration,
+
+    /// Time to wait before reconnecting after the connection was lost
+    pub reconnect: Duration,
+
+    pub will: Option<Will>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting to reconnect
+    Disconnected {
+        since: Instant,
+    },
+
+    /// TCP connection is being established
+    Connecting,
+
+    /// CONNECT was sent, waiting for CONNACK
+    AwaitConnack,
+
+    Connected,
+}
+
+pub struct Mqtt {
+    broker: SocketAddr,
+    client_id: String,
+    options: Options,
+    state: State,
+    stream: Option<TcpStream>,
+
+    /// Encoded packets not yet written to the stream
+    tx_buf: Vec<u8>,
+
+    /// Received bytes not yet parsed
+    rx_buf: Vec<u8>,
+
+    /// Unacknowledged QoS 1 PUBLISH packets by packet id
+    inflight: BTreeMap<u16, Vec<u8>>,
+
+    /// Identifier of the next packet requiring one
+    packet_id: u16,
+
+    /// Payload of the latest message on the subscribed topic, not yet pulled
+    latest: Option<Vec<u8>>,
+
+    last_tx: Instant,
+    last_rx: Instant,
+}
+
+/// Append the variable length encoding of `len`
+fn encode_remaining_len(mut len: usize, packet: &mut Vec<u8>) {
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+}
+
+/// Decode a remaining length, yielding it and the number of bytes it occupied
+///
+/// Returns `Ok(None)` if more data is needed.
+fn decode_remaining_len(bytes: &[u8]) -> Result<Option<(usize, usize)>, ()> {
+    let mut len = 0;
+    for (idx, byte) in bytes.iter().enumerate().take(4) {
+        len |= ((byte & 0x7f) as usize) << (7 * idx);
+        if byte & 0x80 == 0 {
+            return Ok(Some((len, idx + 1)));
+        }
+    }
+    if bytes.len() >= 4 {
+        return Err(());
+    }
+    Ok(None)
+}
+
+fn encode_str(s: &[u8], packet: &mut Vec<u8>) {
+    packet.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    packet.extend_from_slice(s);
+}
+
+fn encode_packet(first_byte: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![first_byte];
+    encode_remaining_len(body.len(), &mut packet);
+    packet.extend_from_slice(body);
+    packet
+}
+
+impl Mqtt {
+    pub fn new(broker: SocketAddr, options: Options) -> Result<Self, LwskError> {
+        for topic in [&options.publish, &options.subscribe]
+            .into_iter()
+            .flatten()
+            .chain(options.will.as_ref().map(|will| &will.topic))
+        {
+            if topic.is_empty() || topic.len() > u16::MAX as usize {
+                error!("invalid MQTT topic {topic:?}");
+                return Err(LwskError::IoChannelCreationError);
+            }
+        }
+        if options
+            .publish
+            .as_ref()
+            .is_some_and(|t| t.contains(['+', '#']))
+        {
+            error!(
+                "can not publish to the MQTT topic filter {:?}",
+                options.publish
+            );
+            return Err(LwskError::IoChannelCreationError);
+        }
+
+        let client_id = options.client_id.clone().unwrap_or_else(|| {
+            format!(
+                "lwsk-{}-{}",
+                std::process::id(),
+                CLIENT_COUNT.fetch_add(1, Ordering::Relaxed)
+            )
+        });
+
+        let now = Instant::now();
+        let mut mqtt = Self {
+            broker,
+            client_id,
+            options,
+            state: State::Disconnected { since: now },
+            stream: None,
+            tx_buf: Vec::new(),
+            rx_buf: Vec::new(),
+            inflight: BTreeMap::new(),
+            packet_id: 0,
+            latest: None,
+            last_tx: now,
+            last_rx: now,
+        };
+        mqtt.connect();
+        Ok(mqtt)
+    }
+
+    fn next_packet_id(&mut self) -> u16 {
+        // zero is not a valid packet id
+        self.packet_id = self.packet_id.checked_add(1).unwrap_or(1);
+        self.packet_id
+    }
+
+    /// Start connecting to the broker without waiting for the connection to be established
+    fn connect(&mut self) {
+        debug!("connecting to MQTT broker {}", self.broker);
+        let result = (|| {
+            let socket = Socket::new(
+                Domain::for_address(self.broker),
+                Type::STREAM,
+                Some(Protocol::TCP),
+            )?;
+            socket.set_nonblocking(true)?;
+            socket.set_nodelay(true)?;
+            match socket.connect(&self.broker.into()) {
+                Ok(()) => {}
+                Err(e) if e.raw_os_error() == Some(libc::EINPROGRESS) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+            Ok(TcpStream::from(socket))
+        })();
+
+        match result {
+            Ok(stream) => {
+                self.stream = Some(stream);
+                self.state = State::Connecting;
+            }
+            Err(e) => self.disconnect(&format!("could not connect: {e}")),
+        }
+    }
+
+    /// Drop the connection, reconnecting after [Options::reconnect]
+    fn disconnect(&mut self, reason: &str) {
+        warn!("lost connection to MQTT broker {}: {reason}", self.broker);
+        self.stream = None;
+        self.tx_buf.clear();
+        self.rx_buf.clear();
+        self.state = State::Disconnected {
+            since: Instant::now(),
+        };
+    }
+
+    fn send(&mut self, packet: &[u8]) {
+        self.tx_buf.extend_from_slice(packet);
+        self.last_tx = Instant::now();
+    }
+
+    fn send_connect(&mut self) {
+        let options = &self.options;
+        let mut flags = 0u8;
+        if options.clean_session {
+            flags |= 0x02;
+        }
+        if let Some(will) = &options.will {
+            flags |= 0x04 | (u8::from(will.qos) << 3);
+            if will.retain {
+                flags |= 0x20;
+            }
+        }
+        if options.password.is_some() {
+            flags |= 0x40;
+        }
+        if options.username.is_some() {
+            flags |= 0x80;
+        }
+
+        let mut body = Vec::new();
+        encode_str(b"MQTT", &mut body);
+        body.push(4); // protocol level of 3.1.1
+        body.push(flags);
+        body.extend_from_slice(
+            &(options.keep_alive.as_secs().min(u16::MAX as u64) as u16).to_be_bytes(),
+        );
+        encode_str(self.client_id.as_bytes(), &mut body);
+        if let Some(will) = &options.will {
+            encode_str(will.topic.as_bytes(), &mut body);
+            encode_str(&will.payload, &mut body);
+        }
+        if let Some(username) = &options.username {
+            encode_str(username.as_bytes(), &mut body);
+        }
+        if let Some(password) = &options.password {
+            encode_str(password.as_bytes(), &mut body);
+        }
+
+        self.send(&encode_packet(CONNECT, &body));
+        self.state = State::AwaitConnack;
+        self.last_rx = Instant::now();
+    }
+
+    /// Subscribe and resend unacknowledged messages once the broker accepted the connection
+    fn on_connected(&mut self) {
+        info!("connected to MQTT broker {}", self.broker);
+        self.state = State::Connected;
+
+        if let Some(filter) = self.options.subscribe.clone() {
+            let mut body = Vec::new();
+            body.extend_from_slice(&self.next_packet_id().to_be_bytes());
+            encode_str(filter.as_bytes(), &mut body);
+            body.push(self.options.qos.into());
+            self.send(&encode_packet(SUBSCRIBE, &body));
+        }
+
+        let inflight: Vec<_> = self
+            .inflight
+            .values_mut()
+            .map(|packet| {
+                packet[0] |= PUBLISH_DUP;
+                packet.clone()
+            })
+            .collect();
+        for packet in inflight {
+            self.send(&packet);
+        }
+    }
+
+    /// Make as much progress as possible without blocking
+    fn service(&mut self) {
+        match self.state {
+            State::Disconnected { since } => {
+                if since.elapsed() >= self.options.reconnect {
+                    self.connect();
+                }
+                return;
+            }
+            State::Connecting => {
+                let stream = self.stream.as_ref().unwrap();
+                match stream.take_error() {
+                    Ok(None) => {}
+                    Ok(Some(e)) | Err(e) => return self.disconnect(&format!("{e}")),
+                }
+                if stream.peer_addr().is_err() {
+                    // not yet established
+                    return;
+                }
+                self.send_connect();
+            }
+            State::AwaitConnack | State::Connected => {}
+        }
+
+        if let Err(e) = self.flush().and_then(|()| self.receive()) {
+            return self.disconnect(&format!("{e}"));
+        }
+        if let Err(reason) = self.process() {
+            return self.disconnect(&reason);
+        }
+
+        let keep_alive = self.options.keep_alive;
+        if self.state == State::Connected
+            && !keep_alive.is_zero()
+            && self.last_tx.elapsed() >= keep_alive
+        {
+            self.send(&[PINGREQ, 0]);
+        }
+        if !keep_alive.is_zero() && self.last_rx.elapsed() >= keep_alive * 3 / 2 {
+            return self.disconnect("broker is not responding");
+        }
+
+        if let Err(e) = self.flush() {
+            self.disconnect(&format!("{e}"));
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let stream = self.stream.as_mut().unwrap();
+        while !self.tx_buf.is_empty() {
+            match stream.write(&self.tx_buf) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => drop(self.tx_buf.drain(..n)),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self) -> std::io::Result<()> {
+        let stream = self.stream.as_mut().unwrap();
+        let mut chunk = [0u8; 4096];
+        loop {
+            match stream.read(&mut chunk) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    self.rx_buf.extend_from_slice(&chunk[..n]);
+                    self.last_rx = Instant::now();
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Handle all complete packets in the receive buffer
+    fn process(&mut self) -> Result<(), String> {
+        while let Some(first_byte) = self.rx_buf.first().copied() {
+            let Some((len, len_size)) = decode_remaining_len(&self.rx_buf[1..])
+                .map_err(|()| "malformed remaining length".to_string())?
+            else {
+                break;
+            };
+            if len > MAX_PACKET_LEN {
+                return Err(format!("packet of {len} bytes is too large"));
+            }
+            if self.rx_buf.len() < 1 + len_size + len {
+                break;
+            }
+
+            let body: Vec<u8> = self
+                .rx_buf
+                .drain(..1 + len_size + len)
+                .skip(1 + len_size)
+                .collect();
+            self.handle(first_byte, &body)?;
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, first_byte: u8, body: &[u8]) -> Result<(), String> {
+        let word = |idx: usize| {
+            body.get(idx..idx + 2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                .ok_or_else(|| format!("truncated packet {first_byte:#04x}"))
+        };
+
+        match (first_byte & 0xf0, self.state) {
+            (CONNACK, State::AwaitConnack) => match body.get(1) {
+                Some(0) => self.on_connected(),
+                code => return Err(format!("broker refused the connection with {code:?}")),
+            },
+            (PUBLISH, State::Connected) => {
+                let qos = (first_byte >> 1) & 0b11;
+                let topic_len = word(0)? as usize;
+                let mut offset = 2 + topic_len;
+                if qos > 0 {
+                    let packet_id = word(offset)?;
+                    offset += 2;
+                    let mut ack = vec![PUBACK, 2];
+                    ack.extend_from_slice(&packet_id.to_be_bytes());
+                    self.send(&ack);
+                }
+                let payload = body
+                    .get(offset..)
+                    .ok_or_else(|| "truncated PUBLISH".to_string())?;
+                trace!(
+                    "received MQTT message on {:?}",
+                    String::from_utf8_lossy(&body[2..2 + topic_len])
+                );
+                if self.latest.replace(payload.to_vec()).is_some() {
+                    debug!("overwriting unread MQTT message");
+                }
+            }
+            (PUBACK, State::Connected) => {
+                if self.inflight.remove(&word(0)?).is_none() {
+                    debug!("ignoring PUBACK of unknown packet");
+                }
+            }
+            (SUBACK, State::Connected) => {
+                if body.get(2) == Some(&0x80) {
+                    error!(
+                        "MQTT broker {} rejected the subscription to {:?}",
+                        self.broker, self.options.subscribe
+                    );
+                }
+            }
+            (PINGRESP, _) => {}
+            _ => return Err(format!("unexpected packet {first_byte:#04x}")),
+        }
+        Ok(())
+    }
+}
+
+impl IoDriver for Mqtt {
+    fn connection(&self) -> ConnectionState {
+        match self.state {
+            State::Disconnected { .. } => ConnectionState::Disconnected,
+            State::Connecting | State::AwaitConnack => ConnectionState::Connecting,
+            State::Connected => ConnectionState::Connected,
+        }
+    }
+
+    fn readiness_fd(&self) -> Option<i32> {
+        use std::os::fd::AsRawFd;
+
+        // while not connected, pulls are needed to make progress
+        match (&self.state, &self.stream) {
+            (State::Connected, Some(stream)) => Some(stream.as_raw_fd()),
+            _ => None,
+        }
+    }
+
+    fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+        self.service();
+
+        let Some(payload) = self.latest.take() else {
+            debug!("no new MQTT message");
+            return Ok(None);
+        };
+        if payload.len() != buf.len() {
+            warn!(
+                "MQTT message carries {} bytes, but the channel has {} bytes",
+                payload.len(),
+                buf.len()
+            );
+        }
+        let n = payload.len().min(buf.len());
+        buf[..n].copy_from_slice(&payload[..n]);
+        debug!("received {n} bytes via MQTT");
+
+        Ok(Some(n))
+    }
+
+    fn push(&mut self, buf: &[u8]) -> Result<(), LwskError> {
+        let Some(topic) = self.options.publish.clone() else {
+            return Ok(());
+        };
+
+        let mut first_byte = PUBLISH | (u8::from(self.options.qos) << 1);
+        if self.options.retain {
+            first_byte |= PUBLISH_RETAIN;
+        }
+        let mut body = Vec::with_capacity(topic.len() + buf.len() + 4);
+        encode_str(topic.as_bytes(), &mut body);
+        let packet_id = (self.options.qos == Qos::AtLeastOnce).then(|| self.next_packet_id());
+        if let Some(packet_id) = packet_id {
+            body.extend_from_slice(&packet_id.to_be_bytes());
+        }
+        body.extend_from_slice(buf);
+        let packet = encode_packet(first_byte, &body);
+
+        let connected = self.state == State::Connected;
+        if let Some(packet_id) = packet_id {
+            // resent on reconnection until acknowledged
+            if self.inflight.len() >= MAX_INFLIGHT {
+                warn!("dropping unacknowledged MQTT message");
+                self.inflight.pop_first();
+            }
+            self.inflight.insert(packet_id, packet.clone());
+        }
+
+        if !connected {
+            debug!(
+                "not connected to MQTT broker, deferring {} bytes",
+                buf.len()
+            );
+        } else if packet_id.is_none() && self.tx_buf.len() + packet.len() > MAX_TX_BUF_LEN {
+            warn!("MQTT broker {} is congested, dropping message", self.broker);
+        } else {
+            self.send(&packet);
+            debug!("published {} bytes via MQTT", buf.len());
+        }
+
+        self.service();
+        Ok(())
+    }
+}
+
+impl Drop for Mqtt {
+    fn drop(&mut self) {
+        // a graceful disconnect keeps the broker from publishing the will
+        if self.state == State::Connected {
+            self.send(&[DISCONNECT, 0]);
+            let _ = self.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::thread::JoinHandle;
+
+    /// In-process stand-in for an MQTT broker, supporting just what the driver uses
+    ///
+    /// Messages are forwarded with QoS 0, topic filters are matched exactly or by a trailing `#`.
+    struct Broker {
+        address: SocketAddr,
+        stop: Arc<AtomicBool>,
+
+        /// Number of subscriptions made so far
+        subscriptions: Arc<AtomicUsize>,
+
+        thread: Option<JoinHandle<()>>,
+    }
+
+    struct Session {
+        stream: TcpStream,
+        rx_buf: Vec<u8>,
+        filters: Vec<String>,
+        will: Option<(String, Vec<u8>)>,
+        closed: bool,
+        graceful: bool,
+    }
+
+    fn matches(filter: &str, topic: &str) -> bool {
+        match filter.strip_suffix('#') {
+            Some(prefix) => topic.starts_with(prefix),
+            None => filter == topic,
+        }
+    }
+
+    /// Split a string off the front of `body`
+    fn take_str<'a>(body: &mut &'a [u8]) -> &'a [u8] {
+        let len = u16::from_be_bytes([body[0], body[1]]) as usize;
+        let s = &body[2..2 + len];
+        *body = &body[2 + len..];
+        s
+    }
+
+    fn publish_packet(topic: &str, payload: &[u8], retain: bool) -> Vec<u8> {
+        let mut body = Vec::new();
+        encode_str(topic.as_bytes(), &mut body);
+        body.extend_from_slice(payload);
+        let first_byte = if retain {
+            PUBLISH | PUBLISH_RETAIN
+        } else {
+            PUBLISH
+        };
+        encode_packet(first_byte, &body)
+    }
+
+    impl Session {
+        /// Handle all complete packets received, collecting messages to deliver
+        fn process(
+            &mut self,
+            retained: &mut BTreeMap<String, Vec<u8>>,
+            outgoing: &mut Vec<(String, Vec<u8>)>,
+            subscriptions: &AtomicUsize,
+        ) {
+            while let Some(Ok(Some((len, len_size)))) =
+                self.rx_buf.get(1..).map(decode_remaining_len)
+            {
+                if self.rx_buf.len() < 1 + len_size + len {
+                    break;
+                }
+                let first_byte = self.rx_buf[0];
+                let packet: Vec<u8> = self.rx_buf.drain(..1 + len_size + len).collect();
+                let mut body = &packet[1 + len_size..];
+
+                match first_byte & 0xf0 {
+                    CONNECT => {
+                        take_str(&mut body);
+                        let flags = body[1];
+                        body = &body[4..];
+                        take_str(&mut body);
+                        if flags & 0x04 != 0 {
+                            let topic = String::from_utf8(take_str(&mut body).to_vec()).unwrap();
+                            self.will = Some((topic, take_str(&mut body).to_vec()));
+                        }
+                        self.write(&[CONNACK, 2, 0, 0]);
+                    }
+                    _ if first_byte == SUBSCRIBE => {
+                        let packet_id = [body[0], body[1]];
+                        body = &body[2..];
+                        let filter = String::from_utf8(take_str(&mut body).to_vec()).unwrap();
+                        self.write(&[SUBACK, 3, packet_id[0], packet_id[1], body[0]]);
+                        for (topic, payload) in retained.iter() {
+                            if matches(&filter, topic) {
+                                self.write(&publish_packet(topic, payload, true));
+                            }
+                        }
+                        self.filters.push(filter);
+                        subscriptions.fetch_add(1, Ordering::Relaxed);
+                    }
+                    PUBLISH => {
+                        let topic = String::from_utf8(take_str(&mut body).to_vec()).unwrap();
+                        if (first_byte >> 1) & 0b11 > 0 {
+                            self.write(&[PUBACK, 2, body[0], body[1]]);
+                            body = &body[2..];
+                        }
+                        if first_byte & PUBLISH_RETAIN != 0 {
+                            retained.insert(topic.clone(), body.to_vec());
+                        }
+                        outgoing.push((topic, body.to_vec()));
+                    }
+                    PINGREQ => self.write(&[PINGRESP, 0]),
+                    DISCONNECT => {
+                        self.graceful = true;
+                        self.closed = true;
+                    }
+                    _ => panic!("unexpected packet {first_byte:#04x}"),
+                }
+            }
+        }
+
+        fn write(&mut self, packet: &[u8]) {
+            if self.stream.write_all(packet).is_err() {
+                self.closed = true;
+            }
+        }
+    }
+
+    impl Broker {
+        fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.set_nonblocking(true).unwrap();
+            let address = listener.local_addr().unwrap();
+            let stop = Arc::new(AtomicBool::new(false));
+            let subscriptions = Arc::new(AtomicUsize::new(0));
+
+            let thread = std::thread::spawn({
+                let stop = stop.clone();
+                let subscriptions = subscriptions.clone();
+                move || {
+                    let mut sessions: Vec<Session> = Vec::new();
+                    let mut retained = BTreeMap::new();
+                    while !stop.load(Ordering::Relaxed) {
+                        if let Ok((stream, _)) = listener.accept() {
+                            stream.set_nonblocking(true).unwrap();
+                            sessions.push(Session {
+                                stream,
+                                rx_buf: Vec::new(),
+                                filters: Vec::new(),
+                                will: None,
+                                closed: false,
+                                graceful: false,
+                            });
+                        }
+
+                        let mut outgoing = Vec::new();
+                        for session in &mut sessions {
+                            let mut chunk = [0u8; 4096];
+                            match session.stream.read(&mut chunk) {
+                                Ok(0) => session.closed = true,
+                                Ok(n) => session.rx_buf.extend_from_slice(&chunk[..n]),
+                                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                                Err(_) => session.closed = true,
+                            }
+                            session.process(&mut retained, &mut outgoing, &subscriptions);
+                        }
+
+                        for session in sessions.iter_mut().filter(|s| s.closed && !s.graceful) {
+                            outgoing.extend(session.will.take());
+                        }
+                        sessions.retain(|session| !session.closed);
+
+                        for (topic, payload) in outgoing {
+                            for session in &mut sessions {
+                                if session.filters.iter().any(|f| matches(f, &topic)) {
+                                    session.write(&publish_packet(&topic, &payload, false));
+                                }
+                            }
+                        }
+                        std::thread::sleep(Duration::from_millis(1));
+                    }
+                }
+            });
+
+            Self {
+                address,
+                stop,
+                subscriptions,
+                thread: Some(thread),
+            }
+        }
+
+        /// Whether the broker processed at least `count` subscriptions
+        fn subscribed(&self, count: usize) -> bool {
+            self.subscriptions.load(Ordering::Relaxed) >= count
+        }
+    }
+
+    impl Drop for Broker {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    fn options() -> Options {
+        Options {
+            client_id: None,
+            subscribe: None,
+            publish: None,
+            qos: Qos::AtMostOnce,
+            retain: false,
+            clean_session: true,
+            keep_alive: Duration::from_secs(60),
+            reconnect: Duration::from_millis(10),
+            will: None,
+            username: None,
+            password: None,
+        }
+    }
+
+    /// Service `clients` until `done` holds, failing after two seconds
+    fn until(clients: &mut [&mut Mqtt], mut done: impl FnMut(&mut [&mut Mqtt]) -> bool) {
+        let start = Instant::now();
+        while !done(clients) {
+            assert!(start.elapsed() < Duration::from_secs(2), "timed out");
+            for client in clients.iter_mut() {
+                client.service();
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn connected(clients: &mut [&mut Mqtt]) -> bool {
+        clients
+            .iter()
+            .all(|client| client.connection() == ConnectionState::Connected)
+    }
+
+    /// Pull from `client` until a message arrives
+    fn receive(client: &mut Mqtt) -> Vec<u8> {
+        let mut buf = [0u8; 4];
+        let mut received = None;
+        until(&mut [client], |clients| {
+            received = clients[0]
+                .pull(&mut buf)
+                .unwrap()
+                .map(|n| buf[..n].to_vec());
+            received.is_some()
+        });
+        received.unwrap()
+    }
+
+    #[test]
+    fn publish_and_subscribe() {
+        let broker = Broker::start();
+        let mut subscriber = Mqtt::new(
+            broker.address,
+            Options {
+                subscribe: Some("lwsk/#".into()),
+                ..options()
+            },
+        )
+        .unwrap();
+        let mut publisher = Mqtt::new(
+            broker.address,
+            Options {
+                publish: Some("lwsk/speed".into()),
+                ..options()
+            },
+        )
+        .unwrap();
+        until(&mut [&mut subscriber, &mut publisher], connected);
+        until(&mut [&mut subscriber], |_| broker.subscribed(1));
+
+        publisher.push(&[1, 2, 3, 4]).unwrap();
+        assert_eq!(receive(&mut subscriber), [1, 2, 3, 4]);
+        assert_eq!(subscriber.pull(&mut [0u8; 4]).unwrap(), None);
+    }
+
+    #[test]
+    fn unacknowledged_messages_are_resent_once_connected() {
+        let broker = Broker::start();
+        let mut subscriber = Mqtt::new(
+            broker.address,
+            Options {
+                subscribe: Some("lwsk/speed".into()),
+                ..options()
+            },
+        )
+        .unwrap();
+        until(&mut [&mut subscriber], connected);
+        until(&mut [&mut subscriber], |_| broker.subscribed(1));
+
+        let mut publisher = Mqtt::new(
+            broker.address,
+            Options {
+                publish: Some("lwsk/speed".into()),
+                qos: Qos::AtLeastOnce,
+                ..options()
+            },
+        )
+        .unwrap();
+        // not yet connected, deferred until the connection is established
+        publisher.push(&[5, 6, 7, 8]).unwrap();
+        assert_eq!(publisher.inflight.len(), 1);
+
+        until(&mut [&mut publisher], |clients| {
+            clients[0].inflight.is_empty()
+        });
+        assert_eq!(receive(&mut subscriber), [5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn retained_messages_reach_later_subscribers() {
+        let broker = Broker::start();
+        let mut publisher = Mqtt::new(
+            broker.address,
+            Options {
+                publish: Some("lwsk/mode".into()),
+                qos: Qos::AtLeastOnce,
+                retain: true,
+                ..options()
+            },
+        )
+        .unwrap();
+        until(&mut [&mut publisher], connected);
+        publisher.push(&[9]).unwrap();
+        until(&mut [&mut publisher], |clients| {
+            clients[0].inflight.is_empty()
+        });
+
+        let mut subscriber = Mqtt::new(
+            broker.address,
+            Options {
+                subscribe: Some("lwsk/mode".into()),
+                ..options()
+            },
+        )
+        .unwrap();
+        assert_eq!(receive(&mut subscriber), [9]);
+    }
+
+    #[test]
+    fn will_is_published_on_ungraceful_disconnect() {
+        let broker = Broker::start();
+        let mut subscriber = Mqtt::new(
+            broker.address,
+            Options {
+                subscribe: Some("lwsk/status".into()),
+                ..options()
+            },
+        )
+        .unwrap();
+        let mut client = Mqtt::new(
+            broker.address,
+            Options {
+                will: Some(Will {
+                    topic: "lwsk/status".into(),
+                    payload: b"dead".to_vec(),
+                    qos: Qos::AtMostOnce,
+                    retain: false,
+                }),
+                ..options()
+            },
+        )
+        .unwrap();
+        until(&mut [&mut subscriber, &mut client], connected);
+        until(&mut [&mut subscriber], |_| broker.subscribed(1));
+
+        // vanish without DISCONNECT
+        drop(client.stream.take());
+        client.state = State::Disconnected {
+            since: Instant::now(),
+        };
+        assert_eq!(receive(&mut subscriber), *b"dead");
+    }
+
+    #[test]
+    fn unreachable_broker_does_not_block() {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut client = Mqtt::new(
+            address,
+            Options {
+                publish: Some("lwsk/speed".into()),
+                subscribe: Some("lwsk/speed".into()),
+                ..options()
+            },
+        )
+        .unwrap();
+
+        let start = Instant::now();
+        for _ in 0..100 {
+            client.push(&[1, 2, 3, 4]).unwrap();
+            assert_eq!(client.pull(&mut [0u8; 4]).unwrap(), None);
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_ne!(client.connection(), ConnectionState::Connected);
+    }
+}
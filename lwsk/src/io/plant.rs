@@ -0,0 +1,193 @@
+//! Simulated device, itself hosted as a Wasm module
+//!
+//! The plant is a [Function] in its own store, following the same conventions as any other
+//! function: pushed data is copied into its `INPUT` global, and each pull calls its entry function
+//! once before copying its `OUTPUT` global into the channel. Thus the plant advances by exactly one
+//! step per pull, keeping closed loop simulations deterministic.
+
+use super::{DriverError, IoDriver};
+use crate::{Function, LwskError};
+
+pub struct Plant {
+    function: Function,
+}
+
+impl Plant {
+    /// Load the plant model from `wasm_module_path`, providing `fuel_per_call` per step
+    pub fn load(wasm_module_path: &str, fuel_per_call: u64) -> Result<Self, LwskError> {
+        Self::new(
+            Function::load(wasm_module_path, wasm_module_path)?,
+            fuel_per_call,
+        )
+    }
+
+    /// Load the plant model named `name` from the bytes of its Wasm module
+    pub fn from_bytes(name: &str, wasm: &[u8], fuel_per_call: u64) -> Result<Self, LwskError> {
+        Self::new(Function::from_bytes(name, wasm)?, fuel_per_call)
+    }
+
+    fn new(mut function: Function, fuel_per_call: u64) -> Result<Self, LwskError> {
+        function.fuel_per_call = fuel_per_call;
+        function.get_entry_function()?;
+
+        Ok(Self { function })
+    }
+
+    /// Advance the model by one step
+    fn step(&mut self) -> Result<(), LwskError> {
+        let process = self.function.get_entry_function()?;
+        let f = &mut self.function;
+        f.store.set_fuel(f.fuel_per_call).map_err(|e| {
+            error!("could not refuel plant {:?}: {e}", f.name);
+            LwskError::WasmLoadError
+        })?;
+
+        let result = process.call(&mut f.store, ()).map_err(|e| {
+            error!("plant {:?} trapped: {e}", f.name);
+            DriverError::Other(e.to_string())
+        })?;
+        trace!("stepping plant {:?} yielded {result}", f.name);
+
+        Ok(())
+    }
+}
+
+impl IoDriver for Plant {
+    fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+        self.step()?;
+
+        let output = self.function.get_global("OUTPUT", buf.len())?;
+        buf.copy_from_slice(output);
+        debug!("received {} bytes from plant", buf.len());
+
+        Ok(Some(buf.len()))
+    }
+
+    fn push(&mut self, buf: &[u8]) -> Result<(), LwskError> {
+        let input = self.function.get_global_mut("INPUT", buf.len())?;
+        input.copy_from_slice(buf);
+        debug!("sent {} bytes to plant", buf.len());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blueprint::{Blueprint, Overrides};
+    use crate::kernel::Step;
+
+    /// Room model of `fn_thermostat/src/plant.c`
+    const ROOM: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global (export "INPUT") i32 (i32.const 0))
+          (global (export "OUTPUT") i32 (i32.const 8))
+          ;; room temperature and desired temperature
+          (data (i32.const 8) "\00\00\70\41\00\00\a8\41")
+          (func (export "process") (result i32)
+            (local $duty f32)
+            (local.set $duty
+              (f32.max (f32.const 0) (f32.min (f32.const 1) (f32.load (i32.const 0)))))
+            (f32.store (i32.const 8)
+              (f32.add (f32.load (i32.const 8))
+                (f32.div
+                  (f32.mul
+                    (f32.sub
+                      (f32.mul (local.get $duty) (f32.const 2000))
+                      (f32.mul (f32.const 25) (f32.sub (f32.load (i32.const 8)) (f32.const 5))))
+                    (f32.const 10))
+                  (f32.const 50000))))
+            (i32.const 0)))
+    "#;
+
+    /// Controller of `fn_thermostat/src/main.c`, with `pidctl_t PID` at 16
+    const THERMOSTAT: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global (export "INPUT") i32 (i32.const 0))
+          (global (export "OUTPUT") i32 (i32.const 8))
+          (global (export "PID") i32 (i32.const 16))
+          (func (export "process") (result i32)
+            (local $e f32) (local $i f32) (local $o f32)
+            (local.set $e (f32.sub (f32.load (i32.const 4)) (f32.load (i32.const 0))))
+            ;; i = clamp(i + Ki * e, i_saturation)
+            (local.set $i
+              (f32.add (f32.load (i32.const 40)) (f32.mul (f32.load (i32.const 20)) (local.get $e))))
+            (local.set $i
+              (f32.max (f32.neg (f32.load (i32.const 36)))
+                (f32.min (f32.load (i32.const 36)) (local.get $i))))
+            (f32.store (i32.const 40) (local.get $i))
+            ;; o = clamp(Kd * (e - e_last) + Kp * e + i + offset, saturation)
+            (local.set $o
+              (f32.add
+                (f32.add
+                  (f32.add
+                    (f32.mul (f32.load (i32.const 24))
+                      (f32.sub (local.get $e) (f32.load (i32.const 44))))
+                    (f32.mul (f32.load (i32.const 16)) (local.get $e)))
+                  (local.get $i))
+                (f32.load (i32.const 28))))
+            (local.set $o
+              (f32.max (f32.neg (f32.load (i32.const 32)))
+                (f32.min (f32.load (i32.const 32)) (local.get $o))))
+            (f32.store (i32.const 44) (local.get $e))
+            (f32.store (i32.const 8) (local.get $o))
+            (i32.const 0)))
+    "#;
+
+    #[test]
+    fn thermostat_controls_simulated_room() {
+        let dir = std::env::temp_dir().join(format!("lwsk-plant-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let room = dir.join("plant.wasm");
+        let thermostat = dir.join("main.wasm");
+        std::fs::write(&room, wat::parse_str(ROOM).unwrap()).unwrap();
+        std::fs::write(&thermostat, wat::parse_str(THERMOSTAT).unwrap()).unwrap();
+
+        let mut overrides = Overrides::default();
+        overrides
+            .set("io.room.wasm", room.to_str().unwrap())
+            .set("functions.thermostat.wasm", thermostat.to_str().unwrap());
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../fn_thermostat/hil_blueprint.toml"
+        );
+        let blueprint = Blueprint::with_overrides(path, &overrides).unwrap();
+        let mut kernel = blueprint.to_kernel_config().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let temperature = kernel
+            .channels
+            .iter()
+            .position(|channel| channel.name == "temperature")
+            .unwrap();
+        let room_temperature = |kernel: &crate::KernelConfig| {
+            f32::from_le_bytes(kernel.channels[temperature].buf[..4].try_into().unwrap())
+        };
+
+        let mut trace = Vec::new();
+        for _ in 0..200 {
+            // pull, invoke, push and wait
+            for _ in 0..4 {
+                match kernel.step() {
+                    Step::Invoked { result, .. } => assert_eq!(result.unwrap(), 0),
+                    Step::Pulled { result, .. } => assert_eq!(result.unwrap(), Some(8)),
+                    Step::Pushed { result, .. } => result.unwrap(),
+                    Step::Wait(_) => {}
+                    step => panic!("unexpected {step:?}"),
+                }
+            }
+            trace.push(room_temperature(&kernel));
+        }
+
+        // the heater is off until the thermostat ran once
+        assert!(trace[0] < 15.0, "{trace:?}");
+        assert!(trace[1] > trace[0], "{trace:?}");
+        let settled = &trace[100..];
+        assert!(
+            settled.iter().all(|t| (t - 21.0).abs() < 0.1),
+            "{settled:?}"
+        );
+    }
+}
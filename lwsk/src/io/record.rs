@@ -0,0 +1,529 @@
+//! Record and replay of IO traffic
+//!
+//! A [Recorder] logs the outcome of every [IoDriver::pull] together with the position in the
+//! schedule and a timestamp. Such a recording can later be opened as a [Recording], which yields
+//! one [Player] per IO binding. The players replace the actual IO drivers, feeding the recorded
+//! data back in the original order. As schedules are static and functions are deterministic, a
+//! re-run reproduces the recorded run bit-for-bit, including all function outputs. The players
+//! check each record against the position of the schedule shared via a [Cursor], so that a
+//! replay diverging from the recording is detected instead of feeding data to the wrong action.
+//!
+//! The file consists of a [Header] followed by one [Record] per pull, each encoded with postcard
+//! and framed using COBS.
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use super::{DriverError, IoDriver};
+use crate::schedule::ScheduleEntry;
+use crate::{KernelConfig, LwskError};
+
+/// Version of the recording format
+pub const RECORDING_VERSION: u32 = 4;
+
+/// Position of an action in the schedules of a [KernelConfig]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Position {
+    /// Index of the schedule
+    pub schedule_idx: usize,
+
+    /// Index of the action in the schedule
+    pub action_idx: usize,
+}
+
+impl Position {
+    /// Position of the action performed by the next [KernelConfig::step] of `kconfig`
+    pub fn next(kconfig: &KernelConfig) -> Self {
+        let schedule = &kconfig.schedules[kconfig.current_schedule_idx];
+        Self {
+            schedule_idx: kconfig.current_schedule_idx,
+            action_idx: schedule.current_action.wrapping_add(1) % schedule.sequence.len(),
+        }
+    }
+}
+
+/// First entry of every recording
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    /// Version of the recording format, see [RECORDING_VERSION]
+    pub version: u32,
+
+    /// Wall clock time at which the recording started, in nanoseconds since the Unix epoch
+    pub start_unix_ns: u64,
+
+    /// Names of the IO bindings, in the order of their indices
+    pub io: Vec<String>,
+}
+
+/// Outcome of a single [IoDriver::pull]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    /// Where in the schedule the pull happened
+    pub position: Position,
+
+    /// Index of the IO driver that was pulled
+    pub io_idx: usize,
+
+    /// Time since the start of the recording in nanoseconds
+    pub timestamp_ns: u64,
+
+    /// Contents of the channel after the pull, or [None] if the pull failed
+    pub data: Option<Vec<u8>>,
+
+    /// Outcome of the pull, as returned by the IO binding
+    pub result: Result<Option<usize>, DriverError>,
+
+    /// Receive time of the data as reported by the driver, in nanoseconds since the Unix epoch
+    pub rx_timestamp_ns: Option<u64>,
+}
+
+/// Writes a recording of all pulled IO data
+pub struct Recorder {
+    writer: BufWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Create a new recording at `path` for the IO bindings named `io`
+    pub fn create<P: AsRef<Path>, S: Into<String>, I: IntoIterator<Item = S>>(
+        path: P,
+        io: I,
+    ) -> Result<Self, LwskError> {
+        let mut recorder = Self {
+            writer: BufWriter::new(File::create(path)?),
+            start: Instant::now(),
+        };
+
+        let start_unix_ns = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        recorder.write(&Header {
+            version: RECORDING_VERSION,
+            start_unix_ns,
+            io: io.into_iter().map(Into::into).collect(),
+        })?;
+
+        Ok(recorder)
+    }
+
+    /// Record the outcome of pulling `io_idx` at `position`, `buf` being the channel contents
+    pub fn record_pull(
+        &mut self,
+        position: Position,
+        io_idx: usize,
+        result: &Result<Option<usize>, DriverError>,
+        buf: &[u8],
+        rx_timestamp: Option<Duration>,
+    ) -> Result<(), LwskError> {
+        let record = Record {
+            position,
+            io_idx,
+            timestamp_ns: self.start.elapsed().as_nanos() as u64,
+            data: result.as_ref().ok().map(|_| buf.to_vec()),
+            result: result.clone(),
+            rx_timestamp_ns: rx_timestamp.map(|t| t.as_nanos() as u64),
+        };
+        self.write(&record)
+    }
+
+    fn write<T: Serialize>(&mut self, value: &T) -> Result<(), LwskError> {
+        let frame = postcard::to_stdvec_cobs(value).map_err(|e| {
+            error!("could not encode recording entry: {e}");
+            LwskError::InvalidRecording
+        })?;
+        self.writer.write_all(&frame)?;
+        Ok(())
+    }
+
+    /// Write all buffered records to the file
+    ///
+    /// Records are buffered to keep file IO out of the schedule, flush at the end of each frame
+    /// and while the schedule waits.
+    pub fn flush(&mut self) -> Result<(), LwskError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// A recording read back from a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recording {
+    pub header: Header,
+    pub records: Vec<Record>,
+}
+
+impl Recording {
+    /// Read the recording at `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, LwskError> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+
+        let mut frames = bytes
+            .split_mut(|b| *b == 0)
+            .filter(|frame| !frame.is_empty());
+
+        let header: Header = frames
+            .next()
+            .ok_or(LwskError::InvalidRecording)
+            .and_then(decode)?;
+        if header.version != RECORDING_VERSION {
+            error!(
+                "recording has version {}, expected {RECORDING_VERSION}",
+                header.version
+            );
+            return Err(LwskError::InvalidRecording);
+        }
+
+        let records = frames.map(decode).collect::<Result<_, _>>()?;
+
+        Ok(Self { header, records })
+    }
+
+    /// Check that this recording fits `kconfig`, whose IO bindings are named `io`
+    ///
+    /// # Checks
+    ///
+    /// - the IO bindings are the same as in the recording
+    /// - each record points to an [ScheduleEntry::IoIn] of the same IO driver
+    pub fn validate<'a, I: IntoIterator<Item = &'a str>>(
+        &self,
+        kconfig: &KernelConfig,
+        io: I,
+    ) -> Result<(), LwskError> {
+        if !io.into_iter().eq(self.header.io.iter().map(String::as_str)) {
+            error!(
+                "recording was made with the io bindings {:?}",
+                self.header.io
+            );
+            return Err(LwskError::InvalidRecording);
+        }
+
+        for record in &self.records {
+            let Position {
+                schedule_idx,
+                action_idx,
+            } = record.position;
+            let entry = kconfig
+                .schedules
+                .get(schedule_idx)
+                .and_then(|schedule| schedule.sequence.get(action_idx));
+            match entry {
+                Some(ScheduleEntry::IoIn { from_io_idx, .. }) if *from_io_idx == record.io_idx => {}
+                _ => {
+                    error!(
+                        "recorded pull of io[{}] does not match schedules[{schedule_idx}][{action_idx}]",
+                        record.io_idx
+                    );
+                    return Err(LwskError::InvalidRecording);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Create a [Player] for each IO binding, following the schedule via `cursor`
+    pub fn into_players(self, cursor: &Cursor) -> Vec<Player> {
+        let mut players: Vec<_> = (0..self.header.io.len())
+            .map(|io_idx| Player {
+                io_idx,
+                records: VecDeque::new(),
+                rx_timestamp: None,
+                cursor: cursor.clone(),
+            })
+            .collect();
+
+        for record in self.records {
+            match players.get_mut(record.io_idx) {
+                Some(player) => player.records.push_back(record),
+                None => warn!("dropping record of unknown io[{}]", record.io_idx),
+            }
+        }
+
+        players
+    }
+}
+
+fn decode<'a, T: Deserialize<'a>>(frame: &'a mut [u8]) -> Result<T, LwskError> {
+    postcard::from_bytes_cobs(frame).map_err(|e| {
+        error!("could not decode recording entry: {e}");
+        LwskError::InvalidRecording
+    })
+}
+
+/// Position in the schedule shared with all [Player]s of a replay
+#[derive(Debug, Clone, Default)]
+pub struct Cursor {
+    /// Position of the upcoming action, [None] to not check the records against it
+    position: Rc<Cell<Option<Position>>>,
+
+    /// Whether a record did not match the position it was replayed at
+    diverged: Rc<Cell<bool>>,
+}
+
+impl Cursor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Follow `kconfig`, to be called before each of its [KernelConfig::step]s
+    pub fn advance(&self, kconfig: &KernelConfig) {
+        self.position.set(Some(Position::next(kconfig)));
+    }
+
+    /// Whether the replay diverged from the recording
+    pub fn diverged(&self) -> bool {
+        self.diverged.get()
+    }
+}
+
+/// Replays the recorded pulls of one IO binding
+pub struct Player {
+    io_idx: usize,
+    records: VecDeque<Record>,
+
+    /// Recorded receive time of the last replayed data
+    rx_timestamp: Option<Duration>,
+
+    cursor: Cursor,
+}
+
+impl IoDriver for Player {
+    fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+        let Some(record) = self.records.front() else {
+            warn!("recording of io[{}] is exhausted", self.io_idx);
+            return Ok(None);
+        };
+        if let Some(position) = self.cursor.position.get() {
+            if record.position != position {
+                error!(
+                    "io[{}] is pulled at {position:?}, but was recorded at {:?}",
+                    self.io_idx, record.position
+                );
+                self.cursor.diverged.set(true);
+                return Err(LwskError::InvalidRecording);
+            }
+        }
+        let record = self.records.pop_front().expect("checked above");
+        trace!(
+            "replaying io[{}] recorded at {:?} after {} ns",
+            self.io_idx,
+            record.position,
+            record.timestamp_ns
+        );
+
+        let (data, pulled) = match (record.data, record.result) {
+            (_, Err(e)) => return Err(LwskError::DriverError(e)),
+            (_, Ok(None)) => return Ok(None),
+            (Some(data), Ok(Some(pulled))) => (data, pulled),
+            (None, Ok(Some(_))) => {
+                error!("recorded pull of io[{}] lacks its data", self.io_idx);
+                return Err(LwskError::InvalidRecording);
+            }
+        };
+        if data.len() != buf.len() {
+            error!(
+                "recorded {} bytes for io[{}], but the channel has {} bytes",
+                data.len(),
+                self.io_idx,
+                buf.len()
+            );
+            return Err(LwskError::InvalidRecording);
+        }
+        buf.copy_from_slice(&data);
+        self.rx_timestamp = record.rx_timestamp_ns.map(Duration::from_nanos);
+
+        Ok(Some(pulled))
+    }
+
+    fn rx_timestamp(&self) -> Option<Duration> {
+        self.rx_timestamp
+    }
+
+    fn push(&mut self, buf: &[u8]) -> Result<(), LwskError> {
+        debug!("discarding data pushed to io[{}]: {buf:02x?}", self.io_idx);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blueprint::Blueprint;
+    use crate::Step;
+
+    const BLUEPRINT: &str = r#"
+        [channels.input]
+        size = 2
+
+        [io.scripted]
+        type = "Loopback"
+        retries = 2
+
+        [[schedules.main]]
+        from_io = "scripted"
+        to_channel = "input"
+        [[schedules.main]]
+        wait_ns = 1
+    "#;
+
+    /// Yields a fixed sequence of pull outcomes
+    struct Scripted(VecDeque<Result<Option<Vec<u8>>, DriverError>>);
+
+    impl IoDriver for Scripted {
+        fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+            match self.0.pop_front() {
+                Some(Ok(Some(data))) => {
+                    buf.copy_from_slice(&data);
+                    Ok(Some(data.len()))
+                }
+                Some(Err(e)) => Err(e.into()),
+                Some(Ok(None)) | None => Ok(None),
+            }
+        }
+
+        fn push(&mut self, _buf: &[u8]) -> Result<(), LwskError> {
+            Ok(())
+        }
+    }
+
+    fn kernel(dir: &Path, io: Box<dyn IoDriver>) -> KernelConfig {
+        let mut io = Some(io);
+        Blueprint::new(dir.join("blueprint.toml"))
+            .unwrap()
+            .to_kernel_config_with_io(|_, _, _| io.take().ok_or(LwskError::InvalidRecording))
+            .unwrap()
+    }
+
+    /// Outcome of a pull and the channel contents after it
+    type Pull = (Result<Option<usize>, DriverError>, Vec<u8>);
+
+    /// Pulls of the first `frames` frames
+    fn run(
+        kernel: &mut KernelConfig,
+        cursor: Option<&Cursor>,
+        mut recorder: Option<&mut Recorder>,
+        frames: usize,
+    ) -> Vec<Pull> {
+        let mut pulls = Vec::new();
+        for _ in 0..frames * 2 {
+            if let Some(cursor) = cursor {
+                cursor.advance(kernel);
+            }
+            if let Step::Pulled {
+                io_idx,
+                channel_idx,
+                result,
+            } = kernel.step()
+            {
+                let buf = kernel.channels[channel_idx].buf.clone();
+                if let Some(recorder) = recorder.as_deref_mut() {
+                    let position = Position {
+                        schedule_idx: kernel.current_schedule_idx,
+                        action_idx: kernel.schedules[kernel.current_schedule_idx].current_action,
+                    };
+                    recorder
+                        .record_pull(position, io_idx, &result, &buf, None)
+                        .unwrap();
+                }
+                pulls.push((result, buf));
+            }
+        }
+        pulls
+    }
+
+    #[test]
+    fn replay_reproduces_recorded_pulls() {
+        let dir = std::env::temp_dir().join(format!("lwsk-record-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("blueprint.toml"), BLUEPRINT).unwrap();
+        let path = dir.join("recording.lwsk");
+
+        // the error persists over all retries, the recording only holds the final outcome
+        let script = [
+            Ok(Some(vec![1, 2])),
+            Ok(None),
+            Err(DriverError::Timeout),
+            Err(DriverError::Timeout),
+            Err(DriverError::Timeout),
+            Ok(Some(vec![3, 4])),
+        ];
+        let mut recorded = kernel(&dir, Box::new(Scripted(script.into())));
+        let mut recorder = Recorder::create(&path, ["scripted"]).unwrap();
+        let expected = run(&mut recorded, None, Some(&mut recorder), 4);
+        recorder.flush().unwrap();
+        assert_eq!(
+            expected,
+            [
+                (Ok(Some(2)), vec![1, 2]),
+                (Ok(None), vec![1, 2]),
+                (Err(DriverError::Timeout), vec![1, 2]),
+                (Ok(Some(2)), vec![3, 4]),
+            ]
+        );
+
+        let recording = Recording::open(&path).unwrap();
+        assert_eq!(recording.records.len(), 4);
+        let cursor = Cursor::new();
+        let mut players = recording.clone().into_players(&cursor);
+        let mut replayed = kernel(&dir, Box::new(players.remove(0)));
+        recording.validate(&replayed, ["scripted"]).unwrap();
+        replayed.io[0].policy.retries = 0;
+        assert_eq!(run(&mut replayed, Some(&cursor), None, 4), expected);
+        assert!(!cursor.diverged());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn player_detects_divergence() {
+        let record = |action_idx, data: u8| Record {
+            position: Position {
+                schedule_idx: 0,
+                action_idx,
+            },
+            io_idx: 0,
+            timestamp_ns: 0,
+            data: Some(vec![data]),
+            result: Ok(Some(1)),
+            rx_timestamp_ns: None,
+        };
+        let recording = Recording {
+            header: Header {
+                version: RECORDING_VERSION,
+                start_unix_ns: 0,
+                io: vec!["io".into()],
+            },
+            records: vec![record(0, 1), record(2, 2)],
+        };
+        let cursor = Cursor::new();
+        let mut player = recording.into_players(&cursor).remove(0);
+        let mut buf = [0u8; 1];
+
+        cursor.position.set(Some(Position {
+            schedule_idx: 0,
+            action_idx: 2,
+        }));
+        assert!(matches!(
+            player.pull(&mut buf),
+            Err(LwskError::InvalidRecording)
+        ));
+        assert!(cursor.diverged());
+
+        // the mismatching record is kept
+        cursor.position.set(Some(Position {
+            schedule_idx: 0,
+            action_idx: 0,
+        }));
+        assert_eq!(player.pull(&mut buf).unwrap(), Some(1));
+        assert_eq!(buf, [1]);
+    }
+}
@@ -0,0 +1,217 @@
+//! Registry of IO drivers provided by other crates
+//!
+//! IO bindings in a blueprint whose `type` is not built into lwsk are looked up here by their type
+//! name. The remaining keys of the binding's table, except for those common to all bindings like
+//! `codec` or `on_error`, are passed to the registered factory as generic value. Factories
+//! registered via [DriverRegistry::register_with_config] deserialize this value into their own
+//! configuration type, whose JSON Schema is part of the blueprint schema.
+//!
+//! To run the kernel with additional drivers, build a registry and hand it to [crate::cli::run]
+//! or [crate::blueprint::Blueprint::to_kernel_config_with_registry].
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use schemars::{JsonSchema, Schema};
+use serde::de::DeserializeOwned;
+
+use super::IoDriver;
+use crate::blueprint::IoBp;
+use crate::{LwskError, LwskResult};
+
+/// Creates an [IoDriver] from the configuration of an IO binding
+pub type Factory = dyn Fn(&toml::Table) -> LwskResult<Box<dyn IoDriver>> + Send + Sync;
+
+/// IO driver factories by type name
+///
+/// Factories are shared between clones, so that threaded drivers can be created on their own
+/// thread.
+#[derive(Clone, Default)]
+pub struct DriverRegistry {
+    factories: BTreeMap<String, Arc<Factory>>,
+
+    /// Schema of the configuration by type name, if known
+    schemas: BTreeMap<String, Schema>,
+}
+
+impl DriverRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `factory` for IO bindings of type `type_name`, replacing any previous one
+    ///
+    /// Fails if `type_name` is [built in](IoBp::is_built_in), as a blueprint could never refer to
+    /// the plugin by it.
+    pub fn register<F>(&mut self, type_name: &str, factory: F) -> LwskResult<&mut Self>
+    where
+        F: Fn(&toml::Table) -> LwskResult<Box<dyn IoDriver>> + Send + Sync + 'static,
+    {
+        if IoBp::is_built_in(type_name) {
+            error!("cannot register the built-in IO driver type {type_name:?}");
+            return Err(LwskError::BuiltInDriverType(type_name.into()));
+        }
+
+        self.schemas.remove(type_name);
+        if self
+            .factories
+            .insert(type_name.into(), Arc::new(factory))
+            .is_some()
+        {
+            warn!("replacing IO driver factory of type {type_name:?}");
+        }
+        Ok(self)
+    }
+
+    /// Register `factory` for IO bindings of type `type_name`, deserializing their configuration
+    /// into `C` first
+    pub fn register_with_config<C, F>(
+        &mut self,
+        type_name: &str,
+        factory: F,
+    ) -> LwskResult<&mut Self>
+    where
+        C: DeserializeOwned + JsonSchema,
+        F: Fn(C) -> LwskResult<Box<dyn IoDriver>> + Send + Sync + 'static,
+    {
+        let name = type_name.to_owned();
+        self.register(type_name, move |config| {
+            let config = toml::Value::Table(config.clone()).try_into().map_err(|e| {
+                error!("invalid configuration of IO driver type {name:?}: {e}");
+                LwskError::InvalidDriverConfig
+            })?;
+            factory(config)
+        })?;
+        self.schemas
+            .insert(type_name.into(), schemars::schema_for!(C));
+        Ok(self)
+    }
+
+    /// Create a driver of type `type_name` from `config`
+    pub fn create(&self, type_name: &str, config: &toml::Table) -> LwskResult<Box<dyn IoDriver>> {
+        let Some(factory) = self.factories.get(type_name) else {
+            error!("no IO driver of type {type_name:?} is registered");
+            return Err(LwskError::UnknownDriverType(type_name.into()));
+        };
+        factory(config)
+    }
+
+    /// Names of all registered types
+    pub fn type_names(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(String::as_str)
+    }
+
+    /// Schema of the configuration of type `type_name`, if registered with one
+    pub fn schema(&self, type_name: &str) -> Option<&Schema> {
+        self.schemas.get(type_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blueprint::Blueprint;
+    use crate::io::memory::Constant;
+
+    /// Configuration of a plugin yielding `value` repeatedly
+    #[derive(serde::Deserialize, JsonSchema)]
+    struct RepeatConfig {
+        value: u8,
+        count: usize,
+    }
+
+    fn registry() -> DriverRegistry {
+        let mut registry = DriverRegistry::new();
+        registry
+            .register_with_config("Repeat", |config: RepeatConfig| {
+                Ok(Box::new(Constant::new(vec![config.value; config.count])))
+            })
+            .unwrap()
+            .register("Empty", |_| Ok(Box::new(Constant::new(Vec::new()))))
+            .unwrap();
+        registry
+    }
+
+    fn table(toml: &str) -> toml::Table {
+        toml.parse().unwrap()
+    }
+
+    #[test]
+    fn registered_types_are_created_from_their_config() {
+        let registry = registry();
+        assert_eq!(
+            registry.type_names().collect::<Vec<_>>(),
+            ["Empty", "Repeat"]
+        );
+
+        let mut driver = registry
+            .create("Repeat", &table("value = 7\ncount = 2"))
+            .unwrap();
+        let mut buf = [0u8; 2];
+        assert_eq!(driver.pull(&mut buf).unwrap(), Some(2));
+        assert_eq!(buf, [7, 7]);
+        assert!(registry.create("Empty", &toml::Table::new()).is_ok());
+
+        assert!(matches!(
+            registry.create("Missing", &toml::Table::new()),
+            Err(LwskError::UnknownDriverType(ty)) if ty == "Missing"
+        ));
+        for invalid in [
+            "value = 7",
+            "value = 256\ncount = 1",
+            "value = \"7\"\ncount = 1",
+        ] {
+            assert!(
+                matches!(
+                    registry.create("Repeat", &table(invalid)),
+                    Err(LwskError::InvalidDriverConfig)
+                ),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn built_in_types_cannot_be_registered() {
+        for ty in [
+            "Udp", "UDP", "Constant", "Plant", "Timer", "TimerFd", "EventFd",
+        ] {
+            assert!(IoBp::is_built_in(ty), "{ty}");
+        }
+        for ty in ["Plugin", "Repeat", "udp", ""] {
+            assert!(!IoBp::is_built_in(ty), "{ty}");
+        }
+
+        let mut registry = registry();
+        assert!(matches!(
+            registry.register("UDP", |_| Ok(Box::new(Constant::new(Vec::new())))),
+            Err(LwskError::BuiltInDriverType(ty)) if ty == "UDP"
+        ));
+        assert!(registry.type_names().all(|ty| ty != "UDP"));
+    }
+
+    #[test]
+    fn schema_includes_registered_configs() {
+        let registry = registry();
+        assert!(registry.schema("Repeat").is_some());
+        assert!(registry.schema("Empty").is_none());
+
+        let schema = Blueprint::json_schema_with_registry(&registry);
+        let variants = schema["$defs"]["IoBindingBp"]["anyOf"].as_array().unwrap();
+        let repeat = variants
+            .iter()
+            .find(|variant| variant["properties"]["type"]["const"] == "Repeat")
+            .unwrap();
+        assert!(repeat["properties"]["count"].is_object());
+        assert!(repeat["required"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|key| key == "type"));
+
+        let plugin = variants.last().unwrap();
+        let excluded = &plugin["allOf"][1]["properties"]["type"]["not"]["enum"];
+        assert!(excluded.as_array().unwrap().iter().any(|ty| ty == "Repeat"));
+        assert!(excluded.as_array().unwrap().iter().all(|ty| ty != "Empty"));
+    }
+}
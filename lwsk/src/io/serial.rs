@@ -0,0 +1,102 @@
+//! Serial port driver for POSIX terminals
+//!
+//! The port is put into raw mode, so bytes are passed through unaltered. As a serial port is a
+//! byte stream, a pull yields whatever bytes arrived since the last pull, which makes this driver
+//! mostly useful as transport for protocol drivers such as [super::mavlink::Mavlink].
+
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+
+use crate::LwskError;
+
+pub struct Serial {
+    port: File,
+}
+
+impl Serial {
+    /// Open the serial port at `path` with the given `baud` rate
+    pub fn new(path: &str, baud: u32) -> Result<Self, LwskError> {
+        let port = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open(path)
+            .inspect_err(|e| log::error!("could not open serial port {path:?}: {e}"))?;
+
+        let speed = match baud {
+            9600 => libc::B9600,
+            19200 => libc::B19200,
+            38400 => libc::B38400,
+            57600 => libc::B57600,
+            115200 => libc::B115200,
+            230400 => libc::B230400,
+            460800 => libc::B460800,
+            921600 => libc::B921600,
+            _ => {
+                log::error!("unsupported baud rate {baud}");
+                return Err(LwskError::IoChannelCreationError);
+            }
+        };
+
+        let fd = port.as_raw_fd();
+        // Safety: termios is plain old data, and fd is a valid file descriptor
+        let configured = unsafe {
+            let mut termios: libc::termios = core::mem::zeroed();
+            libc::tcgetattr(fd, &mut termios) == 0 && {
+                libc::cfmakeraw(&mut termios);
+                termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+                libc::cfsetspeed(&mut termios, speed) == 0
+                    && libc::tcsetattr(fd, libc::TCSANOW, &termios) == 0
+            }
+        };
+        if !configured {
+            log::error!(
+                "could not configure serial port {path:?}: {}",
+                std::io::Error::last_os_error()
+            );
+            return Err(LwskError::IoChannelCreationError);
+        }
+
+        Ok(Self { port })
+    }
+}
+
+impl super::IoDriver for Serial {
+    fn readiness_fd(&self) -> Option<i32> {
+        Some(self.port.as_raw_fd())
+    }
+
+    fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+        match self.port.read(buf) {
+            Ok(0) => {
+                log::debug!("no new data on serial port");
+                Ok(None)
+            }
+            Ok(n) => {
+                log::debug!("received {n} bytes from serial port");
+                Ok(Some(n))
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                log::debug!("no new data on serial port");
+                Ok(None)
+            }
+            Err(e) => {
+                log::error!("could not read from serial port: {e}");
+                Err(LwskError::DriverError(e.into()))
+            }
+        }
+    }
+
+    fn push(&mut self, buf: &[u8]) -> Result<(), LwskError> {
+        match self.port.write_all(buf) {
+            Ok(()) => log::debug!("wrote {} byte to serial port", buf.len()),
+            Err(e) => {
+                log::error!("could not write to serial port: {e}");
+                return Err(LwskError::DriverError(e.into()));
+            }
+        }
+        Ok(())
+    }
+}
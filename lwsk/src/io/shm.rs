@@ -0,0 +1,50 @@
+//! Shared memory driver for co-simulation with another process
+//!
+//! The protocol is implemented in the `lwsk-shm` crate, which the other side (for example a plant
+//! model) uses to open the same region.
+
+use lwsk_shm::{Endpoint, Side};
+
+use crate::LwskError;
+
+pub struct SharedMemory {
+    endpoint: Endpoint,
+}
+
+impl SharedMemory {
+    /// Open the shared memory region `name` with slots of `size` bytes
+    pub fn new(name: &str, size: usize) -> Result<Self, LwskError> {
+        let endpoint = Endpoint::open(name, size, Side::Kernel).inspect_err(|e| {
+            log::error!("could not open shared memory region {name:?}: {e}");
+        })?;
+        Ok(Self { endpoint })
+    }
+}
+
+impl super::IoDriver for SharedMemory {
+    fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+        let received = self.endpoint.recv(buf).inspect_err(|e| {
+            log::error!("could not receive from shared memory: {e}");
+        })?;
+        match received {
+            Some(n) => log::debug!("received {n} bytes from shared memory"),
+            None => log::debug!("no new data in shared memory"),
+        }
+        Ok(received)
+    }
+
+    fn push(&mut self, buf: &[u8]) -> Result<(), LwskError> {
+        let capacity = self.endpoint.region().capacity();
+        if buf.len() > capacity {
+            log::error!("could not write {} bytes to shared memory", buf.len());
+            return Err(LwskError::BufferTooSmall {
+                expected: buf.len(),
+                got: capacity,
+            });
+        }
+
+        self.endpoint.send(buf)?;
+        log::debug!("wrote {} byte to shared memory", buf.len());
+        Ok(())
+    }
+}
@@ -0,0 +1,473 @@
+//! Drivers running on their own thread, decoupled from the schedule by lock-free mailboxes
+//!
+//! A [Threaded] driver owns a worker thread which polls the actual driver once per poll interval.
+//! Pulled data is published to a [Mailbox], from which [Threaded::pull] takes the most recent
+//! entry. Pushed data is published to another mailbox, from which the worker pushes it on its next
+//! iteration. Thus neither a pull nor a push by the schedule ever waits for the driver; a driver
+//! which blocks, e.g. while reconnecting, only delays its own thread.
+//!
+//! Since the mailboxes only hold the latest entry, data pulled or pushed faster than the other
+//! side takes it is overwritten. Errors of pulls are delivered like data, errors of pushes are
+//! reported by the next [Threaded::push].
+//!
+//! The size of the channel pulled into is only known after the first pull, thus the worker starts
+//! pulling from the driver after that.
+//!
+//! On Linux, an eventfd signalled on each publish of pulled data serves as readiness fd.
+//!
+//! Dropping a threaded driver stops its worker. A worker blocked in its driver for longer than
+//! [STOP_TIMEOUT] is left behind, to exit on its own once the driver returns.
+
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::{ConnectionState, DriverError, IoDriver};
+use crate::{LwskError, LwskResult};
+
+/// Flag in [Mailbox::middle], set while the middle slot holds an entry not yet taken
+const NEW: u8 = 0b100;
+
+/// Time to wait for the worker to stop when dropping a [Threaded] driver
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Lock-free single producer, single consumer mailbox holding the latest entry (triple buffer)
+///
+/// Of the three slots, one is owned by the [Writer], one by the [Reader], and the third is
+/// exchanged between them.
+pub struct Mailbox<T> {
+    slots: [UnsafeCell<T>; 3],
+
+    /// Index of the exchanged slot, with [NEW] set if the writer published since the last take
+    middle: AtomicU8,
+}
+
+// SAFETY: each slot is only ever accessed by the side currently owning its index, and ownership is
+// only transferred through the atomic swap of `middle`
+unsafe impl<T: Send> Sync for Mailbox<T> {}
+
+/// Writing end of a [Mailbox]
+pub struct Writer<T> {
+    mailbox: Arc<Mailbox<T>>,
+    idx: u8,
+}
+
+/// Reading end of a [Mailbox]
+pub struct Reader<T> {
+    mailbox: Arc<Mailbox<T>>,
+    idx: u8,
+}
+
+/// Create a mailbox, returning its writing and reading end
+pub fn mailbox<T: Default>() -> (Writer<T>, Reader<T>) {
+    let mailbox = Arc::new(Mailbox {
+        slots: Default::default(),
+        middle: AtomicU8::new(1),
+    });
+    (
+        Writer {
+            mailbox: mailbox.clone(),
+            idx: 0,
+        },
+        Reader { mailbox, idx: 2 },
+    )
+}
+
+impl<T> Writer<T> {
+    /// The slot to fill before the next [Self::publish]
+    pub fn slot(&mut self) -> &mut T {
+        // SAFETY: the writer exclusively owns the slot at its index
+        unsafe { &mut *self.mailbox.slots[self.idx as usize].get() }
+    }
+
+    /// Hand the slot over to the reader, replacing any entry it did not take yet
+    pub fn publish(&mut self) {
+        let previous = self.mailbox.middle.swap(self.idx | NEW, Ordering::AcqRel);
+        self.idx = previous & !NEW;
+    }
+}
+
+impl<T> Reader<T> {
+    /// Take the latest published entry, if there is one not taken before
+    pub fn take(&mut self) -> Option<&mut T> {
+        if self.mailbox.middle.load(Ordering::Relaxed) & NEW == 0 {
+            return None;
+        }
+        let previous = self.mailbox.middle.swap(self.idx, Ordering::AcqRel);
+        self.idx = previous & !NEW;
+
+        // SAFETY: the reader exclusively owns the slot at its index
+        Some(unsafe { &mut *self.mailbox.slots[self.idx as usize].get() })
+    }
+}
+
+/// Result of one pull of the worker
+#[derive(Debug)]
+struct Pulled {
+    buf: Vec<u8>,
+    result: Result<usize, DriverError>,
+    rx_timestamp: Option<Duration>,
+}
+
+impl Default for Pulled {
+    fn default() -> Self {
+        Self {
+            buf: Vec::new(),
+            result: Ok(0),
+            rx_timestamp: None,
+        }
+    }
+}
+
+/// State shared between a [Threaded] driver and its worker
+struct Shared {
+    stop: AtomicBool,
+
+    /// Length of the channel pulled into, zero until the first pull
+    pull_len: AtomicUsize,
+
+    /// [ConnectionState] of the driver, as last reported to the worker
+    connection: AtomicU8,
+
+    /// Signalled whenever the worker published pulled data
+    #[cfg(target_os = "linux")]
+    ready: Option<super::fd::Event>,
+}
+
+/// [IoDriver] running another driver on its own thread
+pub struct Threaded {
+    shared: Arc<Shared>,
+    pulled: Reader<Pulled>,
+    to_push: Writer<Vec<u8>>,
+    push_errors: Reader<Option<DriverError>>,
+    rx_timestamp: Option<Duration>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Threaded {
+    /// Spawn a worker thread named `name`, polling the driver created by `make_driver` every
+    /// `poll_interval`
+    ///
+    /// The driver is created on the worker thread, thus it does not need to be [Send]. Errors
+    /// creating it are returned here.
+    pub fn spawn<F>(name: &str, poll_interval: Duration, make_driver: F) -> LwskResult<Self>
+    where
+        F: FnOnce() -> LwskResult<Box<dyn IoDriver>> + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            stop: AtomicBool::new(false),
+            pull_len: AtomicUsize::new(0),
+            connection: AtomicU8::new(0),
+            #[cfg(target_os = "linux")]
+            ready: super::fd::Event::new(false)
+                .inspect_err(|_| warn!("IO thread {name:?} can not tell its readiness"))
+                .ok(),
+        });
+        let (pulled_writer, pulled) = mailbox();
+        let (to_push, to_push_reader) = mailbox();
+        let (push_errors_writer, push_errors) = mailbox();
+        let (created_tx, created_rx) = mpsc::sync_channel(1);
+
+        let worker = Worker {
+            shared: shared.clone(),
+            poll_interval,
+            pulled: pulled_writer,
+            to_push: to_push_reader,
+            push_errors: push_errors_writer,
+            current: Vec::new(),
+        };
+        let worker =
+            std::thread::Builder::new()
+                .name(name.into())
+                .spawn(move || match make_driver() {
+                    Ok(driver) => {
+                        // the receiver only goes away if spawning failed
+                        let _ = created_tx.send(Ok(()));
+                        worker.run(driver);
+                    }
+                    Err(e) => {
+                        let _ = created_tx.send(Err(e));
+                    }
+                })?;
+
+        match created_rx.recv() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                error!("could not create driver of IO thread {name:?}: {e}");
+                let _ = worker.join();
+                return Err(e);
+            }
+            Err(_) => {
+                error!("IO thread {name:?} panicked while creating its driver");
+                let _ = worker.join();
+                return Err(DriverError::Other("IO thread panicked".into()).into());
+            }
+        }
+
+        Ok(Self {
+            shared,
+            pulled,
+            to_push,
+            push_errors,
+            rx_timestamp: None,
+            worker: Some(worker),
+        })
+    }
+}
+
+impl IoDriver for Threaded {
+    fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+        self.shared.pull_len.store(buf.len(), Ordering::Relaxed);
+
+        // reset before taking, so that data published in between signals again
+        #[cfg(target_os = "linux")]
+        if let Some(ready) = &self.shared.ready {
+            ready.reset()?;
+        }
+
+        let Some(pulled) = self.pulled.take() else {
+            return Ok(None);
+        };
+        let n = pulled.result.clone()?;
+        // the worker's buffer mirrors the whole channel, not only the first n bytes
+        let len = buf.len().min(pulled.buf.len());
+        buf[..len].copy_from_slice(&pulled.buf[..len]);
+        self.rx_timestamp = pulled.rx_timestamp;
+        Ok(Some(n))
+    }
+
+    fn push(&mut self, buf: &[u8]) -> Result<(), LwskError> {
+        let slot = self.to_push.slot();
+        slot.clear();
+        slot.extend_from_slice(buf);
+        self.to_push.publish();
+
+        if let Some(Some(e)) = self.push_errors.take() {
+            return Err(e.clone().into());
+        }
+        Ok(())
+    }
+
+    fn connection(&self) -> ConnectionState {
+        match self.shared.connection.load(Ordering::Relaxed) {
+            1 => ConnectionState::Connecting,
+            2 => ConnectionState::Connected,
+            3 => ConnectionState::Disconnected,
+            _ => ConnectionState::Connectionless,
+        }
+    }
+
+    fn rx_timestamp(&self) -> Option<Duration> {
+        self.rx_timestamp
+    }
+
+    fn readiness_fd(&self) -> Option<i32> {
+        #[cfg(target_os = "linux")]
+        {
+            self.shared
+                .ready
+                .as_ref()
+                .and_then(|ready| ready.readiness_fd())
+        }
+        #[cfg(not(target_os = "linux"))]
+        None
+    }
+}
+
+impl Drop for Threaded {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        let Some(worker) = self.worker.take() else {
+            return;
+        };
+
+        // a blocked driver must not block the schedule dropping it, so the join is bounded
+        let deadline = Instant::now() + STOP_TIMEOUT;
+        while !worker.is_finished() {
+            if Instant::now() >= deadline {
+                warn!("IO thread did not stop within {STOP_TIMEOUT:?}, leaving it behind");
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        if worker.join().is_err() {
+            error!("IO thread panicked");
+        }
+    }
+}
+
+/// The worker thread's end of a [Threaded] driver
+struct Worker {
+    shared: Arc<Shared>,
+    poll_interval: Duration,
+    pulled: Writer<Pulled>,
+    to_push: Reader<Vec<u8>>,
+    push_errors: Writer<Option<DriverError>>,
+
+    /// The channel as seen by the driver, as drivers may only update parts of it
+    current: Vec<u8>,
+}
+
+impl Worker {
+    fn run(mut self, mut driver: Box<dyn IoDriver>) {
+        let mut next_poll = Instant::now();
+        while !self.shared.stop.load(Ordering::Relaxed) {
+            if let Some(buf) = self.to_push.take() {
+                if let Err(e) = driver.push(buf) {
+                    *self.push_errors.slot() = Some(into_driver_error(e));
+                    self.push_errors.publish();
+                }
+            }
+
+            let pull_len = self.shared.pull_len.load(Ordering::Relaxed);
+            if pull_len > 0 {
+                self.pull(driver.as_mut(), pull_len);
+            }
+
+            let connection = match driver.connection() {
+                ConnectionState::Connectionless => 0,
+                ConnectionState::Connecting => 1,
+                ConnectionState::Connected => 2,
+                ConnectionState::Disconnected => 3,
+            };
+            self.shared.connection.store(connection, Ordering::Relaxed);
+
+            next_poll += self.poll_interval;
+            let now = Instant::now();
+            if next_poll > now {
+                std::thread::sleep(next_poll - now);
+            } else {
+                // do not try to catch up after the driver blocked
+                next_poll = now;
+            }
+        }
+        debug!("IO thread stopped");
+    }
+
+    fn pull(&mut self, driver: &mut dyn IoDriver, pull_len: usize) {
+        self.current.resize(pull_len, 0);
+        let result = match driver.pull(&mut self.current) {
+            Ok(None) => return,
+            Ok(Some(n)) => Ok(n),
+            Err(e) => Err(into_driver_error(e)),
+        };
+
+        let slot = self.pulled.slot();
+        slot.buf.clear();
+        slot.buf.extend_from_slice(&self.current);
+        slot.result = result;
+        slot.rx_timestamp = driver
+            .rx_timestamp()
+            .or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).ok());
+        self.pulled.publish();
+
+        #[cfg(target_os = "linux")]
+        if let Some(ready) = &self.shared.ready {
+            let _ = ready.signal();
+        }
+    }
+}
+
+fn into_driver_error(e: LwskError) -> DriverError {
+    match e {
+        LwskError::DriverError(e) => e,
+        e => DriverError::Other(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::memory::Loopback;
+
+    /// Pull `driver` until it yields data, for at most a second
+    fn pull_until_some(driver: &mut Threaded, buf: &mut [u8]) -> Result<usize, LwskError> {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        loop {
+            if let Some(n) = driver.pull(buf)? {
+                return Ok(n);
+            }
+            assert!(Instant::now() < deadline, "no data was pulled");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn mailbox_holds_the_latest_entry() {
+        let (mut writer, mut reader) = mailbox::<u32>();
+        assert_eq!(reader.take(), None);
+        for i in 1..=3 {
+            *writer.slot() = i;
+            writer.publish();
+        }
+        assert_eq!(reader.take().copied(), Some(3));
+        assert_eq!(reader.take(), None);
+
+        // entries taken concurrently are never older than those taken before
+        let (mut writer, mut reader) = mailbox::<u32>();
+        let producer = std::thread::spawn(move || {
+            for i in 1..=100_000 {
+                *writer.slot() = i;
+                writer.publish();
+            }
+        });
+        let mut last = 0;
+        while last < 100_000 {
+            if let Some(&mut i) = reader.take() {
+                assert!(i > last, "{i} after {last}");
+                last = i;
+            }
+        }
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn threaded_forwards_pushes_and_pulls() {
+        let mut driver = Threaded::spawn("loopback", Duration::from_millis(1), || {
+            Ok(Box::new(Loopback::new(None)))
+        })
+        .unwrap();
+        let mut buf = [0u8; 2];
+
+        // the worker only pulls once it knows the size of the channel
+        assert_eq!(driver.pull(&mut buf).unwrap(), None);
+        driver.push(&[1, 2]).unwrap();
+        assert_eq!(pull_until_some(&mut driver, &mut buf).unwrap(), 2);
+        assert_eq!(buf, [1, 2]);
+        assert!(driver.rx_timestamp().is_some());
+    }
+
+    /// Blocks in each pull until its sender is dropped
+    struct Blocking(mpsc::Receiver<()>);
+
+    impl IoDriver for Blocking {
+        fn pull(&mut self, _buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+            let _ = self.0.recv();
+            Err(DriverError::Disconnected.into())
+        }
+
+        fn push(&mut self, _buf: &[u8]) -> Result<(), LwskError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn drop_leaves_a_blocked_worker_behind() {
+        let (unblock, blocked) = mpsc::channel();
+        let mut driver = Threaded::spawn("blocking", Duration::from_millis(1), move || {
+            Ok(Box::new(Blocking(blocked)))
+        })
+        .unwrap();
+        assert_eq!(driver.pull(&mut [0u8; 1]).unwrap(), None);
+        std::thread::sleep(Duration::from_millis(10));
+
+        let start = Instant::now();
+        drop(driver);
+        let elapsed = start.elapsed();
+        assert!(elapsed >= STOP_TIMEOUT, "{elapsed:?}");
+        assert!(elapsed < STOP_TIMEOUT * 2, "{elapsed:?}");
+        drop(unblock);
+    }
+}
@@ -1,46 +1,533 @@
 //! UDP driver based on Rust's std library
+//!
+//! The driver either exchanges datagrams with exactly one peer (`connect`), or works unconnected:
+//! pushed data is sent to every address in `send_to` (which may be multicast or broadcast
+//! addresses), and pulled data is accepted from any source on the `allow_from` list.
+//!
+//! Where available, the receive time of each datagram is taken from the OS (`SO_TIMESTAMPNS`).
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::os::fd::AsRawFd;
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
 
 use crate::LwskError;
 
+/// Largest payload a UDP datagram can carry
+const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+/// Configuration of a [Udp] driver beyond the local address
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UdpOptions {
+    /// Only peer to exchange datagrams with
+    pub connect: Option<SocketAddr>,
+
+    /// Destinations of pushed data when not connected
+    pub send_to: Vec<SocketAddr>,
+
+    /// Multicast groups to join
+    pub join: Vec<IpAddr>,
+
+    /// Sources to accept datagrams from when not connected, an empty list accepts any source
+    pub allow_from: Vec<Source>,
+
+    /// Time to live (IPv4) or hop limit (IPv6) of outgoing multicast datagrams
+    pub ttl: Option<u32>,
+
+    /// Interface for multicast traffic
+    pub interface: Option<Interface>,
+
+    /// Whether outgoing multicast datagrams are looped back to the local host
+    pub multicast_loop: Option<bool>,
+
+    /// Allow sending to broadcast addresses
+    pub broadcast: bool,
+}
+
+/// Source address on an allow-list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// Any port on this host
+    Host(IpAddr),
+
+    /// Exactly this address and port
+    Socket(SocketAddr),
+}
+
+/// Network interface used for multicast
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interface {
+    /// Local IPv4 address of the interface
+    V4(Ipv4Addr),
+
+    /// Index of the interface, as used by IPv6
+    Index(u32),
+}
+
 pub struct Udp {
-    socket: std::net::UdpSocket,
+    socket: UdpSocket,
+    connected: bool,
+    send_to: Vec<SocketAddr>,
+    allow_from: Vec<Source>,
+
+    /// Receive buffer used to filter unconnected traffic before touching the channel
+    scratch: Vec<u8>,
+
+    /// Receive time of the last pulled datagram, as reported by the OS
+    rx_timestamp: Option<Duration>,
+}
+
+impl Source {
+    /// Whether datagrams from `addr` are accepted by this source
+    pub fn matches(&self, addr: &SocketAddr) -> bool {
+        match self {
+            Source::Host(ip) => addr.ip() == *ip,
+            Source::Socket(socket_addr) => addr == socket_addr,
+        }
+    }
+}
+
+impl core::str::FromStr for Source {
+    type Err = LwskError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = s.parse() {
+            Ok(Source::Socket(addr))
+        } else if let Ok(ip) = s.parse() {
+            Ok(Source::Host(ip))
+        } else {
+            log::error!("could not parse address {s:?}");
+            Err(LwskError::InvalidAddress)
+        }
+    }
+}
+
+impl core::str::FromStr for Interface {
+    type Err = LwskError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = s.parse() {
+            Ok(Interface::V4(addr))
+        } else if let Ok(idx) = s.parse() {
+            Ok(Interface::Index(idx))
+        } else {
+            log::error!("could not parse address {s:?}");
+            Err(LwskError::InvalidAddress)
+        }
+    }
+}
+
+/// Resolve `addr` to exactly one socket address
+pub fn resolve<A: ToSocketAddrs>(addr: A) -> Result<SocketAddr, LwskError> {
+    addr.to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or(LwskError::InvalidAddress)
+        .inspect_err(|_| log::error!("could not resolve address"))
 }
 
 impl Udp {
+    /// Bind to `addr` and exchange datagrams exclusively with `connect`
     pub fn new<A: std::net::ToSocketAddrs, B: std::net::ToSocketAddrs>(
         addr: A,
         connect: B,
     ) -> Result<Self, LwskError> {
-        let socket = std::net::UdpSocket::bind(addr)?;
-        socket.connect(connect)?;
+        let options = UdpOptions {
+            connect: Some(resolve(connect)?),
+            ..Default::default()
+        };
+        Self::with_options(resolve(addr)?, &options)
+    }
+
+    /// Bind to `addr` and configure the socket according to `options`
+    pub fn with_options(addr: SocketAddr, options: &UdpOptions) -> Result<Self, LwskError> {
+        match options.interface {
+            Some(Interface::V4(_)) if addr.is_ipv6() => {
+                log::error!("IPv4 multicast interface given for IPv6 socket bound to {addr}");
+                return Err(LwskError::InvalidDriverConfig);
+            }
+            Some(Interface::Index(_)) if addr.is_ipv4() => {
+                log::error!("multicast interface index given for IPv4 socket bound to {addr}");
+                return Err(LwskError::InvalidDriverConfig);
+            }
+            _ => {}
+        }
+        if let Some(group) = options
+            .join
+            .iter()
+            .find(|group| group.is_ipv4() != addr.is_ipv4())
+        {
+            log::error!("can not join multicast group {group} with socket bound to {addr}");
+            return Err(LwskError::InvalidDriverConfig);
+        }
+
+        let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+
+        // multiple receivers of a multicast group may share the same port
+        if !options.join.is_empty() {
+            socket.set_reuse_address(true)?;
+        }
+        socket.bind(&addr.into())?;
+
+        for group in &options.join {
+            log::debug!("joining multicast group {group}");
+            match (group, options.interface) {
+                (IpAddr::V4(group), Some(Interface::V4(interface))) => {
+                    socket.join_multicast_v4(group, &interface)?
+                }
+                (IpAddr::V4(group), _) => {
+                    socket.join_multicast_v4(group, &Ipv4Addr::UNSPECIFIED)?
+                }
+                (IpAddr::V6(group), Some(Interface::Index(idx))) => {
+                    socket.join_multicast_v6(group, idx)?
+                }
+                (IpAddr::V6(group), _) => socket.join_multicast_v6(group, 0)?,
+            }
+        }
+
+        match options.interface {
+            Some(Interface::V4(interface)) => socket.set_multicast_if_v4(&interface)?,
+            Some(Interface::Index(idx)) => socket.set_multicast_if_v6(idx)?,
+            None => {}
+        }
+
+        if let Some(ttl) = options.ttl {
+            match addr {
+                SocketAddr::V4(_) => socket.set_multicast_ttl_v4(ttl)?,
+                SocketAddr::V6(_) => socket.set_multicast_hops_v6(ttl)?,
+            }
+        }
+
+        if let Some(multicast_loop) = options.multicast_loop {
+            match addr {
+                SocketAddr::V4(_) => socket.set_multicast_loop_v4(multicast_loop)?,
+                SocketAddr::V6(_) => socket.set_multicast_loop_v6(multicast_loop)?,
+            }
+        }
+
+        if options.broadcast {
+            socket.set_broadcast(true)?;
+        }
+
+        if let Some(peer) = options.connect {
+            socket.connect(&peer.into())?;
+        }
         socket.set_nonblocking(true)?;
-        Ok(Self { socket })
+        enable_timestamps(&socket);
+
+        let scratch = if options.connect.is_none() && !options.allow_from.is_empty() {
+            vec![0u8; MAX_DATAGRAM_SIZE]
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            socket: socket.into(),
+            connected: options.connect.is_some(),
+            send_to: options.send_to.clone(),
+            allow_from: options.allow_from.clone(),
+            scratch,
+            rx_timestamp: None,
+        })
+    }
+
+    fn is_allowed(&self, addr: &SocketAddr) -> bool {
+        self.allow_from.iter().any(|source| source.matches(addr))
+    }
+
+    /// Receive the next datagram from an allowed source, discarding all others
+    fn recv_filtered(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let (n, addr, timestamp) = recv_msg(&self.socket, &mut self.scratch)?;
+            match addr {
+                Some(addr) if self.is_allowed(&addr) => {
+                    let n = n.min(buf.len());
+                    buf[..n].copy_from_slice(&self.scratch[..n]);
+                    self.rx_timestamp = timestamp;
+                    return Ok(n);
+                }
+                addr => log::debug!("dropping {n} bytes from {addr:?}, which is not allowed"),
+            }
+        }
     }
 }
 
+/// Ask the OS to timestamp received datagrams, falling back to software timestamps otherwise
+fn enable_timestamps(socket: &Socket) {
+    #[cfg(target_os = "linux")]
+    {
+        let enable: libc::c_int = 1;
+        // SAFETY: the option value is a valid c_int of the given size
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_TIMESTAMPNS,
+                (&enable as *const libc::c_int).cast(),
+                size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            log::warn!(
+                "could not enable receive timestamps: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+/// Receive a datagram, yielding its length, source and receive time as reported by the OS
+fn recv_msg(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> std::io::Result<(usize, Option<SocketAddr>, Option<Duration>)> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    // u64 for alignment, large enough for a cmsghdr carrying a timespec
+    let mut control = [0u64; 8];
+    let mut timestamp = None;
+
+    // SAFETY: all pointers in the message header point to live buffers of the given lengths, and
+    // control messages are only accessed through the CMSG_* macros
+    let (n, addr) = unsafe {
+        SockAddr::try_init(|storage, len| {
+            let mut msg: libc::msghdr = core::mem::zeroed();
+            msg.msg_name = storage.cast();
+            msg.msg_namelen = *len;
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr().cast();
+            msg.msg_controllen = size_of_val(&control) as _;
+
+            let n = libc::recvmsg(socket.as_raw_fd(), &mut msg, 0);
+            if n < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            *len = msg.msg_namelen;
+
+            #[cfg(target_os = "linux")]
+            {
+                let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+                while !cmsg.is_null() {
+                    if (*cmsg).cmsg_level == libc::SOL_SOCKET
+                        && (*cmsg).cmsg_type == libc::SCM_TIMESTAMPNS
+                    {
+                        let time: libc::timespec =
+                            core::ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast());
+                        timestamp = Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32));
+                    }
+                    cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+                }
+            }
+
+            Ok(n as usize)
+        })?
+    };
+
+    Ok((n, addr.as_socket(), timestamp))
+}
+
 impl super::IoDriver for Udp {
-    fn pull(&mut self, buf: &mut [u8]) -> Result<(), LwskError> {
-        match self.socket.recv(buf) {
-            Ok(n) => log::debug!("received {n} bytes from UDP"),
+    fn rx_timestamp(&self) -> Option<Duration> {
+        self.rx_timestamp
+    }
+
+    fn readiness_fd(&self) -> Option<i32> {
+        Some(self.socket.as_raw_fd())
+    }
+
+    fn pull(&mut self, buf: &mut [u8]) -> Result<Option<usize>, LwskError> {
+        let received = if self.connected || self.allow_from.is_empty() {
+            recv_msg(&self.socket, buf).map(|(n, _, timestamp)| {
+                self.rx_timestamp = timestamp;
+                n
+            })
+        } else {
+            self.recv_filtered(buf)
+        };
+
+        match received {
+            Ok(n) => {
+                log::debug!("received {n} bytes from UDP");
+                Ok(Some(n))
+            }
             Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                log::debug!("no new message in UDP port")
+                log::debug!("no new message in UDP port");
+                Ok(None)
             }
             Err(e) => {
                 log::error!("could not receive from UDP socket: {e}");
-                return Err(LwskError::DriverError(e.raw_os_error().unwrap().into()));
+                Err(LwskError::DriverError(e.into()))
             }
         }
-        Ok(())
     }
 
     fn push(&mut self, buf: &[u8]) -> Result<(), LwskError> {
-        match self.socket.send(buf) {
-            Ok(n) => log::debug!("wrote {n} byte to UDP"),
-            Err(e) => {
-                log::error!("could not send to UDP socket: {e}");
-                return Err(LwskError::DriverError(e.raw_os_error().unwrap().into()));
+        if self.connected {
+            match self.socket.send(buf) {
+                Ok(n) => log::debug!("wrote {n} byte to UDP"),
+                Err(e) => {
+                    log::error!("could not send to UDP socket: {e}");
+                    return Err(LwskError::DriverError(e.into()));
+                }
+            }
+            return Ok(());
+        }
+
+        if self.send_to.is_empty() {
+            log::warn!("UDP socket has neither a peer nor destinations, dropping data");
+        }
+
+        for destination in &self.send_to {
+            match self.socket.send_to(buf, destination) {
+                Ok(n) => log::debug!("wrote {n} byte to UDP destination {destination}"),
+                Err(e) => {
+                    log::error!("could not send to UDP destination {destination}: {e}");
+                    return Err(LwskError::DriverError(e.into()));
+                }
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::IoDriver;
+
+    fn local_addr(udp: &Udp) -> SocketAddr {
+        udp.socket.local_addr().unwrap()
+    }
+
+    /// Pull from `udp` until a datagram arrives, or give up after a second
+    fn pull(udp: &mut Udp) -> Option<Vec<u8>> {
+        let mut buf = [0u8; 16];
+        for _ in 0..1000 {
+            if let Some(n) = udp.pull(&mut buf).unwrap() {
+                return Some(buf[..n].to_vec());
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        None
+    }
+
+    fn bind(addr: &str, options: UdpOptions) -> Udp {
+        Udp::with_options(addr.parse().unwrap(), &options).unwrap()
+    }
+
+    #[test]
+    fn unicast_to_and_from_multiple_peers() {
+        let mut a = bind("127.0.0.1:0", UdpOptions::default());
+        let mut b = bind("127.0.0.1:0", UdpOptions::default());
+        let mut sender = bind(
+            "127.0.0.1:0",
+            UdpOptions {
+                send_to: vec![local_addr(&a), local_addr(&b)],
+                ..Default::default()
+            },
+        );
+        sender.push(b"both").unwrap();
+        assert_eq!(pull(&mut a).as_deref(), Some(&b"both"[..]));
+        assert_eq!(pull(&mut b).as_deref(), Some(&b"both"[..]));
+
+        let mut filtered = bind(
+            "127.0.0.1:0",
+            UdpOptions {
+                allow_from: vec![Source::Socket(local_addr(&a))],
+                ..Default::default()
+            },
+        );
+        let destination = local_addr(&filtered);
+        b.socket.send_to(b"from b", destination).unwrap();
+        a.socket.send_to(b"from a", destination).unwrap();
+        assert_eq!(pull(&mut filtered).as_deref(), Some(&b"from a"[..]));
+        assert!(filtered.rx_timestamp().is_some());
+    }
+
+    #[test]
+    fn connected_peer() {
+        let mut a = bind("127.0.0.1:0", UdpOptions::default());
+        let mut b = Udp::new("127.0.0.1:0", local_addr(&a)).unwrap();
+        a.socket.connect(local_addr(&b)).unwrap();
+        a.connected = true;
+
+        b.push(b"ping").unwrap();
+        assert_eq!(pull(&mut a).as_deref(), Some(&b"ping"[..]));
+        a.push(b"pong").unwrap();
+        assert_eq!(pull(&mut b).as_deref(), Some(&b"pong"[..]));
+    }
+
+    #[test]
+    fn broadcast() {
+        let mut receiver = bind("0.0.0.0:0", UdpOptions::default());
+        let port = local_addr(&receiver).port();
+        let destination = SocketAddr::from(([127, 255, 255, 255], port));
+
+        let mut refused = bind(
+            "127.0.0.1:0",
+            UdpOptions {
+                send_to: vec![destination],
+                ..Default::default()
+            },
+        );
+        assert!(refused.push(b"refused").is_err());
+
+        let mut sender = bind(
+            "127.0.0.1:0",
+            UdpOptions {
+                send_to: vec![destination],
+                broadcast: true,
+                ..Default::default()
+            },
+        );
+        sender.push(b"everyone").unwrap();
+        assert_eq!(pull(&mut receiver).as_deref(), Some(&b"everyone"[..]));
+    }
+
+    #[test]
+    fn multicast() {
+        let group = Ipv4Addr::new(239, 255, 76, 83);
+        let loopback = Interface::V4(Ipv4Addr::LOCALHOST);
+        let receiver_options = UdpOptions {
+            join: vec![group.into()],
+            interface: Some(loopback),
+            ..Default::default()
+        };
+        let mut receiver = bind("0.0.0.0:0", receiver_options.clone());
+        let port = local_addr(&receiver).port();
+        let mut other = bind(&format!("0.0.0.0:{port}"), receiver_options);
+
+        let mut sender = bind(
+            "127.0.0.1:0",
+            UdpOptions {
+                send_to: vec![SocketAddr::from((group, port))],
+                interface: Some(loopback),
+                ttl: Some(1),
+                multicast_loop: Some(true),
+                ..Default::default()
+            },
+        );
+        sender.push(b"group").unwrap();
+        assert_eq!(pull(&mut receiver).as_deref(), Some(&b"group"[..]));
+        assert_eq!(pull(&mut other).as_deref(), Some(&b"group"[..]));
+    }
+
+    #[test]
+    fn mismatched_address_families_are_rejected() {
+        let index = UdpOptions {
+            interface: Some(Interface::Index(1)),
+            ..Default::default()
+        };
+        assert!(Udp::with_options("127.0.0.1:0".parse().unwrap(), &index).is_err());
+
+        let v6_group = UdpOptions {
+            join: vec!["ff02::1".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(Udp::with_options("127.0.0.1:0".parse().unwrap(), &v6_group).is_err());
+    }
+}
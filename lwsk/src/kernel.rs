@@ -1,8 +1,10 @@
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::time::Duration;
-use std::io::Read;
 
 use wasmi::TypedFunc;
 
+use crate::io::{DriverError, ErrorAction, Health, HealthEvent, IoBinding};
 use crate::schedule::{Schedule, ScheduleEntry};
 use crate::LwskError;
 
@@ -20,14 +22,55 @@ pub struct KernelConfig {
     pub schedules: Vec<Schedule>,
 
     /// IO driver which allow to connect external information sources and sinks to channels
-    pub io: Vec<Box<dyn crate::io::IoDriver>>,
+    pub io: Vec<IoBinding>,
 
     /// Index of the initial schedule
     pub current_schedule_idx: usize,
+
+    /// Health events raised by IO bindings, to be drained via [Self::take_health_events]
+    pub health_events: Vec<HealthEvent>,
 }
 
 pub struct KernelState {}
 
+/// Outcome of a single [KernelConfig::step]
+#[derive(Debug)]
+pub enum Step {
+    /// A function was invoked, yielding the result of its entry function
+    Invoked {
+        function_idx: usize,
+        result: Result<i32, LwskError>,
+    },
+
+    /// An IO driver was pulled into a channel
+    Pulled {
+        io_idx: usize,
+        channel_idx: usize,
+        result: Result<Option<usize>, DriverError>,
+    },
+
+    /// A channel was pushed to an IO driver
+    Pushed {
+        channel_idx: usize,
+        io_idx: usize,
+        result: Result<(), DriverError>,
+    },
+
+    /// A sporadic function was not invoked, as its input was not updated
+    Skipped { function_idx: usize },
+
+    /// The schedule asks to wait for the given time
+    Wait(Duration),
+
+    /// The schedule asks to wait until an IO driver has data to pull, at most for `timeout`
+    ///
+    /// See [IoBinding::wait_ready].
+    AwaitIo { io_idx: usize, timeout: Duration },
+
+    /// The schedule switched to another one
+    Switched { schedule_idx: usize },
+}
+
 /// A function as defined in the servereless idiom
 ///
 /// Functions are characterized by having an actual entry function (as in a callable Wasm function),
@@ -56,6 +99,22 @@ pub struct Function {
 
     /// Upper limit of fuel available per call to this function
     pub fuel_per_call: u64,
+
+    /// [Channel::generation] of the consumed channel at the last invocation
+    pub consumed_generation: u64,
+
+    /// Static configuration written via [Self::configure], if any
+    pub config: Option<StaticConfig>,
+}
+
+/// Static configuration of a [Function], written into one of its globals once at load time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaticConfig {
+    /// Name of the exported global
+    pub global: String,
+
+    /// Serialized configuration
+    pub data: Vec<u8>,
 }
 
 /// A place in memory to hold state
@@ -67,6 +126,15 @@ pub struct Channel {
 
     /// Buffer backing up the data
     pub buf: Vec<u8>,
+
+    /// Whether the data is valid, cleared by failed pulls of IO bindings which invalidate
+    pub valid: bool,
+
+    /// Receive time of the IO data this channel's contents derive from, since the Unix epoch
+    pub timestamp: Option<Duration>,
+
+    /// Number of updates of this channel, by pulls of IO data or by producing functions
+    pub generation: u64,
 }
 
 impl KernelConfig {
@@ -81,7 +149,17 @@ impl KernelConfig {
     ///   - ... it references an existing function, if any
     ///   - ... it references an existing channel, if any
     ///   - ... it references an existing io, if any
+    ///   - ... it references an existing schedule, if any
+    /// - that the initial schedule exists
     pub fn validate(&self) -> Result<(), LwskError> {
+        if self.schedules.get(self.current_schedule_idx).is_none() {
+            error!(
+                "initial schedules[{}] does not exist",
+                self.current_schedule_idx
+            );
+            return Err(LwskError::InvalidScheduleIdx(self.current_schedule_idx));
+        }
+
         for (function_idx, f) in self.functions.iter().enumerate() {
             if let Some(channel_idx) = f.consumes {
                 debug!(
@@ -151,6 +229,19 @@ impl KernelConfig {
                             return Err(LwskError::InvalidFunctionIdx(*function_idx));
                         }
                     }
+                    ScheduleEntry::SporadicInvocation(function_idx) => {
+                        debug!("checking existance of functions[{function_idx}]");
+                        let Some(f) = self.functions.get(*function_idx) else {
+                            error!("functions[{function_idx}] does not exist");
+                            return Err(LwskError::InvalidFunctionIdx(*function_idx));
+                        };
+                        if f.consumes.is_none() {
+                            warn!(
+                                "{:?}/functions[{function_idx}] is sporadic, but consumes no channel, thus never runs",
+                                f.name
+                            );
+                        }
+                    }
                     ScheduleEntry::IoIn {
                         from_io_idx,
                         to_channel_idx,
@@ -183,6 +274,13 @@ impl KernelConfig {
                             return Err(LwskError::InvalidIoIdx(*to_io_idx));
                         }
                     }
+                    ScheduleEntry::AwaitIo { io_idx, .. } => {
+                        debug!("checking existance of io[{io_idx}]");
+                        if self.io.get(*io_idx).is_none() {
+                            error!("io[{io_idx}] does not exist");
+                            return Err(LwskError::InvalidIoIdx(*io_idx));
+                        }
+                    }
                     ScheduleEntry::Wait(duration) => {
                         if *duration > Duration::from_secs(10) {
                             warn!("found a duration greater than 10 s, that might hurt real-time performance bad");
@@ -190,9 +288,9 @@ impl KernelConfig {
                     }
                     ScheduleEntry::SwitchSchedule(schedule_idx) => {
                         debug!("checking existance of schedules[{schedule_idx}]",);
-                        if sched.sequence.get(*schedule_idx).is_none() {
+                        if self.schedules.get(*schedule_idx).is_none() {
                             error!("schedules[{schedule_idx}] does not exist");
-                            return Err(LwskError::WasmLoadError);
+                            return Err(LwskError::InvalidScheduleIdx(*schedule_idx));
                         }
                     }
                 }
@@ -202,6 +300,273 @@ impl KernelConfig {
 
         Ok(())
     }
+
+    /// Perform the next action of the current schedule
+    ///
+    /// Waiting is left to the caller, so that tests may run schedules without delay.
+    pub fn step(&mut self) -> Step {
+        let action = self.schedules[self.current_schedule_idx].next_action();
+        match action {
+            ScheduleEntry::FunctionInvocation(function_idx) => Step::Invoked {
+                function_idx,
+                result: self.invoke(function_idx),
+            },
+            ScheduleEntry::SporadicInvocation(function_idx) => {
+                if self.input_updated(function_idx) {
+                    Step::Invoked {
+                        function_idx,
+                        result: self.invoke(function_idx),
+                    }
+                } else {
+                    trace!("skipping functions[{function_idx}], its input was not updated");
+                    Step::Skipped { function_idx }
+                }
+            }
+            ScheduleEntry::IoIn {
+                from_io_idx,
+                to_channel_idx,
+            } => {
+                trace!("pulling data from io[{from_io_idx}] to channels[{to_channel_idx}]");
+                Step::Pulled {
+                    io_idx: from_io_idx,
+                    channel_idx: to_channel_idx,
+                    result: self.pull_io(from_io_idx, to_channel_idx),
+                }
+            }
+            ScheduleEntry::IoOut {
+                from_channel_idx,
+                to_io_idx,
+            } => {
+                trace!("pushing data from channels[{from_channel_idx}] to io[{to_io_idx}]");
+                Step::Pushed {
+                    channel_idx: from_channel_idx,
+                    io_idx: to_io_idx,
+                    result: self.push_io(from_channel_idx, to_io_idx),
+                }
+            }
+            ScheduleEntry::Wait(duration) => Step::Wait(duration),
+            ScheduleEntry::AwaitIo { io_idx, timeout } => Step::AwaitIo { io_idx, timeout },
+            ScheduleEntry::SwitchSchedule(new_schedule_idx) => {
+                debug!(
+                    "switch from schedule[{}] to schedule[{new_schedule_idx}]",
+                    self.current_schedule_idx
+                );
+                // set the next schedule id
+                self.current_schedule_idx = new_schedule_idx;
+                // reset the schedule to its start
+                self.schedules[self.current_schedule_idx].restart();
+                Step::Switched {
+                    schedule_idx: new_schedule_idx,
+                }
+            }
+        }
+    }
+
+    /// Invoke `functions[function_idx]`, copying its input and output from and to channels
+    pub fn invoke(&mut self, function_idx: usize) -> Result<i32, LwskError> {
+        // get the corresponding kernel function
+        let f = self
+            .functions
+            .get_mut(function_idx)
+            .ok_or(LwskError::InvalidFunctionIdx(function_idx))?;
+
+        // set input if necessary
+        if let Some(channel_idx) = f.consumes {
+            let channel = &self.channels[channel_idx];
+            trace!(
+                "copying {:?}/channels[{channel_idx}] -> {:?}/functions[{function_idx}].INPUT",
+                channel.name,
+                f.name
+            );
+
+            if f.instance.get_global(&f.store, "INPUT").is_none() {
+                warn!("{:?}/functions[{function_idx}] has no INPUT", f.name);
+                return Err(LwskError::GlobalDoesNotExist);
+            }
+            f.get_global_mut("INPUT", channel.buf.len())?
+                .copy_from_slice(&channel.buf);
+            f.consumed_generation = channel.generation;
+
+            // tell the function when its input was received, if it cares
+            if f.instance.get_global(&f.store, "INPUT_TIMESTAMP").is_some() {
+                let ns = channel.timestamp.map_or(0, |t| t.as_nanos() as u64);
+                f.get_global_mut("INPUT_TIMESTAMP", 8)?
+                    .copy_from_slice(&ns.to_le_bytes());
+            }
+
+            // tell the function about invalidated input, if it cares
+            if f.instance.get_global(&f.store, "INPUT_VALID").is_some() {
+                f.get_global_mut("INPUT_VALID", 1)?[0] = channel.valid as u8;
+            } else if !channel.valid {
+                debug!(
+                    "{:?}/functions[{function_idx}] consumes invalid {:?}/channels[{channel_idx}]",
+                    f.name, channel.name
+                );
+            }
+        }
+
+        // get the function
+        let process_data = f.get_entry_function()?;
+
+        // refuel
+        let amount = f.fuel_per_call; // TODO adjust fuel stuff
+        trace!("refuel {:?}/functions[{function_idx}] to {amount}", f.name);
+        f.store
+            .set_fuel(amount)
+            .map_err(|_| LwskError::WasmLoadError)?;
+        let fuel_before = amount;
+
+        // get current time
+        #[cfg(feature = "std")]
+        let now = std::time::Instant::now();
+
+        // call the function
+        let result = process_data.call(&mut f.store, ()).map_err(|e| {
+            warn!("{:?}/functions[{function_idx}] trapped: {e}", f.name);
+            LwskError::FunctionTrapped
+        })?;
+
+        // calculate fuel consumption
+        let fuel_after = f.store.get_fuel().unwrap_or_default();
+        let fuel_consumed = fuel_before - fuel_after;
+
+        // time difference since before the call, without std there is no clock to tell
+        #[cfg(feature = "std")]
+        {
+            let duration = now.elapsed();
+            trace!(
+                "{:?}/functions[{function_idx}] took {duration:?}, consumed {fuel_consumed} fuel",
+                f.name
+            );
+
+            let (fuel_per_time, time_unit) =
+                crate::format_fuel_consumption(fuel_consumed, duration);
+
+            debug!(
+                "burned {fuel_per_time} f/{time_unit}, taking {:?}/fuel",
+                duration.div_f32(fuel_consumed as f32)
+            );
+        }
+        #[cfg(not(feature = "std"))]
+        trace!(
+            "{:?}/functions[{function_idx}] consumed {fuel_consumed} fuel",
+            f.name
+        );
+
+        // anounce the result
+        debug!(
+            "calling {:?}/functions[{function_idx}] yielded {result}",
+            f.name
+        );
+
+        // retrieve outputs if necessary
+        if let Some(channel_idx) = f.produces {
+            trace!(
+                "copying {:?}/functions[{function_idx}].OUTPUT -> {:?}/channels[{channel_idx}]",
+                f.name,
+                self.channels[channel_idx].name,
+            );
+
+            let len = self.channels[channel_idx].buf.len();
+            let wasm_output_buf = f.get_global("OUTPUT", len)?;
+            self.channels[channel_idx]
+                .buf
+                .copy_from_slice(wasm_output_buf);
+
+            // the output is as old as the input it was derived from
+            self.channels[channel_idx].timestamp =
+                f.consumes.and_then(|idx| self.channels[idx].timestamp);
+            self.channels[channel_idx].generation += 1;
+        }
+
+        Ok(result)
+    }
+
+    /// Whether the channel consumed by `functions[function_idx]` was updated since its last
+    /// invocation
+    pub fn input_updated(&self, function_idx: usize) -> bool {
+        let f = &self.functions[function_idx];
+        f.consumes
+            .is_some_and(|idx| self.channels[idx].generation != f.consumed_generation)
+    }
+
+    /// Pull from `io[io_idx]` into `channels[channel_idx]`, applying the binding's error policy
+    pub fn pull_io(
+        &mut self,
+        io_idx: usize,
+        channel_idx: usize,
+    ) -> Result<Option<usize>, DriverError> {
+        let channel = &mut self.channels[channel_idx];
+        let result = self.io[io_idx].pull(&mut channel.buf);
+        match &result {
+            Ok(Some(_)) => {
+                channel.valid = true;
+                channel.timestamp = self.io[io_idx].rx_timestamp();
+                channel.generation += 1;
+            }
+            Ok(None) => {}
+            Err(e) => self.handle_io_error(io_idx, Some(channel_idx), e),
+        }
+        result
+    }
+
+    /// Push `channels[channel_idx]` to `io[io_idx]`, applying the binding's error policy
+    pub fn push_io(&mut self, channel_idx: usize, io_idx: usize) -> Result<(), DriverError> {
+        let channel = &self.channels[channel_idx];
+        let result = self.io[io_idx].push(&channel.buf);
+        match &result {
+            Ok(()) =>
+            {
+                #[cfg(feature = "std")]
+                if let Some(timestamp) = channel.timestamp {
+                    let now = std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .unwrap_or_default();
+                    trace!(
+                        "{:?}/channels[{channel_idx}] reached {:?}/io[{io_idx}] {:?} after it was received",
+                        channel.name,
+                        self.io[io_idx].name,
+                        now.saturating_sub(timestamp)
+                    );
+                }
+            }
+            Err(e) => self.handle_io_error(io_idx, None, e),
+        }
+        result
+    }
+
+    fn handle_io_error(&mut self, io_idx: usize, channel_idx: Option<usize>, error: &DriverError) {
+        let binding = &self.io[io_idx];
+        match binding.policy.action {
+            ErrorAction::Ignore => warn!("{:?}/io[{io_idx}] failed: {error}", binding.name),
+            ErrorAction::Invalidate => {
+                warn!("{:?}/io[{io_idx}] failed: {error}", binding.name);
+                if let Some(channel_idx) = channel_idx {
+                    debug!(
+                        "invalidating {:?}/channels[{channel_idx}]",
+                        self.channels[channel_idx].name
+                    );
+                    self.channels[channel_idx].valid = false;
+                }
+            }
+            ErrorAction::HealthEvent => self.health_events.push(HealthEvent {
+                io_idx,
+                error: error.clone(),
+            }),
+        }
+    }
+
+    /// Health of all IO bindings, by name
+    pub fn io_health(&self) -> impl Iterator<Item = (&str, Health)> {
+        self.io
+            .iter()
+            .map(|binding| (binding.name.as_str(), binding.health()))
+    }
+
+    /// Remove and return all health events raised since the last call
+    pub fn take_health_events(&mut self) -> Vec<HealthEvent> {
+        core::mem::take(&mut self.health_events)
+    }
 }
 
 pub fn initialize_wasm() -> (wasmi::Engine, wasmi::Store<()>) {
@@ -215,30 +580,30 @@ pub fn initialize_wasm() -> (wasmi::Engine, wasmi::Store<()>) {
 type EntryFunctionType = TypedFunc<(), i32>;
 
 impl Function {
+    /// Load a function from a Wasm module file, without file system load an image instead
+    #[cfg(feature = "std")]
     pub fn load(name: &str, wasm_module_path: &str) -> Result<Self, LwskError> {
+        use std::io::Read;
+
         trace!("loading function {name:?} from {wasm_module_path:?}");
 
-        #[cfg(feature = "std")]
-        let wasm_bytes = {
-            let mut buf = Vec::new();
-            if let Err(e) =
-                std::fs::File::open(wasm_module_path).and_then(|mut f| f.read_to_end(&mut buf))
-            {
-                error!("could not open file {wasm_module_path:?}: {e}");
-                return Err(LwskError::WasmLoadError);
-            }
-            buf
-        };
+        let mut wasm_bytes = Vec::new();
+        if let Err(e) =
+            std::fs::File::open(wasm_module_path).and_then(|mut f| f.read_to_end(&mut wasm_bytes))
+        {
+            error!("could not open file {wasm_module_path:?}: {e}");
+            return Err(LwskError::WasmLoadError);
+        }
 
-        #[cfg(not(feature = "std"))]
-        let wasm_bytes = {
-            todo!("interprete the path as resource descriptor for a fit image or something, get a byte slice, be done with it")
-        };
+        Self::from_bytes(name, &wasm_bytes)
+    }
 
+    /// Load a function from the bytes of its Wasm module, e.g. as bundled in an [crate::image]
+    pub fn from_bytes(name: &str, wasm_bytes: &[u8]) -> Result<Self, LwskError> {
         let (engine, mut store) = super::initialize_wasm();
 
-        trace!("parsing wasm file");
-        let module = match wasmi::Module::new(&engine, wasm_bytes.as_slice()) {
+        trace!("parsing wasm module of {name:?}");
+        let module = match wasmi::Module::new(&engine, wasm_bytes) {
             Ok(module) => module,
             Err(e) => {
                 error!("could not load wasm module: {e}");
@@ -251,7 +616,7 @@ impl Function {
         let instance = match linker.instantiate(&mut store, &module) {
             Ok(instance) => instance,
             Err(e) => {
-                error!("could not link wasm module {wasm_module_path:?}: {e}");
+                error!("could not link wasm module of {name:?}: {e}");
                 return Err(LwskError::WasmLoadError);
             }
         };
@@ -261,7 +626,7 @@ impl Function {
         let started_instance = match instance.start(&mut store) {
             Ok(instance) => instance,
             Err(e) => {
-                error!("could not start wasm module {wasm_module_path:?}: {e}");
+                error!("could not start wasm module of {name:?}: {e}");
                 return Err(LwskError::WasmLoadError);
             }
         };
@@ -275,6 +640,8 @@ impl Function {
             store,
             instance: started_instance,
             fuel_per_call: 0,
+            consumed_generation: 0,
+            config: None,
         })
     }
 
@@ -325,7 +692,8 @@ impl Function {
 
     /// Get a shared ref to the memory backing a global in this functions Wasm module
     pub fn get_global(&self, ident: &str, len: usize) -> Result<&[u8], LwskError> {
-        let idx = self.get_global_idx(ident)? as usize;
+        // Wasm pointers are unsigned, a sign extension would point past the memory
+        let idx = self.get_global_idx(ident)? as u32 as usize;
 
         let mem_name = "memory";
         let memory = self
@@ -338,21 +706,22 @@ impl Function {
                     self.name
                 );
             })?;
-        let buf = &memory.data(&self.store)[idx..(idx + len)];
-
-        if buf.len() < len {
-            return Err(LwskError::BufferTooSmall {
+        let data = memory.data(&self.store);
+        let available = data.len().saturating_sub(idx);
+        idx.checked_add(len)
+            .and_then(|end| data.get(idx..end))
+            .ok_or(LwskError::BufferTooSmall {
                 expected: len,
-                got: buf.len(),
-            });
-        }
-
-        Ok(buf)
+                got: available,
+            })
+            .inspect_err(|_| {
+                error!("global {ident:?} does not have {len} bytes of memory behind it");
+            })
     }
 
     /// Get a mutable ref to the data backing a global in this functions Wasm module
     pub fn get_global_mut(&mut self, ident: &str, len: usize) -> Result<&mut [u8], LwskError> {
-        let idx = self.get_global_idx(ident)? as usize;
+        let idx = self.get_global_idx(ident)? as u32 as usize;
 
         let mem_name = "memory";
         let memory = self
@@ -365,15 +734,281 @@ impl Function {
                     self.name
                 );
             })?;
-        let buf = &mut memory.data_mut(&mut self.store)[idx..(idx + len)];
-
-        if buf.len() < len {
-            return Err(LwskError::BufferTooSmall {
+        let data = memory.data_mut(&mut self.store);
+        let available = data.len().saturating_sub(idx);
+        idx.checked_add(len)
+            .and_then(|end| data.get_mut(idx..end))
+            .ok_or(LwskError::BufferTooSmall {
                 expected: len,
-                got: buf.len(),
-            });
+                got: available,
+            })
+            .inspect_err(|_| {
+                error!("global {ident:?} does not have {len} bytes of memory behind it");
+            })
+    }
+
+    /// Write the static configuration `data` into the memory backing the global `global`
+    ///
+    /// This is meant to happen once after loading, before the function is invoked the first time.
+    pub fn configure(&mut self, global: &str, data: &[u8]) -> Result<(), LwskError> {
+        trace!(
+            "writing {} bytes of configuration to {global:?}",
+            data.len()
+        );
+        self.get_global_mut(global, data.len())?
+            .copy_from_slice(data);
+        self.config = Some(StaticConfig {
+            global: global.into(),
+            data: data.into(),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::blueprint::Blueprint;
+    use crate::io::memory::Sink;
+
+    /// Adds one to each of four bytes, returning the first one
+    const ADD: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global (export "INPUT") i32 (i32.const 0))
+          (global (export "OUTPUT") i32 (i32.const 4))
+          (func (export "process") (result i32)
+            (i32.store (i32.const 4) (i32.add (i32.load (i32.const 0)) (i32.const 0x01010101)))
+            (i32.load8_u (i32.const 4))))
+    "#;
+
+    const BLUEPRINT: &str = r#"
+        [channels.input]
+        size = 4
+        [channels.output]
+        size = 4
+        [channels.looped]
+        size = 4
+
+        [functions.add]
+        wasm = "add.wasm"
+        consumes = "input"
+        produces = "output"
+        fuel_per_call = 1000
+
+        [io.seq]
+        type = "Sequence"
+        values = [[1, 2, 3, 4]]
+        [io.sink]
+        type = "Sink"
+        [io.loop]
+        type = "Loopback"
+
+        [[schedules.a]]
+        from_io = "seq"
+        to_channel = "input"
+        [[schedules.a]]
+        sporadic_function = "add"
+        [[schedules.a]]
+        from_channel = "output"
+        to_io = "sink"
+        [[schedules.a]]
+        from_channel = "output"
+        to_io = "loop"
+        [[schedules.a]]
+        switch_to_schedule = "b"
+
+        [[schedules.b]]
+        from_io = "loop"
+        to_channel = "looped"
+        [[schedules.b]]
+        sporadic_function = "add"
+        [[schedules.b]]
+        from_channel = "looped"
+        to_io = "sink"
+        [[schedules.b]]
+        wait_ns = 1000
+        [[schedules.b]]
+        switch_to_schedule = "a"
+    "#;
+
+    fn kernel() -> KernelConfig {
+        let dir = std::env::temp_dir().join(format!("lwsk-kernel-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("add.wasm"), wat::parse_str(ADD).unwrap()).unwrap();
+        std::fs::write(dir.join("blueprint.toml"), BLUEPRINT).unwrap();
+        let kernel = Blueprint::new(dir.join("blueprint.toml"))
+            .unwrap()
+            .to_kernel_config()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        kernel
+    }
+
+    #[test]
+    fn step_runs_schedules_with_in_memory_drivers() {
+        let mut kernel = kernel();
+        let channel = |name| kernel.channels.iter().position(|c| c.name == name).unwrap();
+        let (input, output, looped) = (channel("input"), channel("output"), channel("looped"));
+        let io = |name| kernel.io.iter().position(|io| io.name == name).unwrap();
+        let (seq, sink, lo) = (io("seq"), io("sink"), io("loop"));
+        let (a, b) = (0, 1);
+
+        for round in 0..2 {
+            let step = kernel.step();
+            match round {
+                0 => assert!(
+                    matches!(step, Step::Pulled { io_idx, channel_idx, result: Ok(Some(4)) }
+                        if io_idx == seq && channel_idx == input),
+                    "{step:?}"
+                ),
+                _ => assert!(
+                    matches!(step, Step::Pulled { io_idx, result: Ok(None), .. } if io_idx == seq),
+                    "{step:?}"
+                ),
+            }
+            let step = kernel.step();
+            match round {
+                0 => assert!(
+                    matches!(
+                        step,
+                        Step::Invoked {
+                            function_idx: 0,
+                            result: Ok(2)
+                        }
+                    ),
+                    "{step:?}"
+                ),
+                _ => assert!(
+                    matches!(step, Step::Skipped { function_idx: 0 }),
+                    "{step:?}"
+                ),
+            }
+            for to in [sink, lo] {
+                let step = kernel.step();
+                assert!(
+                    matches!(step, Step::Pushed { channel_idx, io_idx, result: Ok(()) }
+                        if channel_idx == output && io_idx == to),
+                    "{step:?}"
+                );
+            }
+            let step = kernel.step();
+            assert!(
+                matches!(step, Step::Switched { schedule_idx } if schedule_idx == b),
+                "{step:?}"
+            );
+
+            let step = kernel.step();
+            assert!(
+                matches!(step, Step::Pulled { io_idx, channel_idx, result: Ok(Some(4)) }
+                    if io_idx == lo && channel_idx == looped),
+                "{step:?}"
+            );
+            let step = kernel.step();
+            assert!(
+                matches!(step, Step::Skipped { function_idx: 0 }),
+                "{step:?}"
+            );
+            let step = kernel.step();
+            assert!(
+                matches!(step, Step::Pushed { channel_idx, io_idx, result: Ok(()) }
+                    if channel_idx == looped && io_idx == sink),
+                "{step:?}"
+            );
+            let step = kernel.step();
+            assert!(
+                matches!(step, Step::Wait(wait) if wait == Duration::from_nanos(1000)),
+                "{step:?}"
+            );
+            let step = kernel.step();
+            assert!(
+                matches!(step, Step::Switched { schedule_idx } if schedule_idx == a),
+                "{step:?}"
+            );
         }
 
-        Ok(buf)
+        assert_eq!(kernel.channels[output].buf, [2, 3, 4, 5]);
+        assert_eq!(kernel.channels[looped].buf, [2, 3, 4, 5]);
+        assert_eq!(kernel.channels[output].generation, 1);
+        let sink = kernel.io[sink].driver.as_any().unwrap();
+        let pushed: Vec<_> = sink.downcast_ref::<Sink>().unwrap().pushed().collect();
+        assert_eq!(pushed, [[2, 3, 4, 5]; 4]);
+    }
+
+    #[test]
+    fn constant_is_pulled_on_every_step() {
+        let dir = std::env::temp_dir().join(format!("lwsk-constant-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("blueprint.toml"),
+            r#"
+            [channels.c]
+            size = 2
+            [io.constant]
+            type = "Constant"
+            data = "hi"
+            [[schedules.a]]
+            from_io = "constant"
+            to_channel = "c"
+            "#,
+        )
+        .unwrap();
+        let mut kernel = Blueprint::new(dir.join("blueprint.toml"))
+            .unwrap()
+            .to_kernel_config()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        for generation in 1..=3 {
+            let step = kernel.step();
+            assert!(
+                matches!(
+                    step,
+                    Step::Pulled {
+                        result: Ok(Some(2)),
+                        ..
+                    }
+                ),
+                "{step:?}"
+            );
+            assert_eq!(kernel.channels[0].buf, *b"hi");
+            assert_eq!(kernel.channels[0].generation, generation);
+        }
+    }
+
+    #[test]
+    fn globals_outside_of_memory_are_rejected() {
+        let dir = std::env::temp_dir().join(format!("lwsk-global-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let wasm = wat::parse_str(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (global (export "NEGATIVE") i32 (i32.const -4))
+              (global (export "LAST") i32 (i32.const 65532)))
+            "#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("globals.wasm"), wasm).unwrap();
+        let path = dir.join("globals.wasm");
+        let mut function = Function::load("globals", path.to_str().unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(function.get_global("LAST", 4).unwrap().len(), 4);
+        assert!(matches!(
+            function.get_global("LAST", 5),
+            Err(LwskError::BufferTooSmall {
+                expected: 5,
+                got: 4
+            })
+        ));
+        assert!(matches!(
+            function.get_global_mut("NEGATIVE", 4),
+            Err(LwskError::BufferTooSmall { got: 0, .. })
+        ));
+        assert!(matches!(
+            function.get_global("LAST", usize::MAX),
+            Err(LwskError::BufferTooSmall { .. })
+        ));
     }
 }
@@ -1,11 +1,24 @@
 #![cfg_attr(not(any(test, feature = "std")), no_std)]
 
-#[cfg(not(feature = "std"))]
 extern crate alloc;
 
+use alloc::string::String;
+
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
 pub mod blueprint;
+#[cfg(feature = "std")]
+pub mod cli;
+#[cfg(feature = "std")]
+pub mod diff;
+#[cfg(feature = "std")]
+pub mod graph;
+pub mod image;
 pub mod io;
 pub mod kernel;
+#[cfg(feature = "std")]
+pub mod resolved;
 pub mod schedule;
 
 pub use kernel::*;
@@ -39,8 +52,48 @@ pub enum LwskError {
     #[error("The buffer is to small. Got {got}, expected at least {expected}")]
     BufferTooSmall { expected: usize, got: usize },
 
-    #[error("TODO")]
-    DriverError(i64),
+    #[error("IO driver failed: {0}")]
+    DriverError(#[from] io::DriverError),
+
+    #[error("An address could not be parsed or resolved")]
+    InvalidAddress,
+
+    #[error("The recording is malformed or does not match the blueprint")]
+    InvalidRecording,
+
+    #[error("Data could not be encoded or decoded")]
+    CodecError,
+
+    #[error("A function trapped or ran out of fuel")]
+    FunctionTrapped,
+
+    #[error("No IO driver of type {0:?} is known")]
+    UnknownDriverType(String),
+
+    #[error("The IO driver type {0:?} is built in")]
+    BuiltInDriverType(String),
+
+    #[error("The configuration of an IO driver is invalid")]
+    InvalidDriverConfig,
+
+    #[error("The kernel image is malformed or of another version")]
+    InvalidImage,
+
+    #[error("The timing does not match the schedules")]
+    InvalidTiming,
+
+    #[error("The resolved form of a blueprint is malformed")]
+    InvalidResolved,
+
+    #[error("The static configuration of a function does not match its layout")]
+    InvalidStaticConfig,
+
+    #[error("{file} references the unknown {kind} {name:?}")]
+    UnknownReference {
+        file: String,
+        kind: &'static str,
+        name: String,
+    },
 
     #[error("TODO")]
     InvalidFunctionIdx(usize),
@@ -48,6 +101,8 @@ pub enum LwskError {
     InvalidChannelIdx(usize),
     #[error("TODO")]
     InvalidIoIdx(usize),
+    #[error("schedules[{0}] does not exist")]
+    InvalidScheduleIdx(usize),
 }
 
 #[cfg(feature = "std")]
@@ -0,0 +1,419 @@
+//! The explicit, fully resolved form of a blueprint, as printed by `lwsk resolve`
+//!
+//! All implicit rules of a blueprint are applied: includes are merged, parameters substituted,
+//! paths made absolute, defaults filled in and names replaced by the indices the kernel uses.
+//! Wasm modules are pinned by their SHA-256 hash. The resolved form is an artifact to audit,
+//! which the kernel can load directly, and which can be verified against the original blueprint.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::analysis::Timing;
+use crate::blueprint::{IoBindingBp, IoContext, TimingBp};
+use crate::image::EntryImage;
+use crate::io::registry::DriverRegistry;
+use crate::io::{IoBinding, IoDriver};
+use crate::schedule::{Schedule, ScheduleEntry};
+use crate::{Channel, Function, KernelConfig, LwskError, LwskResult};
+
+/// Version of the resolved form, incremented on incompatible changes
+pub const RESOLVED_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resolved {
+    /// Version of the resolved form, which also tells it apart from a blueprint
+    pub resolved: u32,
+
+    /// Index of the schedule to start with
+    pub initial_schedule: u32,
+
+    pub channels: Vec<ResolvedChannel>,
+    pub functions: Vec<ResolvedFunction>,
+    pub io: Vec<ResolvedIo>,
+    pub schedules: Vec<ResolvedSchedule>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing: Option<TimingBp>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolvedChannel {
+    pub index: u32,
+    pub name: String,
+
+    /// Size in byte
+    pub size: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolvedFunction {
+    pub index: u32,
+    pub name: String,
+
+    /// Absolute path of the Wasm module
+    pub wasm: String,
+
+    /// SHA-256 of the Wasm module, in hex
+    pub sha256: String,
+
+    /// Index of the consumed channel, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consumes: Option<u32>,
+
+    /// Index of the produced channel, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub produces: Option<u32>,
+
+    pub fuel_per_call: u64,
+
+    /// Static configuration, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<ResolvedConfig>,
+}
+
+/// Static configuration of a function, already serialized according to its layout
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolvedConfig {
+    pub global: String,
+
+    /// Serialized configuration, in hex
+    pub data: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolvedIo {
+    pub index: u32,
+    pub name: String,
+
+    /// The binding, with all defaults filled in
+    pub binding: IoBindingBp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolvedSchedule {
+    pub index: u32,
+    pub name: String,
+
+    /// Entries with indices and durations in nanoseconds
+    pub entries: Vec<EntryImage>,
+}
+
+/// `bytes` in hex
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Bytes of the hex string `hex`, if it is one
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16).ok())
+        .collect()
+}
+
+/// SHA-256 of `bytes`, in hex
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+/// Check that each item of `items` carries its position as index
+fn check_indices<T>(items: &[T], kind: &str, index: impl Fn(&T) -> u32) -> LwskResult<()> {
+    for (position, item) in items.iter().enumerate() {
+        if index(item) as usize != position {
+            error!("{kind}[{position}] has the index {}", index(item));
+            return Err(LwskError::InvalidResolved);
+        }
+    }
+    Ok(())
+}
+
+impl Resolved {
+    /// Load a resolved form from a file in any blueprint [Format](crate::blueprint::Format)
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let format = crate::blueprint::Format::from_path(path)?;
+        let value = format.parse(&std::fs::read(path)?)?;
+        Self::from_value(value)
+    }
+
+    /// Whether the parsed blueprint `value` is a resolved form
+    pub fn is_resolved(value: &toml::Value) -> bool {
+        value.get("resolved").is_some()
+    }
+
+    pub fn from_value(value: toml::Value) -> std::io::Result<Self> {
+        let resolved: Self = value
+            .try_into()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        if resolved.resolved != RESOLVED_VERSION {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "resolved form has version {}, expected {RESOLVED_VERSION}",
+                    resolved.resolved
+                ),
+            ));
+        }
+        Ok(resolved)
+    }
+
+    /// Derive a validated [KernelConfig], using `make_io` to create the driver for each IO binding
+    ///
+    /// Fails if a Wasm module differs from the one which was resolved, or if an index is out of
+    /// range, see [KernelConfig::validate].
+    pub fn to_kernel_config_with_io<F>(&self, mut make_io: F) -> LwskResult<KernelConfig>
+    where
+        F: FnMut(usize, &str, &IoBindingBp) -> LwskResult<Box<dyn IoDriver>>,
+    {
+        check_indices(&self.channels, "channels", |channel| channel.index)?;
+        check_indices(&self.functions, "functions", |function| function.index)?;
+        check_indices(&self.io, "io", |io| io.index)?;
+        check_indices(&self.schedules, "schedules", |schedule| schedule.index)?;
+
+        let channels = self
+            .channels
+            .iter()
+            .map(|channel| Channel {
+                name: channel.name.clone(),
+                buf: vec![0u8; channel.size as usize],
+                valid: true,
+                timestamp: None,
+                generation: 0,
+            })
+            .collect();
+
+        let mut functions = Vec::with_capacity(self.functions.len());
+        for function in &self.functions {
+            let wasm = std::fs::read(&function.wasm).map_err(|e| {
+                error!("could not read {:?}: {e}", function.wasm);
+                LwskError::WasmLoadError
+            })?;
+            let sha256 = sha256_hex(&wasm);
+            if sha256 != function.sha256 {
+                error!(
+                    "{:?} has the hash {sha256}, but {} was resolved",
+                    function.wasm, function.sha256
+                );
+                return Err(LwskError::WasmLoadError);
+            }
+
+            let mut f = Function::from_bytes(&function.name, &wasm)?;
+            f.consumes = function.consumes.map(|idx| idx as usize);
+            f.produces = function.produces.map(|idx| idx as usize);
+            f.fuel_per_call = function.fuel_per_call;
+            if let Some(config) = &function.config {
+                let Some(data) = from_hex(&config.data) else {
+                    error!("the configuration of {:?} is no hex string", function.name);
+                    return Err(LwskError::InvalidResolved);
+                };
+                f.configure(&config.global, &data)?;
+            }
+            functions.push(f);
+        }
+
+        let mut io = Vec::with_capacity(self.io.len());
+        for (idx, resolved) in self.io.iter().enumerate() {
+            let driver = make_io(idx, &resolved.name, &resolved.binding)?;
+            io.push(IoBinding::new(
+                &resolved.name,
+                driver,
+                resolved.binding.policy(),
+            ));
+        }
+
+        let mut schedules = Vec::with_capacity(self.schedules.len());
+        for schedule in &self.schedules {
+            let entries: Vec<ScheduleEntry> = schedule.entries.iter().map(Into::into).collect();
+            schedules.push(Schedule::new(schedule.name.clone(), entries)?);
+        }
+
+        let kconfig = KernelConfig {
+            channels,
+            functions,
+            schedules,
+            io,
+            current_schedule_idx: self.initial_schedule as usize,
+            health_events: Vec::new(),
+        };
+        kconfig.validate()?;
+        Ok(kconfig)
+    }
+
+    /// Derive a [KernelConfig], looking up IO drivers which are not built in from `registry`
+    pub fn to_kernel_config(&self, registry: &DriverRegistry) -> LwskResult<KernelConfig> {
+        let mut ctx = IoContext::new(registry);
+        self.to_kernel_config_with_io(|_, _, binding| binding.to_driver(&mut ctx))
+    }
+
+    /// The timing of the target, if declared
+    pub fn timing(&self) -> Option<Timing> {
+        self.timing.as_ref().map(Into::into)
+    }
+
+    /// Describe where `other` differs from this resolved form
+    pub fn differences(&self, other: &Self) -> Vec<String> {
+        let mut differences = Vec::new();
+        if self.resolved != other.resolved {
+            differences.push("version".into());
+        }
+        if self.initial_schedule != other.initial_schedule {
+            differences.push("initial schedule".into());
+        }
+        section(
+            &mut differences,
+            "channel",
+            &self.channels,
+            &other.channels,
+            |c| &c.name,
+        );
+        section(
+            &mut differences,
+            "function",
+            &self.functions,
+            &other.functions,
+            |f| &f.name,
+        );
+        section(&mut differences, "IO binding", &self.io, &other.io, |io| {
+            &io.name
+        });
+        section(
+            &mut differences,
+            "schedule",
+            &self.schedules,
+            &other.schedules,
+            |s| &s.name,
+        );
+        if self.timing != other.timing {
+            differences.push("timing".into());
+        }
+        differences
+    }
+}
+
+/// Describe differing items of a section
+fn section<T: PartialEq>(
+    differences: &mut Vec<String>,
+    kind: &str,
+    items: &[T],
+    others: &[T],
+    name: impl Fn(&T) -> &String,
+) {
+    for idx in 0..items.len().max(others.len()) {
+        match (items.get(idx), others.get(idx)) {
+            (Some(item), Some(other)) if item == other => {}
+            (Some(item), _) => differences.push(format!("{kind} {:?} [{idx}]", name(item))),
+            (None, Some(other)) => differences.push(format!("{kind} {:?} [{idx}]", name(other))),
+            (None, None) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blueprint::Blueprint;
+
+    const COPY: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global (export "INPUT") i32 (i32.const 0))
+          (global (export "OUTPUT") i32 (i32.const 8))
+          (func (export "process") (result i32)
+            (i64.store (i32.const 8) (i64.load (i32.const 0)))
+            (i32.const 0)))
+    "#;
+
+    const BLUEPRINT: &str = r#"
+        [channels.input]
+        size = 8
+        [channels.output]
+        size = 8
+
+        [functions.copy]
+        wasm = "copy.wasm"
+        consumes = "input"
+        produces = "output"
+        fuel_per_call = 1000
+
+        [io.loop]
+        type = "Loopback"
+
+        [[schedules.main]]
+        from_io = "loop"
+        to_channel = "input"
+        [[schedules.main]]
+        function = "copy"
+        [[schedules.main]]
+        from_channel = "output"
+        to_io = "loop"
+    "#;
+
+    #[test]
+    fn hex_round_trips() {
+        let bytes = [0x00, 0x7f, 0xa5, 0xff];
+        assert_eq!(hex(&bytes), "007fa5ff");
+        assert_eq!(from_hex("007fA5ff").unwrap(), bytes);
+        assert_eq!(from_hex(""), Some(Vec::new()));
+        for invalid in ["0", "0g", "+1", "é0"] {
+            assert_eq!(from_hex(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn resolved_forms_are_verified() {
+        let dir = std::env::temp_dir().join(format!("lwsk-resolved-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("copy.wasm"), wat::parse_str(COPY).unwrap()).unwrap();
+        std::fs::write(dir.join("blueprint.toml"), BLUEPRINT).unwrap();
+        let resolved = Blueprint::new(dir.join("blueprint.toml"))
+            .unwrap()
+            .resolve()
+            .unwrap();
+
+        let value = toml::Value::try_from(&resolved).unwrap();
+        assert!(Resolved::is_resolved(&value));
+        let reloaded = Resolved::from_value(value).unwrap();
+        assert!(reloaded.differences(&resolved).is_empty());
+        let registry = DriverRegistry::default();
+        assert_eq!(
+            reloaded
+                .to_kernel_config(&registry)
+                .unwrap()
+                .functions
+                .len(),
+            1
+        );
+
+        let mut edited = reloaded.clone();
+        edited.functions[0].fuel_per_call += 1;
+        edited.channels[1].size = 4;
+        assert_eq!(
+            resolved.differences(&edited),
+            ["channel \"output\" [1]", "function \"copy\" [0]"]
+        );
+
+        let mut reordered = reloaded.clone();
+        reordered.channels.swap(0, 1);
+        assert!(matches!(
+            reordered.to_kernel_config(&registry),
+            Err(LwskError::InvalidResolved)
+        ));
+
+        // the module changed after it was resolved
+        std::fs::write(
+            dir.join("copy.wasm"),
+            wat::parse_str(COPY.replace("(i32.const 0)))", "(i32.const 1)))")).unwrap(),
+        )
+        .unwrap();
+        assert!(matches!(
+            reloaded.to_kernel_config(&registry),
+            Err(LwskError::WasmLoadError)
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
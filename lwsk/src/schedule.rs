@@ -1,3 +1,6 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
 use crate::LwskError;
 
 // TODO do something similar to enum_dispatch
@@ -7,6 +10,9 @@ pub enum ScheduleEntry {
     /// Run a function
     FunctionInvocation(usize),
 
+    /// Run a function, but only if its consumed channel was updated since its last invocation
+    SporadicInvocation(usize),
+
     /// Sample IO driver, writing to a channel
     IoIn {
         from_io_idx: usize,
@@ -22,6 +28,12 @@ pub enum ScheduleEntry {
     /// Wait for a specified period of time
     Wait(core::time::Duration),
 
+    /// Wait until an IO driver has data to pull, at most for `timeout`
+    AwaitIo {
+        io_idx: usize,
+        timeout: core::time::Duration,
+    },
+
     /// Switch to other schedule
     SwitchSchedule(usize),
 }
@@ -34,7 +46,7 @@ pub struct Schedule {
     /// Sequence of actions
     pub sequence: Vec<ScheduleEntry>,
 
-    /// Index to the event sequence
+    /// Index of the action performed last, [usize::MAX] before the first one
     pub current_action: usize,
 }
 
@@ -53,10 +65,15 @@ impl Schedule {
         Ok(Self {
             name,
             sequence: order,
-            current_action: 0,
+            current_action: usize::MAX,
         })
     }
 
+    /// Start over, so that the next action is the first one
+    pub fn restart(&mut self) {
+        self.current_action = usize::MAX;
+    }
+
     pub fn next_action(&mut self) -> ScheduleEntry {
         debug_assert!(
             !self.sequence.is_empty(),